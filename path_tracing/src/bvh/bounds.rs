@@ -1,6 +1,11 @@
-use std::{f64::EPSILON, fmt::Display};
+use std::fmt::Display;
 
 use crate::{math::vector::Vector3f, domain::domain::{Axis, Ray}};
+
+// relative widening of the exit distance, rounding would otherwise put a
+// surface at the very end of a ray just outside its own bounds
+const SLAB_ROUNDING: f64 = 4.0 * f64::EPSILON;
+
 pub struct Bounds3 {
     pub p_min: Vector3f,
     pub p_max: Vector3f
@@ -41,7 +46,12 @@ impl Bounds3 {
     }
 
     pub fn diagonal(&self) -> Vector3f {
-        &self.p_max - &self.p_min
+        self.p_max - self.p_min
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let d = self.diagonal();
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
     }
 
     pub fn max_extent_axis(&self) -> Axis {
@@ -61,42 +71,36 @@ impl Bounds3 {
         }
     }
 
+    // slab test, a zero direction component gives infinite slab distances
+    // so axis aligned rays are handled without perturbing the direction
     pub fn intersect(&self, ray: &Ray) -> bool {
-        let inv_dir = Vector3f::new(
-            1.0 / (ray.direction.x + EPSILON),
-            1.0 / (ray.direction.y + EPSILON),
-            1.0 / (ray.direction.z + EPSILON)
-        );
-        let is_dir_neg = [
-            ray.direction.x >= 0.0,
-            ray.direction.y >= 0.0,
-            ray.direction.z >= 0.0
-        ];
-        let origin = &ray.origin;
-        let p_min = &self.p_min;
-        let p_max = &self.p_max;
-        let t_min = &(p_min - origin) * &inv_dir;
-        let t_max = &(p_max - origin) * &inv_dir;
-        let mut t_enter3 = Vector3f::zero();
-        let mut t_exit3 = Vector3f::zero();
-        t_enter3.x = if is_dir_neg[0] { t_min.x } else { t_max.x };
-        t_enter3.y = if is_dir_neg[1] { t_min.y } else { t_max.y };
-        t_enter3.z = if is_dir_neg[2] { t_min.z } else { t_max.z };
-
-        t_exit3.x = if !is_dir_neg[0] { t_min.x } else { t_max.x };
-        t_exit3.y = if !is_dir_neg[1] { t_min.y } else { t_max.y };
-        t_exit3.z = if !is_dir_neg[2] { t_min.z } else { t_max.z };
-
-        let t_enter = f64::max(t_enter3.x, f64::max(t_enter3.y, t_enter3.z));
-        let t_exit = f64::min(t_exit3.x, f64::min(t_exit3.y, t_exit3.z));
-        t_exit >= t_enter && t_exit >= 0.0
+        let origin = [ray.origin.x, ray.origin.y, ray.origin.z];
+        let direction = [ray.direction.x, ray.direction.y, ray.direction.z];
+        let p_min = [self.p_min.x, self.p_min.y, self.p_min.z];
+        let p_max = [self.p_max.x, self.p_max.y, self.p_max.z];
+        // the box only counts where it overlaps the range of the ray
+        let mut t_enter = ray.t_min;
+        let mut t_exit = ray.t_max;
+        for axis in 0..3 {
+            let inv_dir = 1.0 / direction[axis];
+            let mut t0 = (p_min[axis] - origin[axis]) * inv_dir;
+            let mut t1 = (p_max[axis] - origin[axis]) * inv_dir;
+            if t0 > t1 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // an origin lying on a slab plane of a parallel ray gives NaN,
+            // f64::max / f64::min ignore it and keep the ray inside the slab
+            t_enter = f64::max(t_enter, t0);
+            t_exit = f64::min(t_exit, t1);
+        }
+        t_exit * (1.0 + SLAB_ROUNDING) >= t_enter
     }
 
 }
 
 impl Clone for Bounds3 {
     fn clone(&self) -> Self {
-        Bounds3 { p_min: self.p_min.clone(), p_max: self.p_max.clone() }
+        Bounds3 { p_min: self.p_min, p_max: self.p_max }
     }
 }
 
@@ -104,4 +108,48 @@ impl Display for Bounds3 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "(min={}, max={})", self.p_min, self.p_max)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box() -> Bounds3 {
+        Bounds3::from_points(&Vector3f::new(0.0, 0.0, 0.0), &Vector3f::new(1.0, 1.0, 1.0))
+    }
+
+    fn ray(origin: Vector3f, direction: Vector3f) -> Ray {
+        Ray::new(&origin, &direction, 0.0)
+    }
+
+    #[test]
+    fn axis_aligned_rays_hit_and_miss() {
+        let bounds = unit_box();
+        let x = Vector3f::new(1.0, 0.0, 0.0);
+        assert!(bounds.intersect(&ray(Vector3f::new(-1.0, 0.5, 0.5), x)));
+        assert!(!bounds.intersect(&ray(Vector3f::new(-1.0, 1.5, 0.5), x)));
+        assert!(!bounds.intersect(&ray(Vector3f::new(2.0, 0.5, 0.5), x)));
+        assert!(bounds.intersect(&ray(Vector3f::new(2.0, 0.5, 0.5), -&x)));
+
+        // the range of the ray ends before the box
+        let mut short = ray(Vector3f::new(-1.0, 0.5, 0.5), x);
+        short.t_max = 0.5;
+        assert!(!bounds.intersect(&short));
+    }
+
+    #[test]
+    fn grazing_rays_hit_the_face_they_slide_along() {
+        let bounds = unit_box();
+        let x = Vector3f::new(1.0, 0.0, 0.0);
+        // along the top face and along an edge
+        assert!(bounds.intersect(&ray(Vector3f::new(-1.0, 1.0, 0.5), x)));
+        assert!(bounds.intersect(&ray(Vector3f::new(-1.0, 1.0, 1.0), x)));
+        assert!(!bounds.intersect(&ray(Vector3f::new(-1.0, 1.0 + 1e-9, 0.5), x)));
+
+        // the flat bounds of an axis aligned triangle, from within its plane
+        // and head on
+        let flat = Bounds3::from_points(&Vector3f::new(0.0, 0.0, 2.0), &Vector3f::new(1.0, 1.0, 2.0));
+        assert!(flat.intersect(&ray(Vector3f::new(-1.0, 0.5, 2.0), x)));
+        assert!(flat.intersect(&ray(Vector3f::new(0.5, 0.5, 0.0), Vector3f::new(0.0, 0.0, 1.0))));
+        assert!(flat.intersect(&ray(Vector3f::new(0.0, 0.0, 0.0), Vector3f::new(0.0, 0.0, 1.0))));
+    }
+}
@@ -1,11 +1,22 @@
+use std::cell::Cell;
 use std::cmp::Ordering;
 use std::sync::Arc;
 
 use crate::domain::domain::{Axis, Intersection, Ray};
-use crate::math::Math;
+use crate::math::Sampler;
 use crate::mesh::object::Object;
 use crate::bvh::bounds::Bounds3;
 
+// subtrees with fewer primitives are built on the current thread, the
+// rayon task overhead outweighs the gain below this size
+const PARALLEL_BUILD_THRESHOLD: usize = 1024;
+
+thread_local! {
+    // nodes whose bounds were tested on this thread, including the bvhs
+    // nested inside models
+    static NODE_VISITS: Cell<u64> = const { Cell::new(0) };
+}
+
 pub struct BVH {
     pub primitives: Vec<Arc<dyn Object>>,
     root: Option<Box<BVHNode>>
@@ -21,25 +32,59 @@ impl BVH {
 
     pub fn build(&mut self) {
         let tmp = self.primitives.clone();
-        self.root = Some(Self::build_recursively(tmp))
+        self.root = Some(Self::build_recursively(tmp, PARALLEL_BUILD_THRESHOLD))
     }
 
     pub fn intersect(&self, ray: &Ray) -> Intersection {
         if self.root.is_none() {
             return Intersection::new();
         }
-        return BVH::intersect_internal(self.root.as_deref(), ray);
+        BVH::intersect_internal(self.root.as_deref(), ray)
+    }
+
+    // whether any primitive is hit within the range of `ray`, the traversal
+    // stops at the first one found instead of looking for the closest
+    pub fn intersect_any(&self, ray: &Ray) -> bool {
+        match self.root.as_deref() {
+            Some(root) => BVH::intersect_any_internal(root, ray),
+            None => false,
+        }
+    }
+
+    pub fn node_visits() -> u64 {
+        NODE_VISITS.with(|visits| visits.get())
+    }
+
+    // surface area times primitive count summed over every node, the tree
+    // quality measure of the surface area heuristic. lower is better when
+    // comparing builds over the same primitives
+    pub fn total_sah_cost(&self) -> f64 {
+        match self.root.as_deref() {
+            Some(root) => Self::sah_cost(root),
+            None => 0.0,
+        }
     }
 
-    pub fn sample(&self) -> (Intersection, f64) {
+    fn sah_cost(node: &BVHNode) -> f64 {
+        let children = [node.left.as_deref(), node.right.as_deref()]
+            .into_iter()
+            .flatten()
+            .map(Self::sah_cost)
+            .sum::<f64>();
+        node.bounds.surface_area() * node.n_primitives as f64 + children
+    }
+
+    pub fn sample(&self, sampler: &mut dyn Sampler) -> (Intersection, f64) {
         let root_node = self.root.as_ref().unwrap();
-        let p = f64::sqrt(Math::sample_uniform_distribution(0.0, 1.0)) * root_node.area;
-        let (inter, mut pdf) = Self::get_sample(root_node, p);
+        // uniform in the summed area, so every point is picked with 1 / area
+        let p = sampler.next_1d() * root_node.area;
+        let (inter, mut pdf) = Self::get_sample(root_node, p, sampler);
         pdf /= root_node.area;
         (inter, pdf)
     }
 
-    fn build_recursively(mut primitives: Vec<Arc<dyn Object>>) -> Box<BVHNode> {
+    // subtrees of at least `parallel_threshold` primitives are built in parallel
+    fn build_recursively(mut primitives: Vec<Arc<dyn Object>>, parallel_threshold: usize) -> Box<BVHNode> {
         let mut root = BVHNode::new();
         let mut bounds = Bounds3::zero();
         for object in primitives.iter() {
@@ -47,6 +92,7 @@ impl BVH {
         }
         
         let n_objs = primitives.len();
+        root.n_primitives = n_objs as i32;
         if n_objs == 1 {
             let obj = &primitives[0];
             root.bounds = obj.get_bounds();
@@ -56,10 +102,10 @@ impl BVH {
             root.area = obj.get_area();
         } else if n_objs == 2 {
             let left = vec![Arc::clone(&primitives[0])];
-            root.left = Some(Self::build_recursively(left));
+            root.left = Some(Self::build_recursively(left, parallel_threshold));
 
             let right = vec![Arc::clone(&primitives[1])];
-            root.right = Some(Self::build_recursively(right));
+            root.right = Some(Self::build_recursively(right, parallel_threshold));
 
             root.bounds = Bounds3::union2(
                 &root.left.as_ref().unwrap().bounds,
@@ -117,8 +163,17 @@ impl BVH {
             let left = primitives[0..middle_index].to_vec();
             let right = primitives[middle_index..].to_vec();
             assert!(left.len() + right.len() == primitives.len());
-            root.left = Some(Self::build_recursively(left));
-            root.right = Some(Self::build_recursively(right));
+            // the split only depends on the input order, so building the
+            // halves in parallel yields the same tree as a sequential build
+            let (left, right) = if primitives.len() >= parallel_threshold {
+                rayon::join(|| Self::build_recursively(left, parallel_threshold),
+                            || Self::build_recursively(right, parallel_threshold))
+            } else {
+                (Self::build_recursively(left, parallel_threshold),
+                 Self::build_recursively(right, parallel_threshold))
+            };
+            root.left = Some(left);
+            root.right = Some(right);
             root.bounds = Bounds3::union2(&root.left.as_ref().unwrap().bounds, 
                                           &root.right.as_ref().unwrap().bounds);
             root.area = root.left.as_ref().unwrap().area +
@@ -133,6 +188,7 @@ impl BVH {
         }
 
         let node = root.unwrap();
+        NODE_VISITS.with(|visits| visits.set(visits.get() + 1));
         if !node.bounds.intersect(ray) {
             return Intersection::new();
         }
@@ -152,10 +208,28 @@ impl BVH {
         }
     }
 
-    fn get_sample(node: &BVHNode, p: f64) -> (Intersection, f64) {
+    fn intersect_any_internal(node: &BVHNode, ray: &Ray) -> bool {
+        NODE_VISITS.with(|visits| visits.set(visits.get() + 1));
+        if !node.bounds.intersect(ray) {
+            return false;
+        }
+
+        // leaf node
+        if node.left.is_none() && node.right.is_none() {
+            let obj = Arc::clone(node.object.as_ref().unwrap());
+            return obj.intersect_any(ray);
+        }
+
+        [node.left.as_deref(), node.right.as_deref()]
+            .into_iter()
+            .flatten()
+            .any(|child| BVH::intersect_any_internal(child, ray))
+    }
+
+    fn get_sample(node: &BVHNode, p: f64, sampler: &mut dyn Sampler) -> (Intersection, f64) {
         if node.left.is_none() || node.right.is_none() {
             assert!(node.object.is_some());
-            let (inter, mut pdf) = node.object.as_ref().unwrap().sample();
+            let (inter, mut pdf) = node.object.as_ref().unwrap().sample(sampler);
             pdf *= node.area;
             return (inter, pdf);
         }
@@ -163,9 +237,9 @@ impl BVH {
         let left_node = node.left.as_ref().unwrap();
         let right_node = node.right.as_ref().unwrap();
         if p < left_node.area {
-            Self::get_sample(left_node, p)
+            Self::get_sample(left_node, p, sampler)
         } else {
-            Self::get_sample(right_node, p - left_node.area)
+            Self::get_sample(right_node, p - left_node.area, sampler)
         }
     }
 }
@@ -194,4 +268,106 @@ impl BVHNode {
             n_primitives: 0 
         })
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::material::LitMaterial;
+    use crate::math::{vector::Vector3f, UniformSampler};
+    use crate::mesh::triangle::Triangle;
+
+    fn random_point(sampler: &mut UniformSampler, extent: f64) -> Vector3f {
+        let (x, y) = sampler.next_2d();
+        Vector3f::new(x, y, sampler.next_1d()) * (2.0 * extent) - Vector3f::scalar(extent)
+    }
+
+    // small triangles scattered through a cube of half size 4
+    fn scattered_triangles(n: usize) -> Vec<Arc<dyn Object>> {
+        let material = Arc::new(LitMaterial::new(&Vector3f::scalar(0.5), &Vector3f::zero()));
+        let mut sampler = UniformSampler::new(11);
+        (0..n).map(|_| {
+            let v0 = random_point(&mut sampler, 4.0);
+            let v1 = v0 + random_point(&mut sampler, 0.5);
+            let v2 = v0 + random_point(&mut sampler, 0.5);
+            Triangle::new("scattered", &v0, &v1, &v2, material.clone()) as Arc<dyn Object>
+        }).collect()
+    }
+
+    // rays from outside the cube towards points inside it
+    fn probe_rays(n: usize) -> Vec<Ray> {
+        let mut sampler = UniformSampler::new(5);
+        (0..n).map(|_| {
+            let origin = random_point(&mut sampler, 1.0) + Vector3f::new(0.0, 0.0, -8.0);
+            let target = random_point(&mut sampler, 3.0);
+            Ray::new(&origin, &(target - origin).normalize(), 0.0)
+        }).collect()
+    }
+
+    fn same_object(a: &Intersection, b: &Intersection) -> bool {
+        match (&a.obj, &b.obj) {
+            (Some(a), Some(b)) => std::ptr::addr_eq(Arc::as_ptr(a), Arc::as_ptr(b)),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn parallel_build_matches_the_sequential_one() {
+        let primitives = scattered_triangles(600);
+        let sequential = BVH { primitives: primitives.clone(), root: Some(BVH::build_recursively(primitives.clone(), usize::MAX)) };
+        let parallel = BVH { primitives: primitives.clone(), root: Some(BVH::build_recursively(primitives, 8)) };
+        assert_eq!(sequential.total_sah_cost(), parallel.total_sah_cost());
+
+        let mut hits = 0;
+        for ray in probe_rays(500) {
+            let (a, b) = (sequential.intersect(&ray), parallel.intersect(&ray));
+            assert_eq!(a.hit, b.hit);
+            assert_eq!(a.distance, b.distance);
+            assert!(same_object(&a, &b));
+            hits += a.hit as usize;
+        }
+        assert!(hits > 50);
+    }
+
+    #[test]
+    fn sah_cost_of_two_unit_boxes() {
+        let unit = Bounds3::from_points(&Vector3f::zero(), &Vector3f::scalar(1.0));
+        assert_eq!(unit.surface_area(), 6.0);
+        let flat = Bounds3::from_points(&Vector3f::zero(), &Vector3f::new(2.0, 3.0, 0.0));
+        assert_eq!(flat.surface_area(), 12.0);
+
+        // two triangles spanning unit boxes 3 apart along x
+        let material = Arc::new(LitMaterial::new(&Vector3f::scalar(0.5), &Vector3f::zero()));
+        let triangle = |x: f64| {
+            let v0 = Vector3f::new(x, 1.0, 1.0);
+            let v1 = Vector3f::new(x + 1.0, 1.0, 1.0);
+            let v2 = Vector3f::new(x, 2.0, 2.0);
+            Triangle::new("box", &v0, &v1, &v2, material.clone()) as Arc<dyn Object>
+        };
+        let mut bvh = BVH::new(vec![triangle(1.0), triangle(4.0)]);
+        assert_eq!(bvh.total_sah_cost(), 0.0);
+        bvh.build();
+        // the root box is 4 x 1 x 1 with both triangles, each leaf a unit box
+        assert!(f64::abs(bvh.total_sah_cost() - (18.0 * 2.0 + 6.0 + 6.0)) < 1e-12);
+    }
+
+    #[test]
+    fn any_hit_agrees_with_the_closest_hit() {
+        let mut bvh = BVH::new(scattered_triangles(600));
+        bvh.build();
+        let (mut hits, mut cut_off) = (0, 0);
+        for (i, ray) in probe_rays(500).into_iter().enumerate() {
+            let any = bvh.intersect_any(&ray);
+            assert_eq!(any, bvh.intersect(&ray).hit);
+            // shadow rays end before the light, a shorter range can drop the hit
+            let mut short = ray;
+            short.t_max = 6.0 + (i % 7) as f64;
+            let any_short = bvh.intersect_any(&short);
+            assert_eq!(any_short, bvh.intersect(&short).hit);
+            hits += any as usize;
+            cut_off += (any && !any_short) as usize;
+        }
+        assert!(hits > 50 && cut_off > 10);
+        assert!(!BVH::new(vec![]).intersect_any(&probe_rays(1)[0]));
+    }
+}
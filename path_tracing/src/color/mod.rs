@@ -0,0 +1,118 @@
+use crate::math::vector::Vector3f;
+
+pub mod cie;
+
+use cie::{CIE_LAMBDA_MAX, CIE_LAMBDA_MIN, CIE_LAMBDA_STEP, CIE_XYZ, CIE_Y_INTEGRAL};
+
+// range covered by the wavelength sampling distribution
+pub const VISIBLE_LAMBDA_MIN: f64 = 360.0;
+pub const VISIBLE_LAMBDA_MAX: f64 = 830.0;
+
+pub struct Color;
+impl Color {
+    // relative luminance of a linear Rec. 709 color
+    pub fn luminance(rgb: &Vector3f) -> f64 {
+        0.2126 * rgb.x + 0.7152 * rgb.y + 0.0722 * rgb.z
+    }
+
+    // linearly interpolated color matching functions, zero outside the table
+    pub fn cie_xyz(lambda: f64) -> Vector3f {
+        if !(CIE_LAMBDA_MIN..=CIE_LAMBDA_MAX).contains(&lambda) {
+            return Vector3f::zero();
+        }
+        let offset = (lambda - CIE_LAMBDA_MIN) / CIE_LAMBDA_STEP;
+        let index = usize::min(offset as usize, CIE_XYZ.len() - 2);
+        let t = offset - index as f64;
+        let a = &CIE_XYZ[index];
+        let b = &CIE_XYZ[index + 1];
+        Vector3f::new(
+            a[0] * (1.0 - t) + b[0] * t,
+            a[1] * (1.0 - t) + b[1] * t,
+            a[2] * (1.0 - t) + b[2] * t,
+        )
+    }
+
+    // XYZ of a single wavelength sample, normalized so that integrating an
+    // equal-energy spectrum of 1 gives Y = 1
+    pub fn wavelength_to_xyz(lambda: f64, radiance: f64) -> Vector3f {
+        Self::cie_xyz(lambda) * (radiance / CIE_Y_INTEGRAL)
+    }
+
+    // XYZ to linear sRGB (D65)
+    pub fn xyz_to_linear_srgb(xyz: &Vector3f) -> Vector3f {
+        Vector3f::new(
+            3.2404542 * xyz.x - 1.5371385 * xyz.y - 0.4985314 * xyz.z,
+            -0.9692660 * xyz.x + 1.8760108 * xyz.y + 0.0415560 * xyz.z,
+            0.0556434 * xyz.x - 0.2040259 * xyz.y + 1.0572252 * xyz.z,
+        )
+    }
+
+    // Bradford adaptation from the equal-energy white point E to D65
+    pub fn adapt_e_to_d65(xyz: &Vector3f) -> Vector3f {
+        Vector3f::new(
+            0.9531874 * xyz.x - 0.0265906 * xyz.y + 0.0238731 * xyz.z,
+            -0.0382467 * xyz.x + 1.0288406 * xyz.y + 0.0094060 * xyz.z,
+            0.0026068 * xyz.x - 0.0030332 * xyz.y + 1.0892565 * xyz.z,
+        )
+    }
+
+    // spectra are white balanced for E, so a flat spectrum maps to neutral
+    // linear sRGB
+    pub fn wavelength_to_rgb(lambda: f64, radiance: f64) -> Vector3f {
+        let xyz = Self::wavelength_to_xyz(lambda, radiance);
+        Self::xyz_to_linear_srgb(&Self::adapt_e_to_d65(&xyz))
+    }
+
+    // importance sampling of the visible range roughly following the sum of
+    // the color matching functions, see Radziszewski et al. 2009
+    pub fn sample_wavelength(u: f64) -> (f64, f64) {
+        let lambda = 538.0 - 138.888889 * f64::atanh(0.85691062 - 1.82750197 * u);
+        (lambda, Self::wavelength_pdf(lambda))
+    }
+
+    pub fn wavelength_pdf(lambda: f64) -> f64 {
+        if !(VISIBLE_LAMBDA_MIN..=VISIBLE_LAMBDA_MAX).contains(&lambda) {
+            return 0.0;
+        }
+        let c = f64::cosh(0.0072 * (lambda - 538.0));
+        0.0039398042 / (c * c)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_spectrum_is_neutral_white() {
+        let n = 4700;
+        let step = (VISIBLE_LAMBDA_MAX - VISIBLE_LAMBDA_MIN) / n as f64;
+        let rgb = (0..n)
+            .map(|i| VISIBLE_LAMBDA_MIN + (i as f64 + 0.5) * step)
+            .fold(Vector3f::zero(), |acc, lambda| {
+                acc + Color::wavelength_to_rgb(lambda, 1.0) * step
+            });
+        assert!(rgb.approx_eq(&Vector3f::scalar(1.0), 1e-2));
+    }
+
+    #[test]
+    fn sampled_flat_spectrum_is_neutral_white() {
+        let n = 20000;
+        let rgb = (0..n)
+            .map(|i| Color::sample_wavelength((i as f64 + 0.5) / n as f64))
+            .fold(Vector3f::zero(), |acc, (lambda, pdf)| {
+                acc + Color::wavelength_to_rgb(lambda, 1.0) / pdf
+            });
+        assert!((rgb / n as f64).approx_eq(&Vector3f::scalar(1.0), 1e-2));
+    }
+
+    #[test]
+    fn wavelength_pdf_integrates_to_one() {
+        let n = 4700;
+        let step = (VISIBLE_LAMBDA_MAX - VISIBLE_LAMBDA_MIN) / n as f64;
+        let total: f64 = (0..n)
+            .map(|i| Color::wavelength_pdf(VISIBLE_LAMBDA_MIN + (i as f64 + 0.5) * step) * step)
+            .sum();
+        assert!(f64::abs(total - 1.0) < 1e-3);
+    }
+}
@@ -24,14 +24,14 @@ impl Ray {
         Ray {
             t_min: 0.0,
             t_max: f64::MAX,
-            origin: origin.clone(),
-            direction: direction.clone(),
+            origin: *origin,
+            direction: *direction,
             t
         }
     }
 
     pub fn eval(&self, t: f64) -> Vector3f {
-        self.origin.clone() + self.direction.clone() * t
+        self.origin + self.direction * t
     }
 }
 
@@ -39,7 +39,16 @@ pub struct Intersection {
     pub hit: bool,
     pub coords: Vector3f,
     pub tcoords: Vector3f,
+    // barycentric coordinates of a triangle hit, the weights of v1 and v2
+    // with v0 weighted 1 - u - v. zero on other shapes
+    pub u: f64,
+    pub v: f64,
     pub normal: Vector3f,
+    pub tangent: Vector3f,
+    // whether the ray arrived from the outside of the surface
+    pub front_face: bool,
+    // vertex color interpolated at the hit, None for meshes without colors
+    pub color: Option<Vector3f>,
     pub emit: Vector3f,
     pub distance: f64,
     pub obj: Option<Arc<dyn Object>>,
@@ -52,7 +61,12 @@ impl Intersection {
             hit: false,
             coords: Vector3f::zero(),
             tcoords: Vector3f::zero(),
+            u: 0.0,
+            v: 0.0,
             normal: Vector3f::zero(),
+            tangent: Vector3f::zero(),
+            front_face: true,
+            color: None,
             emit: Vector3f::zero(),
             distance: f64::MAX,
             obj: None,
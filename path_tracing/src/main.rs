@@ -5,9 +5,10 @@ use math::vector::Vector3f;
 use mesh::model::Model;
 use std::sync::Arc;
 
-use crate::{renderer::{framebuffer::FrameBuffer, rendering::Renderer}, scene::Scene};
+use crate::{renderer::{framebuffer::FrameBuffer, rendering::Renderer, texture::PpmBitDepth}, scene::Scene, util::logutil::LogUtil};
 
 pub mod bvh;
+pub mod color;
 pub mod domain;
 pub mod material;
 pub mod math;
@@ -20,7 +21,6 @@ fn main() {
     let width = 500;
     let height = 500;
     let spp = 128;
-    let n_threads = 12;
     let mut scene = Scene::new(
         width,
         height,
@@ -48,38 +48,29 @@ fn main() {
         + Vector3f::new(0.737 + 0.642, 0.737 + 0.159, 0.737) * 18.4;
     let light_color = Vector3f::new(0.65, 0.65, 0.65);
     let light_mat = Arc::new(LitMaterial::new(&light_color, &light_emission_color));
-    let floor = Arc::new(Model::new(
-        "./resource/cornellbox/floor.obj",
-        white_mat.clone(),
-    ));
-    let shortbox = Arc::new(Model::new(
-        "./resource/cornellbox/shortbox.obj",
-        white_mat.clone(),
-    ));
-    let tallbox = Arc::new(Model::new(
-        "./resource/cornellbox/tallbox.obj",
-        white_mat.clone(),
-    ));
-    let left = Arc::new(Model::new(
-        "./resource/cornellbox/left.obj",
-        red_mat.clone(),
-    ));
-    let right = Arc::new(Model::new(
-        "./resource/cornellbox/right.obj",
-        green_mat.clone(),
-    ));
-    let light = Arc::new(Model::new(
-        "./resource/cornellbox/light.obj",
-        light_mat.clone(),
-    ));
 
-    scene.add(floor);
-    scene.add(shortbox);
-    scene.add(tallbox);
-    scene.add(left);
-    scene.add(right);
-    scene.add(light);
+    let models = [
+        ("floor", white_mat.clone()),
+        ("shortbox", white_mat.clone()),
+        ("tallbox", white_mat.clone()),
+        ("left", red_mat.clone()),
+        ("right", green_mat.clone()),
+        ("light", light_mat.clone()),
+    ];
+    // a broken OBJ only drops that model from the scene
+    for (name, material) in models {
+        let path = format!("./resource/cornellbox/{}.obj", name);
+        match Model::new(&path, material) {
+            Ok(model) => scene.add(Arc::new(model)),
+            Err(err) => println!("[Main] skipping model, {}", err),
+        }
+    }
     scene.build_bvh();
+    // light sampling needs an emitter, a missing light model must not get
+    // as far as the renderer
+    if scene.light_count() == 0 {
+        panic!("[Main] no emissive model loaded, the scene has no light");
+    }
 
     let final_scene = Arc::new(scene);
     let mut renderer = Renderer::new();
@@ -88,7 +79,7 @@ fn main() {
 
     println!("[Main] start rendering...");
     renderer
-        .render(final_scene, n_threads)
+        .render(final_scene, None, None, None)
         .unwrap_or_else(|err| {
             panic!("[Main] renderer error {}", err);
         });
@@ -96,7 +87,11 @@ fn main() {
 
     let fbo = renderer.fbo.as_mut().unwrap();
     let rt = fbo.get_render_target();
-    rt.dump_to_file("out/result.ppm").unwrap_or_else(|err| {
+    let metadata = format!("{} x {}, spp {}\nrendered {}", width, height, spp, LogUtil::utc_timestamp());
+    rt.dump_to_file("out/result.ppm", PpmBitDepth::Eight, Some(&metadata)).unwrap_or_else(|err| {
         panic!("[Main] dump rt to file error {}", err);
     });
+    rt.dump_to_exr("out/result.exr").unwrap_or_else(|err| {
+        panic!("[Main] dump rt to exr error {}", err);
+    });
 }
@@ -0,0 +1,126 @@
+use crate::math::{vector::Vector3f, Sampler};
+
+use super::material::Material;
+
+// wavelength in nanometers the index of refraction is evaluated at when the
+// path carries none, the sodium D line glass catalogs quote n_d for
+pub const REFERENCE_WAVELENGTH: f64 = 589.3;
+
+// smooth glass-like interface refracting and reflecting by the Fresnel
+// equations, with the index of refraction following Cauchy's equation
+// n(λ) = a + b / λ² (λ in micrometers)
+pub struct DielectricMaterial {
+    pub tint: Vector3f,
+    pub cauchy_a: f64,
+    pub cauchy_b: f64,
+}
+
+impl DielectricMaterial {
+    pub fn new(tint: &Vector3f, cauchy_a: f64, cauchy_b: f64) -> DielectricMaterial {
+        DielectricMaterial {
+            tint: *tint,
+            cauchy_a,
+            cauchy_b,
+        }
+    }
+
+    // BK7 crown glass
+    pub fn glass() -> DielectricMaterial {
+        DielectricMaterial::new(&Vector3f::new(1.0, 1.0, 1.0), 1.5046, 0.00420)
+    }
+
+    pub fn ior(&self, lambda: f64) -> f64 {
+        let micrometers = lambda * 1e-3;
+        self.cauchy_a + self.cauchy_b / (micrometers * micrometers)
+    }
+
+    // unpolarized Fresnel reflectance, `eta` is n_incident / n_transmitted
+    fn fresnel(cos_i: f64, cos_t: f64, eta: f64) -> f64 {
+        let r_s = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+        let r_p = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+        0.5 * (r_s * r_s + r_p * r_p)
+    }
+}
+
+impl Material for DielectricMaterial {
+    fn get_albedo(&self) -> Vector3f {
+        self.tint
+    }
+
+    fn has_emission(&self) -> bool {
+        false
+    }
+
+    fn get_emission(&self) -> Vector3f {
+        Vector3f::zero()
+    }
+
+    // rays leave the volume through the back faces
+    fn is_double_sided(&self) -> bool {
+        true
+    }
+
+    fn eval(&self, _ws: &Vector3f, _wo: &Vector3f, _normal: &Vector3f) -> Vector3f {
+        Vector3f::zero()
+    }
+
+    fn sample_specular(&self, wi: &Vector3f, normal: &Vector3f, front_face: bool, lambda: Option<f64>, sampler: &mut dyn Sampler) -> Option<(Vector3f, Vector3f)> {
+        let ior = self.ior(lambda.unwrap_or(REFERENCE_WAVELENGTH));
+        let eta = if front_face { 1.0 / ior } else { ior };
+        let normal = if wi.dot(normal) < 0.0 { *normal } else { -normal };
+
+        // reflection and refraction are picked by their Fresnel weight, which
+        // cancels against the probability of the choice
+        let cos_i = f64::min(-wi.dot(&normal), 1.0);
+        let direction = match wi.refract(&normal, eta) {
+            Some(refracted) => {
+                let cos_t = -refracted.dot(&normal);
+                if sampler.next_1d() < Self::fresnel(cos_i, cos_t, eta) {
+                    wi.reflect(&normal)
+                } else {
+                    refracted
+                }
+            }
+            None => wi.reflect(&normal),
+        };
+        Some((direction.normalize(), self.tint))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // always takes the refraction branch, the Fresnel reflectance of glass
+    // stays far below 1 away from grazing angles
+    struct Transmit;
+
+    impl Sampler for Transmit {
+        fn next_1d(&mut self) -> f64 {
+            0.999
+        }
+    }
+
+    #[test]
+    fn refraction_angle_depends_on_the_wavelength() {
+        let glass = DielectricMaterial::glass();
+        let normal = Vector3f::new(0.0, 0.0, 1.0);
+        let (sin_i, cos_i) = f64::sin_cos(50f64.to_radians());
+        let wi = Vector3f::new(sin_i, 0.0, -cos_i);
+        let refract = |lambda: f64| {
+            let (direction, _) = glass.sample_specular(&wi, &normal, true, Some(lambda), &mut Transmit).unwrap();
+            assert!(direction.z < 0.0);
+            // Snell's law at the index of this wavelength
+            assert!(f64::abs(direction.x * glass.ior(lambda) - sin_i) < 1e-9);
+            direction.x
+        };
+        // blue bends more towards the normal than red
+        let blue = refract(450.0);
+        let red = refract(650.0);
+        assert!(glass.ior(450.0) > glass.ior(650.0));
+        assert!(blue < red - 1e-3);
+        // no wavelength falls back to the reference one
+        let (reference, _) = glass.sample_specular(&wi, &normal, true, None, &mut Transmit).unwrap();
+        assert!(f64::abs(reference.x - refract(REFERENCE_WAVELENGTH)) < 1e-12);
+    }
+}
@@ -0,0 +1,101 @@
+use crate::math::{vector::Vector3f, UniformSampler};
+
+use super::material::Material;
+
+// white furnace check: the material lit by a uniform environment of unit
+// radiance, integrated with its own sample/pdf. an energy conserving brdf
+// with albedo `a` returns `a`, anything above it creates energy and a
+// sample/pdf mismatch shows up as a biased result
+pub fn white_furnace(material: &dyn Material, normal: &Vector3f, wo: &Vector3f, samples: u32) -> Vector3f {
+    let normal = normal.normalize();
+    let wo = wo.normalize();
+    let mut sum = Vector3f::zero();
+    let mut sampler = UniformSampler::new(0);
+    for _ in 0..samples {
+        let wi = material.sample(&-&wo, &normal, &mut sampler).normalize();
+        let pdf = material.pdf(&-&wo, &wi, &normal);
+        let cosine = wi.dot(&normal);
+        if pdf <= 0.0 || cosine <= 0.0 {
+            continue;
+        }
+        sum += material.eval(&wi, &wo, &normal) * cosine / pdf;
+    }
+    sum / samples as f64
+}
+
+// largest deviation of the furnace result from the material's albedo
+// across the channels, for a few outgoing directions from normal to grazing
+pub fn white_furnace_error(material: &dyn Material, samples: u32) -> f64 {
+    let normal = Vector3f::new(0.0, 1.0, 0.0);
+    let albedo = material.get_albedo();
+    [0.0, 0.5, 0.9]
+        .iter()
+        .map(|sin_theta: &f64| {
+            let wo = Vector3f::new(*sin_theta, f64::sqrt(1.0 - sin_theta * sin_theta), 0.0);
+            let result = white_furnace(material, &normal, &wo, samples);
+            let diff = result - albedo;
+            f64::max(f64::abs(diff.x), f64::max(f64::abs(diff.y), f64::abs(diff.z)))
+        })
+        .fold(0.0, f64::max)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use crate::material::material::{LitMaterial, Material};
+    use crate::math::{vector::Vector3f, Sampler};
+
+    use super::white_furnace_error;
+
+    // samples the cosine hemisphere but reports the uniform hemisphere pdf
+    struct MismatchedMaterial {
+        inner: LitMaterial,
+    }
+
+    impl Material for MismatchedMaterial {
+        fn get_albedo(&self) -> Vector3f {
+            self.inner.get_albedo()
+        }
+
+        fn has_emission(&self) -> bool {
+            false
+        }
+
+        fn get_emission(&self) -> Vector3f {
+            Vector3f::zero()
+        }
+
+        fn eval(&self, ws: &Vector3f, wo: &Vector3f, normal: &Vector3f) -> Vector3f {
+            self.inner.eval(ws, wo, normal)
+        }
+
+        fn sample(&self, wi: &Vector3f, normal: &Vector3f, sampler: &mut dyn Sampler) -> Vector3f {
+            self.inner.sample(wi, normal, sampler)
+        }
+
+        fn pdf(&self, _wi: &Vector3f, wo: &Vector3f, normal: &Vector3f) -> f64 {
+            if wo.dot(normal) > 0.0 {
+                0.5 / PI
+            } else {
+                0.0
+            }
+        }
+    }
+
+    #[test]
+    fn lit_material_returns_its_albedo() {
+        for albedo in [Vector3f::scalar(0.5), Vector3f::new(0.9, 0.3, 0.1)] {
+            let material = LitMaterial::new(&albedo, &Vector3f::zero());
+            assert!(white_furnace_error(&material, 4096) < 1e-2);
+        }
+    }
+
+    #[test]
+    fn mismatched_sample_and_pdf_fails() {
+        let material = MismatchedMaterial {
+            inner: LitMaterial::new(&Vector3f::scalar(0.5), &Vector3f::zero()),
+        };
+        assert!(white_furnace_error(&material, 4096) > 0.1);
+    }
+}
@@ -1,37 +1,53 @@
-use std::f64::{EPSILON, consts::PI};
+use std::f64::consts::PI;
 
-use crate::math::{vector::Vector3f, Math};
+use image::RgbImage;
+
+use crate::math::{vector::Vector3f, Math, Sampler};
 
 pub trait Material : Send + Sync {
     fn get_albedo(&self) -> Vector3f;
     fn has_emission(&self) -> bool;
     fn get_emission(&self) -> Vector3f;
+    // emitters radiate from the front face only unless two sided
+    fn is_two_sided(&self) -> bool {
+        false
+    }
+    // surfaces are hit from the front face only unless double sided
+    fn is_double_sided(&self) -> bool {
+        false
+    }
     fn eval(&self, ws: &Vector3f, wo: &Vector3f, normal: &Vector3f) -> Vector3f;
-    fn sample(&self, _wi: &Vector3f, normal: &Vector3f) -> Vector3f {
-        let x1 = Math::sample_uniform_distribution(0.0, 1.0);
-        let x2 = Math::sample_uniform_distribution(0.0, 1.0);
-        let z = f64::abs(1.0 - 2.0 * x1);
-        let r = f64::sqrt(1.0 - z * z);
-        let phi = 2.0 * PI * x2;
-        let local_dir = Vector3f::new(
-            r * f64::cos(phi),
-            r * f64::sin(phi),
-            z
-        );
-        let c = if f64::abs(normal.x) > f64::abs(normal.y) {
-            let inv_len = 1.0 / f64::sqrt(normal.x * normal.x + normal.z * normal.z);
-            Vector3f::new(normal.z * inv_len, 0.0, -normal.x * inv_len)
-        } else {
-            let inv_len = 1.0 / f64::sqrt(normal.y * normal.y + normal.z * normal.z);
-            Vector3f::new(0.0, normal.z * inv_len, -normal.y * inv_len)
-        };
-        let b = c.cross(normal);
-        b * local_dir.x + c * local_dir.y + normal * local_dir.z
+    // `eval` at a hit on a mesh with vertex colors, `color` is interpolated
+    // at the hit. only materials driven by vertex colors look at it
+    fn eval_with_color(&self, ws: &Vector3f, wo: &Vector3f, normal: &Vector3f, _color: &Vector3f) -> Vector3f {
+        self.eval(ws, wo, normal)
+    }
+    // `get_albedo` at a hit on a mesh with vertex colors
+    fn albedo_with_color(&self, _color: &Vector3f) -> Vector3f {
+        self.get_albedo()
+    }
+    // normal used for shading at `tcoords`, perturbed by normal maps
+    fn shading_normal(&self, _tcoords: &Vector3f, normal: &Vector3f, _tangent: &Vector3f) -> Vector3f {
+        *normal
+    }
+    // perfectly specular materials scatter into a single direction and are
+    // traced without light sampling, returning the scattered direction and
+    // its throughput. `front_face` tells whether `wi` arrives from outside,
+    // `lambda` is the wavelength carried by spectral paths and `sampler`
+    // makes any random choice between directions
+    fn sample_specular(&self, _wi: &Vector3f, _normal: &Vector3f, _front_face: bool, _lambda: Option<f64>, _sampler: &mut dyn Sampler) -> Option<(Vector3f, Vector3f)> {
+        None
+    }
+    // cosine weighted, matching the cosine term of diffuse reflection
+    fn sample(&self, _wi: &Vector3f, normal: &Vector3f, sampler: &mut dyn Sampler) -> Vector3f {
+        let (u1, u2) = sampler.next_2d();
+        Math::cosine_hemisphere(normal, u1, u2)
     }
 
     fn pdf(&self, _wi: &Vector3f, wo: &Vector3f, normal: &Vector3f) -> f64 {
-        if wo.dot(normal) > 0.0 {
-            0.5 / PI
+        let cosine = wo.dot(normal);
+        if cosine > 0.0 {
+            cosine / PI
         } else {
             0.0
         }
@@ -41,36 +57,120 @@ pub trait Material : Send + Sync {
 pub struct LitMaterial {
     pub emission: Vector3f,
     pub albedo: Vector3f,
+    pub two_sided: bool,
+    pub double_sided: bool,
+    // tangent space normal map, sampled with wrapping nearest lookups
+    pub normal_map: Option<RgbImage>,
 }
 
 impl LitMaterial {
     pub fn new(albedo: &Vector3f, emission: &Vector3f) -> LitMaterial {
         LitMaterial {
-            albedo: albedo.clone(),
-            emission: emission.clone()
+            albedo: *albedo,
+            emission: *emission,
+            two_sided: false,
+            double_sided: false,
+            normal_map: None
         }
     }
 }
 
 impl Material for LitMaterial {
     fn get_albedo(&self) -> Vector3f {
-        self.albedo.clone()
+        self.albedo
     }
 
     fn has_emission(&self) -> bool {
-        self.emission.length() > EPSILON
+        self.emission.length() > f64::EPSILON
     }
 
     fn get_emission(&self) -> Vector3f {
-        self.emission.clone()
+        self.emission
+    }
+
+    fn is_two_sided(&self) -> bool {
+        self.two_sided
+    }
+
+    fn is_double_sided(&self) -> bool {
+        self.double_sided
+    }
+
+    fn shading_normal(&self, tcoords: &Vector3f, normal: &Vector3f, tangent: &Vector3f) -> Vector3f {
+        let normal_map = match &self.normal_map {
+            Some(normal_map) if tangent.length() > f64::EPSILON => normal_map,
+            _ => return *normal,
+        };
+        let (width, height) = normal_map.dimensions();
+        let u = tcoords.x - f64::floor(tcoords.x);
+        let v = tcoords.y - f64::floor(tcoords.y);
+        // v grows upwards while image rows grow downwards
+        let x = u32::min((u * width as f64) as u32, width - 1);
+        let y = u32::min(((1.0 - v) * height as f64) as u32, height - 1);
+        let texel = normal_map.get_pixel(x, y);
+        let decode = |c: u8| f64::from(c) / 255.0 * 2.0 - 1.0;
+        let bitangent = normal.cross(tangent);
+        (tangent * decode(texel[0]) + bitangent * decode(texel[1]) + normal * decode(texel[2])).normalize()
     }
 
     fn eval(&self, _ws: &Vector3f, wo: &Vector3f, normal: &Vector3f) -> Vector3f {
         let cosalpha = normal.dot(wo);
         if cosalpha > 0.0 {
-            &self.albedo / PI
+            self.albedo / PI
         } else {
             Vector3f::zero()
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::UniformSampler;
+
+    fn normal_mapped(texel: [u8; 3]) -> LitMaterial {
+        let mut material = LitMaterial::new(&Vector3f::scalar(0.5), &Vector3f::zero());
+        material.normal_map = Some(RgbImage::from_pixel(4, 4, image::Rgb(texel)));
+        material
+    }
+
+    #[test]
+    fn flat_normal_map_keeps_the_geometric_normal() {
+        let normal = Vector3f::new(0.0, 0.6, 0.8);
+        let tangent = Vector3f::new(1.0, 0.0, 0.0);
+        let tcoords = Vector3f::new(0.3, 0.7, 0.0);
+        // 128 decodes to 1 / 255 rather than 0, a tilt of about 0.3 degrees
+        let flat = normal_mapped([128, 128, 255]);
+        assert!(flat.shading_normal(&tcoords, &normal, &tangent).approx_eq(&normal, 1e-2));
+        // without a tangent basis the map is ignored
+        let shading = flat.shading_normal(&tcoords, &normal, &Vector3f::zero());
+        assert!(shading == normal);
+
+        // a texel pointing along +u leans towards the tangent
+        let tilted = normal_mapped([255, 128, 128]);
+        let shading = tilted.shading_normal(&tcoords, &normal, &tangent);
+        assert!(shading.dot(&tangent) > 0.99);
+    }
+
+    #[test]
+    fn default_sample_follows_the_default_pdf() {
+        let material = LitMaterial::new(&Vector3f::scalar(0.5), &Vector3f::zero());
+        let normal = Vector3f::new(0.0, 0.6, -0.8);
+        let wi = -&normal;
+        let mut sampler = UniformSampler::new(9);
+        // counts of cos(theta) in four bands, the pdf cos / PI puts
+        // c1^2 - c0^2 of the samples into the band [c0, c1)
+        let mut bands = [0u32; 4];
+        let n = 40000;
+        for _ in 0..n {
+            let wo = material.sample(&wi, &normal, &mut sampler);
+            let cosine = wo.dot(&normal);
+            assert!(f64::abs(material.pdf(&wi, &wo, &normal) - cosine / PI) < 1e-12);
+            bands[usize::min((cosine * 4.0) as usize, 3)] += 1;
+        }
+        for (i, count) in bands.iter().enumerate() {
+            let (c0, c1) = (i as f64 / 4.0, (i + 1) as f64 / 4.0);
+            assert!(f64::abs(*count as f64 / n as f64 - (c1 * c1 - c0 * c0)) < 0.01);
+        }
+    }
+}
@@ -1,2 +1,8 @@
 #[allow(clippy::module_inception)]
-pub mod material;
\ No newline at end of file
+pub mod material;
+pub mod dielectric;
+#[cfg(test)]
+pub mod furnace;
+pub mod oren_nayar;
+pub mod preview;
+pub mod vertex_color;
\ No newline at end of file
@@ -0,0 +1,104 @@
+use std::f64::consts::PI;
+
+use crate::math::vector::Vector3f;
+
+use super::material::Material;
+
+// rough diffuse surface made of lambertian microfacets, following the
+// qualitative Oren-Nayar model. `sigma` is the standard deviation of the
+// facet slopes in radians, 0 reduces to lambertian
+pub struct OrenNayarMaterial {
+    pub albedo: Vector3f,
+    pub sigma: f64,
+    a: f64,
+    b: f64,
+}
+
+impl OrenNayarMaterial {
+    pub fn new(albedo: &Vector3f, sigma: f64) -> OrenNayarMaterial {
+        let sigma2 = sigma * sigma;
+        OrenNayarMaterial {
+            albedo: *albedo,
+            sigma,
+            a: 1.0 - 0.5 * sigma2 / (sigma2 + 0.33),
+            b: 0.45 * sigma2 / (sigma2 + 0.09),
+        }
+    }
+}
+
+impl Material for OrenNayarMaterial {
+    fn get_albedo(&self) -> Vector3f {
+        self.albedo
+    }
+
+    fn has_emission(&self) -> bool {
+        false
+    }
+
+    fn get_emission(&self) -> Vector3f {
+        Vector3f::zero()
+    }
+
+    fn eval(&self, ws: &Vector3f, wo: &Vector3f, normal: &Vector3f) -> Vector3f {
+        let cos_i = ws.dot(normal);
+        let cos_o = wo.dot(normal);
+        if cos_i <= 0.0 || cos_o <= 0.0 {
+            return Vector3f::zero();
+        }
+
+        // cosine of the azimuth difference from the tangent plane projections
+        let proj_i = ws - &(normal * cos_i);
+        let proj_o = wo - &(normal * cos_o);
+        let len = proj_i.length() * proj_o.length();
+        let cos_phi = if len > f64::EPSILON { proj_i.dot(&proj_o) / len } else { 0.0 };
+
+        // sin(alpha) * tan(beta) with alpha = max(theta_i, theta_o) and
+        // beta = min(theta_i, theta_o)
+        let sin_i = f64::sqrt(f64::max(0.0, 1.0 - cos_i * cos_i));
+        let sin_o = f64::sqrt(f64::max(0.0, 1.0 - cos_o * cos_o));
+        let (sin_alpha, tan_beta) = if cos_i < cos_o {
+            (sin_i, sin_o / cos_o)
+        } else {
+            (sin_o, sin_i / cos_i)
+        };
+        self.albedo / PI * (self.a + self.b * f64::max(0.0, cos_phi) * sin_alpha * tan_beta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::furnace::{white_furnace, white_furnace_error};
+    use crate::material::material::LitMaterial;
+
+    #[test]
+    fn smooth_surface_is_lambertian_and_rough_ones_lose_energy() {
+        let albedo = Vector3f::new(0.9, 0.6, 0.3);
+        let smooth = OrenNayarMaterial::new(&albedo, 0.0);
+        let lambert = LitMaterial::new(&albedo, &Vector3f::zero());
+        let normal = Vector3f::new(0.0, 1.0, 0.0);
+        let directions = [
+            Vector3f::new(0.0, 1.0, 0.0),
+            Vector3f::new(0.6, 0.8, 0.0),
+            Vector3f::new(-0.3, 0.4, 0.8).normalize(),
+            Vector3f::new(0.1, 0.05, -0.9).normalize(),
+        ];
+        for ws in directions.iter() {
+            for wo in directions.iter() {
+                let expected = lambert.eval(ws, wo, &normal);
+                assert!(smooth.eval(ws, wo, &normal).approx_eq(&expected, 1e-12));
+            }
+        }
+        assert!(white_furnace_error(&smooth, 20000) < 0.02);
+
+        // the facets shadow and mask each other, so a rough white surface
+        // reflects at most what it receives
+        let rough = OrenNayarMaterial::new(&Vector3f::scalar(1.0), 0.5);
+        for sin_theta in [0.0, 0.5, 0.9] {
+            let wo = Vector3f::new(sin_theta, f64::sqrt(1.0 - sin_theta * sin_theta), 0.0);
+            let result = white_furnace(&rough, &normal, &wo, 20000);
+            assert!(result.x < 1.0 + 0.02);
+            assert!(result.x > 0.5);
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use crate::math::vector::Vector3f;
+
+use super::material::Material;
+
+// fixed studio lighting in view space (x right, y up, z towards the viewer),
+// independent of the lights in the scene
+const KEY_LIGHT: (f64, f64, f64) = (-0.45, 0.6, 0.66);
+const KEY_INTENSITY: f64 = 0.85;
+const FILL_LIGHT: (f64, f64, f64) = (0.7, 0.1, 0.7);
+const FILL_INTENSITY: f64 = 0.3;
+const AMBIENT: f64 = 0.12;
+const RIM_INTENSITY: f64 = 0.25;
+const SPECULAR_POWER: f64 = 48.0;
+const SPECULAR_INTENSITY: f64 = 0.2;
+
+// matcap-like shading used to inspect a material quickly, only depends on
+// the surface normal and the view direction (pointing from the surface
+// towards the eye)
+pub fn preview_shade(material: &dyn Material, normal: &Vector3f, view: &Vector3f) -> Vector3f {
+    if material.has_emission() {
+        let emission = material.get_emission();
+        let peak = f64::max(emission.x, f64::max(emission.y, emission.z));
+        return emission / peak;
+    }
+
+    let n = to_view_space(normal, view);
+    let key = Vector3f::new(KEY_LIGHT.0, KEY_LIGHT.1, KEY_LIGHT.2).normalize();
+    let fill = Vector3f::new(FILL_LIGHT.0, FILL_LIGHT.1, FILL_LIGHT.2).normalize();
+    let diffuse = AMBIENT
+        + KEY_INTENSITY * f64::max(n.dot(&key), 0.0)
+        + FILL_INTENSITY * f64::max(n.dot(&fill), 0.0);
+
+    // blinn-phong highlight of the key light and a fresnel-ish rim
+    let half = (key + Vector3f::new(0.0, 0.0, 1.0)).normalize();
+    let specular = SPECULAR_INTENSITY * f64::powf(f64::max(n.dot(&half), 0.0), SPECULAR_POWER);
+    let rim = RIM_INTENSITY * f64::powf(1.0 - f64::clamp(n.z, 0.0, 1.0), 3.0);
+
+    material.get_albedo() * diffuse + Vector3f::new(1.0, 1.0, 1.0) * (specular + rim)
+}
+
+// expresses the normal in a basis where the view direction is +z
+fn to_view_space(normal: &Vector3f, view: &Vector3f) -> Vector3f {
+    let forward = view.normalize();
+    let world_up = if f64::abs(forward.y) > 0.999 {
+        Vector3f::new(0.0, 0.0, 1.0)
+    } else {
+        Vector3f::new(0.0, 1.0, 0.0)
+    };
+    let right = world_up.cross(&forward).normalize();
+    let up = forward.cross(&right);
+    Vector3f::new(normal.dot(&right), normal.dot(&up), normal.dot(&forward))
+}
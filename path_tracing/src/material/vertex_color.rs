@@ -0,0 +1,80 @@
+use std::f64::consts::PI;
+
+use crate::math::vector::Vector3f;
+
+use super::material::Material;
+
+// lambertian surface taking its albedo from the interpolated vertex colors
+// of the mesh, `albedo` is used where the mesh has none
+pub struct VertexColorMaterial {
+    pub albedo: Vector3f,
+}
+
+impl VertexColorMaterial {
+    pub fn new(albedo: &Vector3f) -> VertexColorMaterial {
+        VertexColorMaterial { albedo: *albedo }
+    }
+
+    fn lambert(albedo: &Vector3f, wo: &Vector3f, normal: &Vector3f) -> Vector3f {
+        if normal.dot(wo) > 0.0 {
+            albedo / PI
+        } else {
+            Vector3f::zero()
+        }
+    }
+}
+
+impl Material for VertexColorMaterial {
+    fn get_albedo(&self) -> Vector3f {
+        self.albedo
+    }
+
+    fn has_emission(&self) -> bool {
+        false
+    }
+
+    fn get_emission(&self) -> Vector3f {
+        Vector3f::zero()
+    }
+
+    fn eval(&self, _ws: &Vector3f, wo: &Vector3f, normal: &Vector3f) -> Vector3f {
+        Self::lambert(&self.albedo, wo, normal)
+    }
+
+    fn eval_with_color(&self, _ws: &Vector3f, wo: &Vector3f, normal: &Vector3f, color: &Vector3f) -> Vector3f {
+        Self::lambert(color, wo, normal)
+    }
+
+    fn albedo_with_color(&self, color: &Vector3f) -> Vector3f {
+        *color
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::domain::domain::Ray;
+    use crate::mesh::object::Object;
+    use crate::mesh::triangle::Triangle;
+
+    #[test]
+    fn centroid_color_is_the_vertex_average() {
+        let (v0, v1, v2) = (Vector3f::new(0.0, 0.0, 0.0), Vector3f::new(3.0, 0.0, 0.0), Vector3f::new(0.0, 3.0, 0.0));
+        let colors = [Vector3f::new(1.0, 0.0, 0.0), Vector3f::new(0.0, 1.0, 0.0), Vector3f::new(0.0, 0.0, 1.0)];
+        let material = Arc::new(VertexColorMaterial::new(&Vector3f::scalar(0.5)));
+        let uv = Vector3f::zero();
+        let triangle = Triangle::new_with_colors("rgb", &v0, &v1, &v2, &uv, &uv, &uv, Some(colors), material.clone());
+
+        let centroid = (v0 + v1 + v2) / 3.0;
+        let ray = Ray::new(&(centroid + Vector3f::new(0.0, 0.0, 1.0)), &Vector3f::new(0.0, 0.0, -1.0), 0.0);
+        let color = triangle.intersect(&ray).color.unwrap();
+        assert!(color.approx_eq(&Vector3f::scalar(1.0 / 3.0), 1e-12));
+
+        // the interpolated color is the albedo, in place of the material's own
+        let normal = Vector3f::new(0.0, 0.0, 1.0);
+        let f = material.eval_with_color(&normal, &normal, &normal, &color);
+        assert!(f.approx_eq(&(Vector3f::scalar(1.0 / 3.0) / PI), 1e-12));
+    }
+}
@@ -0,0 +1,211 @@
+use std::sync::Arc;
+
+use crate::{
+    bvh::bounds::Bounds3, domain::domain::{Intersection, Ray}, math::{vector::Vector3f, Math, Sampler}, mesh::{model::Model, object::Object},
+};
+
+// a placed copy of a shared model. the scene bvh over instances is the top
+// level and the bvh of the model the bottom level, rays are moved into the
+// space of the model instead of copying its triangles
+pub struct Instance {
+    pub model: Arc<Model>,
+    translation: Vector3f,
+    // sin and cos of the rotations around x, y and z
+    rotation: [(f64, f64); 3],
+    scale: Vector3f,
+    bounds: Bounds3,
+    area: f64,
+}
+
+impl Instance {
+    // places the model like `Model::new_transformed`, scaling, then rotating
+    // around x, y and z (degrees), then translating
+    pub fn new(model: Arc<Model>,
+               translation: &Vector3f,
+               rotation: &Vector3f,
+               scale: &Vector3f) -> Result<Instance, &'static str> {
+        if scale.x * scale.y * scale.z == 0.0 {
+            return Err("instance scale must not be zero");
+        }
+        let mut instance = Instance {
+            model,
+            translation: *translation,
+            rotation: [
+                f64::sin_cos(Math::radian(rotation.x)),
+                f64::sin_cos(Math::radian(rotation.y)),
+                f64::sin_cos(Math::radian(rotation.z)),
+            ],
+            scale: *scale,
+            bounds: Bounds3::zero(),
+            area: 0.0,
+        };
+
+        let (p_min, p_max) = (instance.model.bounds.p_min, instance.model.bounds.p_max);
+        let corner = |i: usize| Vector3f::new(
+            if i & 1 == 0 { p_min.x } else { p_max.x },
+            if i & 2 == 0 { p_min.y } else { p_max.y },
+            if i & 4 == 0 { p_min.z } else { p_max.z },
+        );
+        let first = instance.point_to_world(&corner(0));
+        let mut bounds = Bounds3::from_points(&first, &first);
+        for i in 1..8 {
+            bounds.union_point(&instance.point_to_world(&corner(i)));
+        }
+        instance.bounds = bounds;
+
+        // a non-uniform scale stretches every triangle by its own factor
+        instance.area = instance.model.triangles.iter()
+            .map(|triangle| {
+                let e1 = instance.vector_to_world(&triangle.e1);
+                let e2 = instance.vector_to_world(&triangle.e2);
+                e1.cross(&e2).length() * 0.5
+            })
+            .sum();
+        Ok(instance)
+    }
+
+    fn rotate(&self, v: &Vector3f) -> Vector3f {
+        let [(sin_x, cos_x), (sin_y, cos_y), (sin_z, cos_z)] = self.rotation;
+        let v = Vector3f::new(v.x, v.y * cos_x - v.z * sin_x, v.y * sin_x + v.z * cos_x);
+        let v = Vector3f::new(v.x * cos_y + v.z * sin_y, v.y, -v.x * sin_y + v.z * cos_y);
+        Vector3f::new(v.x * cos_z - v.y * sin_z, v.x * sin_z + v.y * cos_z, v.z)
+    }
+
+    fn unrotate(&self, v: &Vector3f) -> Vector3f {
+        let [(sin_x, cos_x), (sin_y, cos_y), (sin_z, cos_z)] = self.rotation;
+        let v = Vector3f::new(v.x * cos_z + v.y * sin_z, -v.x * sin_z + v.y * cos_z, v.z);
+        let v = Vector3f::new(v.x * cos_y - v.z * sin_y, v.y, v.x * sin_y + v.z * cos_y);
+        Vector3f::new(v.x, v.y * cos_x + v.z * sin_x, -v.y * sin_x + v.z * cos_x)
+    }
+
+    fn point_to_world(&self, p: &Vector3f) -> Vector3f {
+        self.vector_to_world(p) + self.translation
+    }
+
+    fn vector_to_world(&self, v: &Vector3f) -> Vector3f {
+        self.rotate(&(v * &self.scale))
+    }
+
+    fn vector_to_local(&self, v: &Vector3f) -> Vector3f {
+        let v = self.unrotate(v);
+        Vector3f::new(v.x / self.scale.x, v.y / self.scale.y, v.z / self.scale.z)
+    }
+
+    // normals follow the inverse transpose, the inverse scale then rotation
+    fn normal_to_world(&self, n: &Vector3f) -> Vector3f {
+        let n = Vector3f::new(n.x / self.scale.x, n.y / self.scale.y, n.z / self.scale.z);
+        self.rotate(&n).normalize()
+    }
+
+    // the direction is not normalized after the transform, so distances
+    // along the local ray are the same as along `ray`
+    fn ray_to_local(&self, ray: &Ray) -> Ray {
+        let mut local = Ray::new(&self.vector_to_local(&(ray.origin - self.translation)),
+                                 &self.vector_to_local(&ray.direction),
+                                 ray.t);
+        local.t_min = ray.t_min;
+        local.t_max = ray.t_max;
+        local
+    }
+
+    fn inter_to_world(&self, inter: &mut Intersection) {
+        inter.coords = self.point_to_world(&inter.coords);
+        inter.normal = self.normal_to_world(&inter.normal);
+        if inter.tangent.length() > 0.0 {
+            inter.tangent = self.vector_to_world(&inter.tangent).normalize();
+        }
+    }
+}
+
+impl Object for Instance {
+    fn get_name(&self) -> String {
+        format!("Instance({})", self.model.get_name())
+    }
+
+    fn get_bounds(&self) -> Bounds3 {
+        self.bounds.clone()
+    }
+
+    fn get_area(&self) -> f64 {
+        self.area
+    }
+
+    fn intersect(self: Arc<Self>, ray: &Ray) -> Intersection {
+        let mut inter = Arc::clone(&self.model).intersect(&self.ray_to_local(ray));
+        if inter.hit {
+            self.inter_to_world(&mut inter);
+        }
+        inter
+    }
+
+    fn intersect_any(self: Arc<Self>, ray: &Ray) -> bool {
+        Arc::clone(&self.model).intersect_any(&self.ray_to_local(ray))
+    }
+
+    fn sample(&self, sampler: &mut dyn Sampler) -> (Intersection, f64) {
+        let (mut inter, pdf) = self.model.sample(sampler);
+        // the area around the point grows by the scale across its normal
+        let n = &inter.normal;
+        let n_scaled = Vector3f::new(n.x / self.scale.x, n.y / self.scale.y, n.z / self.scale.z);
+        let stretch = f64::abs(self.scale.x * self.scale.y * self.scale.z) * n_scaled.length();
+        self.inter_to_world(&mut inter);
+        (inter, pdf / stretch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::material::LitMaterial;
+
+    // a unit right triangle at z = 0 facing -z, towards rays along +z
+    fn triangle_model() -> Arc<Model> {
+        let vertices = [Vector3f::new(0.0, 0.0, 0.0), Vector3f::new(0.0, 1.0, 0.0), Vector3f::new(1.0, 0.0, 0.0)];
+        let material = Arc::new(LitMaterial::new(&Vector3f::scalar(0.5), &Vector3f::zero()));
+        Arc::new(Model::from_triangles("triangle", &vertices, &[], &[0, 1, 2], material))
+    }
+
+    fn ray_along_z(x: f64, y: f64) -> Ray {
+        Ray::new(&Vector3f::new(x, y, -5.0), &Vector3f::new(0.0, 0.0, 1.0), 0.0)
+    }
+
+    #[test]
+    fn instances_share_the_model_and_honour_their_transform() {
+        let model = triangle_model();
+        let left = Arc::new(Instance::new(model.clone(), &Vector3f::new(-3.0, 0.0, 1.0), &Vector3f::zero(), &Vector3f::scalar(1.0)).unwrap());
+        let right = Arc::new(Instance::new(model.clone(), &Vector3f::new(3.0, 0.0, 2.0), &Vector3f::zero(), &Vector3f::new(2.0, 3.0, 1.0)).unwrap());
+        assert!(Arc::ptr_eq(&left.model, &right.model));
+        assert!(Arc::ptr_eq(&left.model, &model));
+
+        let inter = left.clone().intersect(&ray_along_z(-2.8, 0.2));
+        assert!(inter.hit);
+        assert!(f64::abs(inter.distance - 6.0) < 1e-9);
+        assert!(inter.coords.approx_eq(&Vector3f::new(-2.8, 0.2, 1.0), 1e-9));
+        assert!(!left.clone().intersect(&ray_along_z(3.2, 0.2)).hit);
+
+        // the scaled copy covers [3, 5] x [0, 3] and is hit beyond the
+        // extent of the shared model
+        let inter = right.clone().intersect(&ray_along_z(4.0, 1.2));
+        assert!(inter.hit);
+        assert!(f64::abs(inter.distance - 7.0) < 1e-9);
+        assert!(inter.normal.approx_eq(&Vector3f::new(0.0, 0.0, -1.0), 1e-9));
+        assert!(!right.clone().intersect(&ray_along_z(4.5, 2.0)).hit);
+
+        // stretched along the ray, the world space t must not be the local one
+        let deep = Arc::new(Instance::new(model.clone(), &Vector3f::new(0.0, 0.0, 1.0), &Vector3f::zero(), &Vector3f::new(1.0, 1.0, 2.0)).unwrap());
+        let inter = deep.clone().intersect(&ray_along_z(0.2, 0.3));
+        assert!(inter.hit);
+        assert!(f64::abs(inter.distance - 6.0) < 1e-9);
+        assert!(inter.coords.approx_eq(&Vector3f::new(0.2, 0.3, 1.0), 1e-9));
+        for (t_max, hit) in [(5.9, false), (6.1, true)] {
+            let mut ray = ray_along_z(0.2, 0.3);
+            ray.t_max = t_max;
+            assert_eq!(deep.clone().intersect(&ray).hit, hit);
+            assert_eq!(deep.clone().intersect_any(&ray), hit);
+        }
+
+        assert!(f64::abs(left.get_area() - 0.5) < 1e-12);
+        assert!(f64::abs(right.get_area() - 3.0) < 1e-12);
+        assert!(f64::abs(model.area - 0.5) < 1e-12);
+    }
+}
@@ -1,3 +1,5 @@
 pub mod triangle;
 pub mod object;
-pub mod model;
\ No newline at end of file
+pub mod model;
+pub mod sphere;
+pub mod instance;
\ No newline at end of file
@@ -1,14 +1,21 @@
+use std::fmt::{self, Display};
 use std::sync::Arc;
 use tobj;
 
 use crate::{
-    bvh::{bvh::BVH, bounds::Bounds3}, material::material::Material, math::vector::Vector3f, mesh::triangle::Triangle, domain::domain::Intersection,
+    bvh::{bvh::BVH, bounds::Bounds3}, material::material::Material, math::{vector::Vector3f, Math, Sampler}, mesh::triangle::Triangle, domain::domain::Intersection,
 };
 
 use super::object::Object;
 
+// faces with less area have no usable normal, they are left out of the
+// model so light sampling never lands on them
+const DEGENERATE_AREA: f64 = 1e-10;
+
 pub struct Model {
     pub triangles: Vec<Arc<Triangle>>,
+    // faces of the source mesh dropped for being degenerate
+    pub degenerate_triangles: usize,
     pub material: Arc<dyn Material>,
     pub bvh: Option<BVH>,
     pub area: f64,
@@ -16,56 +23,162 @@ pub struct Model {
     pub path: String
 }
 
+// why an OBJ file could not be turned into a model, every variant carries
+// the path so callers can report which file failed
+#[derive(Debug)]
+pub enum ModelError {
+    // the file is missing or unreadable
+    Io(String),
+    // the file was read but is not valid OBJ
+    Parse(String, tobj::LoadError),
+    // valid OBJ the renderer cannot use, e.g. more than one mesh
+    UnsupportedFormat(String, String),
+}
+
+impl Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelError::Io(path) => write!(f, "cannot read OBJ file {}", path),
+            ModelError::Parse(path, err) => write!(f, "cannot parse OBJ file {}: {}", path, err),
+            ModelError::UnsupportedFormat(path, reason) => write!(f, "unsupported OBJ file {}: {}", path, reason),
+        }
+    }
+}
+
+impl std::error::Error for ModelError {}
+
 impl Model {
-    pub fn new(path: &str, material: Arc<dyn Material>) -> Model {
+    pub fn new(path: &str, material: Arc<dyn Material>) -> Result<Model, ModelError> {
+        Model::new_transformed(path, material, &Vector3f::zero(), &Vector3f::zero(), &Vector3f::new(1.0, 1.0, 1.0))
+    }
+
+    // places the mesh by scaling, then rotating around x, y and z (degrees),
+    // then translating every vertex
+    pub fn new_transformed(path: &str,
+                           material: Arc<dyn Material>,
+                           translation: &Vector3f,
+                           rotation: &Vector3f,
+                           scale: &Vector3f) -> Result<Model, ModelError> {
         let mut model = Model {
             triangles: vec![],
+            degenerate_triangles: 0,
             material: Arc::clone(&material),
             bvh: None,
             area: 0.0,
             bounds: Bounds3::zero(),
             path: String::from(path)
         };
-        model.load(path);
+        let (sin_x, cos_x) = f64::sin_cos(Math::radian(rotation.x));
+        let (sin_y, cos_y) = f64::sin_cos(Math::radian(rotation.y));
+        let (sin_z, cos_z) = f64::sin_cos(Math::radian(rotation.z));
+        model.load(path, &|v: Vector3f| {
+            let v = Vector3f::new(v.x * scale.x, v.y * scale.y, v.z * scale.z);
+            let v = Vector3f::new(v.x, v.y * cos_x - v.z * sin_x, v.y * sin_x + v.z * cos_x);
+            let v = Vector3f::new(v.x * cos_y + v.z * sin_y, v.y, -v.x * sin_y + v.z * cos_y);
+            let v = Vector3f::new(v.x * cos_z - v.y * sin_z, v.x * sin_z + v.y * cos_z, v.z);
+            &v + translation
+        }, scale.x * scale.y * scale.z < 0.0)?;
+        Ok(model)
+    }
+
+    // builds a model from indexed triangles that are already in world space,
+    // `uvs` is either empty or holds one texture coordinate per vertex
+    pub fn from_triangles(name: &str,
+                          vertices: &[Vector3f],
+                          uvs: &[Vector3f],
+                          indices: &[u32],
+                          material: Arc<dyn Material>) -> Model {
+        let mut model = Model {
+            triangles: vec![],
+            degenerate_triangles: 0,
+            material,
+            bvh: None,
+            area: 0.0,
+            bounds: Bounds3::zero(),
+            path: String::from(name)
+        };
+        model.build(vertices, uvs, &[], indices, false);
         model
     }
 
-    fn load(&mut self, path: &str) {
-        let obj = tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS);
-        let (models, _) = obj.unwrap_or_else(|_| panic!("Failed to load OBJ file {}", path));
+    // `flip_winding` is set for mirroring transforms so the face normals
+    // keep pointing outwards
+    fn load(&mut self, path: &str, transform: &dyn Fn(Vector3f) -> Vector3f, flip_winding: bool) -> Result<(), ModelError> {
+        let (models, _) = tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS).map_err(|err| match err {
+            tobj::LoadError::OpenFileFailed | tobj::LoadError::ReadError => ModelError::Io(String::from(path)),
+            err => ModelError::Parse(String::from(path), err),
+        })?;
         if models.len() != 1 {
-            panic!("Invalid OBJ format: only single mesh models are supported");
+            return Err(ModelError::UnsupportedFormat(
+                String::from(path),
+                format!("{} meshes, only single mesh models are supported", models.len()),
+            ));
         }
-        let mut p_min = Vector3f::new(f64::MAX, f64::MAX, f64::MAX);
-        let mut p_max = Vector3f::new(f64::MIN, f64::MIN, f64::MIN);
         let mesh = &models[0].mesh;
-        let mut vertices: Vec<Vector3f> = vec![];
         let positions = &mesh.positions;
-        for i in (0..positions.len()).step_by(3) {
-            let vertex = Vector3f::new(f64::from(positions[i]), 
-                                                 f64::from(positions[i + 1]), 
-                                                 f64::from(positions[i + 2]));
+        let vertices: Vec<Vector3f> = (0..positions.len()).step_by(3)
+            .map(|i| transform(Vector3f::new(f64::from(positions[i]), 
+                                             f64::from(positions[i + 1]), 
+                                             f64::from(positions[i + 2]))))
+            .collect();
+        // texture coordinates and the colors of `v x y z r g b` lines share
+        // the position indices with GPU_LOAD_OPTIONS
+        let uvs: Vec<Vector3f> = mesh.texcoords.chunks_exact(2)
+            .map(|uv| Vector3f::new(f64::from(uv[0]), f64::from(uv[1]), 0.0))
+            .collect();
+        let colors: Vec<Vector3f> = mesh.vertex_color.chunks_exact(3)
+            .map(|c| Vector3f::new(f64::from(c[0]), f64::from(c[1]), f64::from(c[2])))
+            .collect();
+        self.build(&vertices, &uvs, &colors, &mesh.indices, flip_winding);
+        Ok(())
+    }
 
+    // `uvs` and `colors` are either empty or hold one entry per vertex
+    fn build(&mut self, vertices: &[Vector3f], uvs: &[Vector3f], colors: &[Vector3f], indicies: &[u32], flip_winding: bool) {
+        let mut p_min = Vector3f::new(f64::MAX, f64::MAX, f64::MAX);
+        let mut p_max = Vector3f::new(f64::MIN, f64::MIN, f64::MIN);
+        for vertex in vertices.iter() {
             p_min.x = f64::min(p_min.x, vertex.x);
             p_min.y = f64::min(p_min.y, vertex.y);
             p_min.z = f64::min(p_min.z, vertex.z);
             p_max.x = f64::max(p_max.x, vertex.x);
             p_max.y = f64::max(p_max.y, vertex.y);
             p_max.z = f64::max(p_max.z, vertex.z);
-
-            vertices.push(vertex);
         }
 
-        let indicies = &mesh.indices;
+        let uv = |index: u32| -> Vector3f {
+            match uvs.get(index as usize) {
+                Some(uv) => *uv,
+                None => Vector3f::zero()
+            }
+        };
+
         for i in (0..indicies.len()).step_by(3) {
-            let v0 = vertices[indicies[i] as usize].clone();
-            let v1 = vertices[indicies[i + 1] as usize].clone();
-            let v2 = vertices[indicies[i + 2] as usize].clone();
+            let (i0, mut i1, mut i2) = (indicies[i], indicies[i + 1], indicies[i + 2]);
+            if flip_winding {
+                std::mem::swap(&mut i1, &mut i2);
+            }
+            let (v0, v1, v2) = (&vertices[i0 as usize], &vertices[i1 as usize], &vertices[i2 as usize]);
+            if (v1 - v0).cross(&(v2 - v0)).length() * 0.5 < DEGENERATE_AREA {
+                self.degenerate_triangles += 1;
+                continue;
+            }
+            let vertex_colors = match (colors.get(i0 as usize), colors.get(i1 as usize), colors.get(i2 as usize)) {
+                (Some(c0), Some(c1), Some(c2)) => Some([*c0, *c1, *c2]),
+                _ => None
+            };
             self.triangles.push(
-                Triangle::new(&format!("Triangle({})", &self.get_name()), &v0, &v1, &v2, Arc::clone(&self.material))
+                Triangle::new_with_colors(&format!("Triangle({})", &self.get_name()),
+                                          v0, v1, v2,
+                                          &uv(i0), &uv(i1), &uv(i2),
+                                          vertex_colors,
+                                          Arc::clone(&self.material))
             );
         }
 
+        if self.degenerate_triangles > 0 {
+            println!("[Model] skipped {} degenerate triangles in {}", self.degenerate_triangles, self.path);
+        }
         self.bounds = Bounds3 { p_min, p_max };
 
         let mut area: f64 = 0.0;
@@ -77,7 +190,11 @@ impl Model {
             })
             .collect();
         self.area = area;
-        
+        // a mesh made only of degenerate faces has nothing to intersect
+        if self.triangles.is_empty() {
+            return;
+        }
+
         let mut bvh = BVH::new(primitives);
         bvh.build();
         self.bvh = Some(bvh);
@@ -104,13 +221,106 @@ impl Object for Model {
         Intersection::new()
     }
 
-    fn sample(&self) -> (Intersection, f64) {
+    fn intersect_any(self: Arc<Self>, ray: &crate::domain::domain::Ray) -> bool {
+        match self.bvh.as_ref() {
+            Some(bvh) => bvh.intersect_any(ray),
+            None => false,
+        }
+    }
+
+    // the scene keeps models without faces out of its light table, one
+    // sampled anyway returns a miss with a zero pdf
+    fn sample(&self, sampler: &mut dyn Sampler) -> (Intersection, f64) {
         if self.bvh.is_none() {
             return (Intersection::new(), 0.0)
         }
 
-        let (mut inter, area) = self.bvh.as_ref().unwrap().sample();
+        let (mut inter, area) = self.bvh.as_ref().unwrap().sample(sampler);
         inter.emit = self.material.get_emission();
+        inter.material = Some(Arc::clone(&self.material));
         (inter, area)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::material::LitMaterial;
+    use crate::math::UniformSampler;
+
+    fn white() -> Arc<dyn Material> {
+        Arc::new(LitMaterial::new(&Vector3f::scalar(0.5), &Vector3f::zero()))
+    }
+
+    // a unit right triangle in the xy plane and a collinear face
+    fn mesh_with_degenerate_face(material: Arc<dyn Material>) -> Model {
+        let vertices = [
+            Vector3f::new(0.0, 0.0, 0.0),
+            Vector3f::new(1.0, 0.0, 0.0),
+            Vector3f::new(0.0, 1.0, 0.0),
+            Vector3f::new(2.0, 0.0, 0.0),
+        ];
+        Model::from_triangles("degenerate", &vertices, &[], &[0, 1, 2, 0, 1, 3], material)
+    }
+
+    #[test]
+    fn degenerate_faces_are_skipped() {
+        let model = mesh_with_degenerate_face(white());
+        assert_eq!(model.triangles.len(), 1);
+        assert_eq!(model.degenerate_triangles, 1);
+        assert_eq!(model.area, 0.5);
+    }
+
+    #[test]
+    fn degenerate_faces_are_never_sampled() {
+        let model = mesh_with_degenerate_face(white());
+        let mut sampler = UniformSampler::new(7);
+        for _ in 0..256 {
+            let (inter, pdf) = model.sample(&mut sampler);
+            assert!(f64::abs(pdf - 2.0) < 1e-9);
+            assert!(f64::abs(inter.normal.length() - 1.0) < 1e-9);
+            assert!(inter.coords.x + inter.coords.y <= 1.0 + 1e-9);
+        }
+    }
+
+    #[test]
+    fn missing_file_is_an_io_error() {
+        let path = "./resource/cornellbox/missing.obj";
+        match Model::new(path, white()) {
+            Err(ModelError::Io(err_path)) => assert_eq!(err_path, path),
+            _ => panic!("expected ModelError::Io"),
+        }
+    }
+
+    #[test]
+    fn translation_moves_the_bounds() {
+        let path = std::env::temp_dir().join("path_tracing_translation_moves_the_bounds.obj");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        let offset = Vector3f::new(2.0, -1.0, 3.0);
+        let model = Model::new_transformed(path, white(), &offset, &Vector3f::zero(), &Vector3f::scalar(1.0));
+        std::fs::remove_file(path).unwrap();
+        let model = model.unwrap();
+        assert!(model.bounds.p_min.approx_eq(&offset, 1e-12));
+        assert!(model.bounds.p_max.approx_eq(&(offset + Vector3f::new(1.0, 1.0, 0.0)), 1e-12));
+        assert!(model.triangles[0].v0.approx_eq(&offset, 1e-12));
+        assert_eq!(model.area, 0.5);
+    }
+
+    #[test]
+    fn mirroring_keeps_the_normals_facing_outwards() {
+        // +1 for every face whose normal leaves the center of the box
+        let facing = |model: &Model| -> Vec<f64> {
+            let center = (model.bounds.p_min + model.bounds.p_max) * 0.5;
+            model.triangles.iter()
+                .map(|t| f64::signum(t.normal.dot(&((t.v0 + t.v1 + t.v2) / 3.0 - center))))
+                .collect()
+        };
+        let path = "./resource/cornellbox/shortbox.obj";
+        let model = Model::new(path, white()).unwrap();
+        let mirrored = Model::new_transformed(path, white(), &Vector3f::zero(), &Vector3f::zero(), &Vector3f::new(-1.0, 1.0, 1.0)).unwrap();
+        assert!(facing(&model).iter().all(|side| *side == 1.0));
+        assert!(facing(&mirrored).iter().all(|side| *side == 1.0));
+        assert!(f64::abs(mirrored.area - model.area) < 1e-6);
+    }
+}
@@ -1,6 +1,6 @@
 use std::sync::Arc;
 
-use crate::{bvh::bounds::Bounds3, domain::domain::{Intersection, Ray}};
+use crate::{bvh::bounds::Bounds3, domain::domain::{Intersection, Ray}, math::Sampler};
 
 pub trait Object : Send + Sync {
     fn get_name(&self) -> String {
@@ -10,5 +10,12 @@ pub trait Object : Send + Sync {
     fn get_bounds(&self) -> Bounds3;
     fn get_area(&self) -> f64;
     fn intersect(self: Arc<Self>, ray: &Ray) -> Intersection;
-    fn sample(&self) -> (Intersection, f64);
+    // whether `ray` hits the surface within its [t_min, t_max] range,
+    // shadow rays need no more than that so shapes may stop before finding
+    // the closest hit
+    fn intersect_any(self: Arc<Self>, ray: &Ray) -> bool {
+        self.intersect(ray).hit
+    }
+    // point on the surface drawn uniformly by area, with its density
+    fn sample(&self, sampler: &mut dyn Sampler) -> (Intersection, f64);
 }
\ No newline at end of file
@@ -0,0 +1,140 @@
+use std::{f64::consts::PI, sync::Arc};
+
+use crate::{
+    bvh::bounds::Bounds3, domain::domain::{Intersection, Ray}, material::material::Material, math::{vector::Vector3f, Sampler},
+};
+
+use super::object::Object;
+
+// hits closer than this are treated as the surface the ray starts on
+const SPHERE_EPSILON: f64 = 1e-4;
+
+pub struct Sphere {
+    pub center: Vector3f,
+    pub radius: f64,
+    pub material: Arc<dyn Material>,
+}
+
+impl Sphere {
+    pub fn new(center: &Vector3f, radius: f64, material: Arc<dyn Material>) -> Sphere {
+        Sphere {
+            center: *center,
+            radius,
+            material,
+        }
+    }
+}
+
+impl Object for Sphere {
+    fn get_name(&self) -> String {
+        format!("Sphere(c={}, r={})", self.center, self.radius)
+    }
+
+    fn get_bounds(&self) -> Bounds3 {
+        let extent = Vector3f::new(self.radius, self.radius, self.radius);
+        Bounds3::from_points(&(self.center - extent), &(self.center + extent))
+    }
+
+    fn get_area(&self) -> f64 {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    fn intersect(self: Arc<Self>, ray: &Ray) -> Intersection {
+        let oc = ray.origin - self.center;
+        let a = ray.direction.dot(&ray.direction);
+        let half_b = oc.dot(&ray.direction);
+        let c = oc.dot(&oc) - self.radius * self.radius;
+        let discriminant = half_b * half_b - a * c;
+        if discriminant < 0.0 {
+            return Intersection::new();
+        }
+
+        // nearest root inside the ray range, the far one when starting inside
+        let sqrt_d = f64::sqrt(discriminant);
+        let t0 = (-half_b - sqrt_d) / a;
+        let t1 = (-half_b + sqrt_d) / a;
+        let t_min = f64::max(ray.t_min, SPHERE_EPSILON);
+        let t = if t0 > t_min && t0 <= ray.t_max {
+            t0
+        } else if t1 > t_min && t1 <= ray.t_max {
+            t1
+        } else {
+            return Intersection::new();
+        };
+
+        let mut inter = Intersection::new();
+        inter.hit = true;
+        inter.coords = ray.eval(t);
+        inter.normal = (inter.coords - self.center).normalize();
+        inter.front_face = ray.direction.dot(&inter.normal) < 0.0;
+        inter.distance = t;
+        inter.material = Some(Arc::clone(&self.material));
+
+        let obj: Arc<dyn Object> = Arc::clone(&self) as _;
+        inter.obj = Some(obj);
+        inter
+    }
+
+    fn sample(&self, sampler: &mut dyn Sampler) -> (Intersection, f64) {
+        let (u1, u2) = sampler.next_2d();
+        let z = 1.0 - 2.0 * u1;
+        let r = f64::sqrt(f64::max(0.0, 1.0 - z * z));
+        let phi = 2.0 * PI * u2;
+        let normal = Vector3f::new(r * f64::cos(phi), r * f64::sin(phi), z);
+
+        let mut inter = Intersection::new();
+        inter.coords = self.center + normal * self.radius;
+        inter.normal = normal;
+        inter.emit = self.material.get_emission();
+        inter.material = Some(Arc::clone(&self.material));
+        (inter, 1.0 / self.get_area())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::material::LitMaterial;
+    use crate::math::UniformSampler;
+
+    fn sphere() -> Arc<Sphere> {
+        let material = Arc::new(LitMaterial::new(&Vector3f::scalar(0.5), &Vector3f::zero()));
+        Arc::new(Sphere::new(&Vector3f::new(1.0, 0.0, 0.0), 2.0, material))
+    }
+
+    #[test]
+    fn ray_through_the_center_hits_the_near_side() {
+        // the roots are at t = 3 and t = 7, the near one is returned
+        let ray = Ray::new(&Vector3f::new(1.0, 0.0, -5.0), &Vector3f::new(0.0, 0.0, 1.0), 0.0);
+        let inter = sphere().intersect(&ray);
+        assert!(inter.hit && inter.front_face);
+        assert!(f64::abs(inter.distance - 3.0) < 1e-12);
+        assert!(inter.coords.approx_eq(&Vector3f::new(1.0, 0.0, -2.0), 1e-12));
+        assert!(inter.normal.approx_eq(&Vector3f::new(0.0, 0.0, -1.0), 1e-12));
+
+        // from inside only the far root is ahead
+        let ray = Ray::new(&Vector3f::new(1.0, 0.0, 0.0), &Vector3f::new(0.0, 0.0, 1.0), 0.0);
+        let inter = sphere().intersect(&ray);
+        assert!(inter.hit && !inter.front_face);
+        assert!(f64::abs(inter.distance - 2.0) < 1e-12);
+
+        let ray = Ray::new(&Vector3f::new(1.0, 2.5, -5.0), &Vector3f::new(0.0, 0.0, 1.0), 0.0);
+        assert!(!sphere().intersect(&ray).hit);
+    }
+
+    #[test]
+    fn samples_are_uniform_over_the_area() {
+        let sphere = sphere();
+        let mut sampler = UniformSampler::new(7);
+        let mut mean_normal = Vector3f::zero();
+        let n = 4096;
+        for _ in 0..n {
+            let (inter, pdf) = sphere.sample(&mut sampler);
+            assert!(f64::abs(pdf * 16.0 * PI - 1.0) < 1e-12);
+            assert!(f64::abs((inter.coords - sphere.center).length() - 2.0) < 1e-12);
+            assert!((inter.coords - sphere.center).approx_eq(&(inter.normal * 2.0), 1e-12));
+            mean_normal += inter.normal / n as f64;
+        }
+        assert!(mean_normal.length() < 0.05);
+    }
+}
@@ -1,6 +1,6 @@
 use std::{sync::{Arc, Mutex}, collections::HashMap};
 
-use crate::{material::material::Material, bvh::bounds::Bounds3, domain::domain::{Ray, Intersection}, math::{vector::Vector3f, Math}};
+use crate::{material::material::Material, bvh::bounds::Bounds3, domain::domain::{Ray, Intersection}, math::{vector::Vector3f, Sampler}};
 use super::object::Object;
 
 lazy_static::lazy_static! {
@@ -14,6 +14,14 @@ pub struct Triangle {
     pub e1: Vector3f,
     pub e2: Vector3f,
     pub normal: Vector3f,
+    // texture coordinates of the vertices in x / y
+    pub uv0: Vector3f,
+    pub uv1: Vector3f,
+    pub uv2: Vector3f,
+    // per vertex colors, None when the mesh has none
+    pub colors: Option<[Vector3f; 3]>,
+    // direction of increasing u, zero when the uvs are degenerate
+    pub tangent: Vector3f,
     pub area: f64,
     pub material: Arc<dyn Material>,
     // weak_self: Weak<Triangle>
@@ -21,14 +29,39 @@ pub struct Triangle {
 
 impl Triangle {
     pub fn new(name: &str, v0: &Vector3f, v1: &Vector3f, v2: &Vector3f, material: Arc<dyn Material>) -> Arc<Triangle> {
+        let uv = Vector3f::zero();
+        Triangle::new_with_uv(name, v0, v1, v2, &uv, &uv, &uv, material)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_uv(name: &str,
+                       v0: &Vector3f, v1: &Vector3f, v2: &Vector3f,
+                       uv0: &Vector3f, uv1: &Vector3f, uv2: &Vector3f,
+                       material: Arc<dyn Material>) -> Arc<Triangle> {
+        Triangle::new_with_colors(name, v0, v1, v2, uv0, uv1, uv2, None, material)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_colors(name: &str,
+                           v0: &Vector3f, v1: &Vector3f, v2: &Vector3f,
+                           uv0: &Vector3f, uv1: &Vector3f, uv2: &Vector3f,
+                           colors: Option<[Vector3f; 3]>,
+                           material: Arc<dyn Material>) -> Arc<Triangle> {
         let e1 = v1 - v0;
         let e2 = v2 - v0; 
+        let normal = e1.cross(&e2).normalize();
+        let tangent = Self::compute_tangent(&e1, &e2, &(uv1 - uv0), &(uv2 - uv0), &normal);
         let s = Arc::new(Triangle { 
             name: String::from(name),
-            v0: v0.clone(),
-            v1: v1.clone(),
-            v2: v2.clone(),
-            normal: e1.cross(&e2).normalize(), 
+            v0: *v0,
+            v1: *v1,
+            v2: *v2,
+            uv0: *uv0,
+            uv1: *uv1,
+            uv2: *uv2,
+            colors,
+            tangent,
+            normal,
             area: e1.cross(&e2).length() * 0.5, 
             // weak_self: Weak::new(),
             material:Arc::clone(&material),
@@ -39,6 +72,20 @@ impl Triangle {
         table.insert(Arc::as_ptr(&s) as usize, Arc::clone(&s));
         s   
     }
+
+    // solves e = du * T + dv * B for T and orthogonalizes it against the normal
+    fn compute_tangent(e1: &Vector3f, e2: &Vector3f, duv1: &Vector3f, duv2: &Vector3f, normal: &Vector3f) -> Vector3f {
+        let det = duv1.x * duv2.y - duv2.x * duv1.y;
+        if f64::abs(det) < f64::EPSILON {
+            return Vector3f::zero();
+        }
+        let tangent = (e1 * duv2.y - e2 * duv1.y) / det;
+        let tangent = tangent - normal * normal.dot(&tangent);
+        if tangent.length() < f64::EPSILON {
+            return Vector3f::zero();
+        }
+        tangent.normalize()
+    }
 }
 
 impl Object for Triangle {
@@ -58,8 +105,9 @@ impl Object for Triangle {
     }
 
     fn intersect(self: Arc<Self>, ray: &Ray) -> Intersection {
-        // backface culling
-        if ray.direction.dot(&self.normal) > 0.0 {
+        // backface culling, double sided surfaces face the ray instead
+        let backface = ray.direction.dot(&self.normal) > 0.0;
+        if backface && !self.material.is_double_sided() {
             return Intersection::new();
         }
 
@@ -70,7 +118,7 @@ impl Object for Triangle {
         }
 
         let det_inv = 1.0 / det;
-        let tvec = &ray.origin - &self.v0;
+        let tvec = ray.origin - self.v0;
         let u = tvec.dot(&pvec) * det_inv;
         if !(0.0..=1.0).contains(&u) {
             return Intersection::new();
@@ -83,11 +131,17 @@ impl Object for Triangle {
         }
 
         let t = self.e2.dot(&qvec) * det_inv;
-        if t > 0.0 {
+        if t > ray.t_min && t <= ray.t_max {
             let mut inter = Intersection::new();
             inter.hit = true;
-            inter.coords = &ray.origin + &(&ray.direction * t);
-            inter.normal = self.normal.clone();
+            inter.coords = ray.origin + ray.direction * t;
+            inter.normal = if backface { -&self.normal } else { self.normal };
+            inter.tcoords = self.uv0 * (1.0 - u - v) + self.uv1 * u + self.uv2 * v;
+            inter.u = u;
+            inter.v = v;
+            inter.color = self.colors.map(|c| c[0] * (1.0 - u - v) + c[1] * u + c[2] * v);
+            inter.tangent = self.tangent;
+            inter.front_face = !backface;
             inter.distance = t;
             inter.material = Some(Arc::clone(&self.material));
 
@@ -99,14 +153,17 @@ impl Object for Triangle {
         }
     }
 
-    fn sample(&self) -> (Intersection, f64) {
-        let x = f64::sqrt(Math::sample_uniform_distribution(0.0, 1.0));
-        let y = Math::sample_uniform_distribution(0.0, 1.0);
+    fn sample(&self, sampler: &mut dyn Sampler) -> (Intersection, f64) {
+        let (u1, u2) = sampler.next_2d();
+        let x = f64::sqrt(u1);
+        let y = u2;
         let mut inter = Intersection::new();
-        inter.coords = &self.v0 * (1.0 - x) 
-                               + &self.v1 * (x * (1.0 - y))
-                               + &self.v2 * (x * y);
-        inter.normal = self.normal.clone();
+        inter.coords = self.v0 * (1.0 - x) 
+                               + self.v1 * (x * (1.0 - y))
+                               + self.v2 * (x * y);
+        inter.u = x * (1.0 - y);
+        inter.v = x * y;
+        inter.normal = self.normal;
         (inter, 1.0 / self.area)
     }
 }
@@ -115,15 +172,105 @@ impl Clone for Triangle {
     fn clone(&self) -> Self {
         Triangle { 
             name: self.name.clone(),
-            v0: self.v0.clone(), 
-            v1: self.v1.clone(),
-            v2: self.v2.clone(), 
-            e1: self.e1.clone(),
-            e2: self.e2.clone(), 
-            normal: self.normal.clone(), 
+            v0: self.v0, 
+            v1: self.v1,
+            v2: self.v2, 
+            e1: self.e1,
+            e2: self.e2, 
+            normal: self.normal, 
+            uv0: self.uv0,
+            uv1: self.uv1,
+            uv2: self.uv2,
+            colors: self.colors,
+            tangent: self.tangent,
             area: self.area, 
             material: Arc::clone(&self.material),
             // weak_self: Weak::clone(&self.weak_self)
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::bvh::BVH;
+    use crate::material::material::LitMaterial;
+    use crate::math::UniformSampler;
+
+    // unit right triangle in the z = 0 plane, facing +z
+    fn triangle(double_sided: bool) -> Arc<Triangle> {
+        let mut material = LitMaterial::new(&Vector3f::scalar(0.5), &Vector3f::zero());
+        material.double_sided = double_sided;
+        Triangle::new("test",
+                      &Vector3f::new(0.0, 0.0, 0.0),
+                      &Vector3f::new(1.0, 0.0, 0.0),
+                      &Vector3f::new(0.0, 1.0, 0.0),
+                      Arc::new(material))
+    }
+
+    // crosses the triangle along z, starting one unit before the plane
+    fn ray_along_z(dz: f64) -> Ray {
+        Ray::new(&Vector3f::new(0.25, 0.25, -dz), &Vector3f::new(0.0, 0.0, dz), 0.0)
+    }
+
+    #[test]
+    fn back_faces_are_culled_unless_double_sided() {
+        let front = triangle(false).intersect(&ray_along_z(-1.0));
+        assert!(front.hit && front.front_face);
+        assert!(front.normal.approx_eq(&Vector3f::new(0.0, 0.0, 1.0), 1e-12));
+        assert!(!triangle(false).intersect(&ray_along_z(1.0)).hit);
+
+        // the back of a double sided triangle is hit with the normal flipped
+        let back = triangle(true).intersect(&ray_along_z(1.0));
+        assert!(back.hit && !back.front_face);
+        assert!(back.normal.approx_eq(&Vector3f::new(0.0, 0.0, -1.0), 1e-12));
+        assert!(f64::abs(back.distance - 1.0) < 1e-12);
+    }
+
+    #[test]
+    fn hits_outside_the_ray_range_are_rejected() {
+        // the plane is reached at t = 1
+        let ranged = |t_min: f64, t_max: f64| {
+            let mut ray = ray_along_z(-1.0);
+            ray.t_min = t_min;
+            ray.t_max = t_max;
+            ray
+        };
+        let mut bvh = BVH::new(vec![triangle(false) as Arc<dyn Object>]);
+        bvh.build();
+        let bounds = triangle(false).get_bounds();
+        for (t_min, t_max, hit) in [(0.0, f64::MAX, true), (0.5, 1.5, true), (0.0, 0.5, false), (1.5, f64::MAX, false)] {
+            let ray = ranged(t_min, t_max);
+            assert_eq!(triangle(false).intersect(&ray).hit, hit);
+            assert_eq!(bvh.intersect(&ray).hit, hit);
+            assert_eq!(bvh.intersect_any(&ray), hit);
+            assert_eq!(bounds.intersect(&ray), hit);
+        }
+    }
+
+    #[test]
+    fn hits_report_the_barycentric_weights_of_v1_and_v2() {
+        let (v0, v1, v2) = (Vector3f::new(-1.0, 0.5, 2.0), Vector3f::new(3.0, -0.5, 2.0), Vector3f::new(0.5, 4.0, 2.0));
+        let material = Arc::new(LitMaterial::new(&Vector3f::scalar(0.5), &Vector3f::zero()));
+        let triangle = Triangle::new("skewed", &v0, &v1, &v2, material);
+        // weights of v0, v1 and v2, seen from the side the triangle faces
+        let hit_at = |w0: f64, w1: f64, w2: f64| {
+            let p = v0 * w0 + v1 * w1 + v2 * w2;
+            let ray = Ray::new(&(p + triangle.normal), &-&triangle.normal, 0.0);
+            triangle.clone().intersect(&ray)
+        };
+        let centroid = hit_at(1.0 / 3.0, 1.0 / 3.0, 1.0 / 3.0);
+        assert!(centroid.hit);
+        assert!(f64::abs(centroid.u - 1.0 / 3.0) < 1e-12 && f64::abs(centroid.v - 1.0 / 3.0) < 1e-12);
+        let off_center = hit_at(0.2, 0.5, 0.3);
+        assert!(f64::abs(off_center.u - 0.5) < 1e-12 && f64::abs(off_center.v - 0.3) < 1e-12);
+
+        // sampled points carry the weights they were built from
+        let mut sampler = UniformSampler::new(3);
+        for _ in 0..16 {
+            let (inter, _) = triangle.sample(&mut sampler);
+            let rebuilt = v0 * (1.0 - inter.u - inter.v) + v1 * inter.u + v2 * inter.v;
+            assert!(rebuilt.approx_eq(&inter.coords, 1e-12));
+        }
+    }
+}
@@ -0,0 +1,44 @@
+// reconstruction filter used to weight a sample into the pixels around it,
+// offsets and radii are in pixels
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum PixelFilter {
+    #[default]
+    Box,
+    Tent(f64),
+    // radius, falloff
+    Gaussian(f64, f64),
+}
+
+impl PixelFilter {
+    pub fn radius(&self) -> f64 {
+        match self {
+            PixelFilter::Box => 0.5,
+            PixelFilter::Tent(radius) => *radius,
+            PixelFilter::Gaussian(radius, _) => *radius,
+        }
+    }
+
+    pub fn weight(&self, dx: f64, dy: f64) -> f64 {
+        match self {
+            PixelFilter::Box => {
+                if f64::abs(dx) <= 0.5 && f64::abs(dy) <= 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            PixelFilter::Tent(radius) => {
+                let wx = f64::max(0.0, 1.0 - f64::abs(dx) / radius);
+                let wy = f64::max(0.0, 1.0 - f64::abs(dy) / radius);
+                wx * wy
+            }
+            PixelFilter::Gaussian(radius, alpha) => {
+                // shifted down so the weight reaches zero at the radius
+                let edge = f64::exp(-alpha * radius * radius);
+                let wx = f64::max(0.0, f64::exp(-alpha * dx * dx) - edge);
+                let wy = f64::max(0.0, f64::exp(-alpha * dy * dy) - edge);
+                wx * wy
+            }
+        }
+    }
+}
@@ -1,3 +1,5 @@
 pub mod rendering;
+pub mod filter;
 pub mod framebuffer;
+pub mod stats;
 pub mod texture;
\ No newline at end of file
@@ -1,38 +1,344 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{mpsc, Arc};
+use std::time::Instant;
 
 use indicatif::{ProgressBar, ProgressStyle};
 use rayon::prelude::*;
 
-use crate::domain::domain::Ray;
+use crate::bvh::bvh::BVH;
+use crate::domain::domain::{Intersection, Ray};
 use crate::math::vector::Vector3f;
-use crate::math::Math;
+use crate::math::{HaltonSampler, Sampler, UniformSampler};
+use crate::renderer::filter::PixelFilter;
 use crate::renderer::framebuffer::FrameBuffer;
-use crate::renderer::texture::RenderTextureSetMode;
-use crate::scene::scene::Scene;
+use crate::renderer::stats::RenderStats;
+use crate::renderer::texture::{PixelVariance, RenderTexture, RenderTextureSetMode};
+use crate::scene::scene::{Aov, Scene, DIMENSION_JITTER, DIMENSION_TIME};
 
 pub struct Renderer {
     pub fbo: Option<FrameBuffer>,
+    pub foveation: Option<FoveatedSampling>,
+    pub adaptive: Option<AdaptiveSampling>,
+    pub filter: PixelFilter,
+    pub mode: RenderMode,
+    // edge length of the square pixel tiles handed to each render task
+    pub tile_size: u32,
+    // only pixels with x0 <= x < x1 and y0 <= y < y1 are rendered, the rest
+    // of the render target keeps its contents. wide filters still splat a
+    // little into the pixels bordering the window
+    pub crop: Option<(u32, u32, u32, u32)>,
+    // statistics of the last render
+    pub stats: Option<RenderStats>,
+    // path tracing traces the primary rays of a whole wave into a hit
+    // buffer before shading any of them, the bounces stay recursive
+    pub deferred: bool,
+    pub sampler: SamplerKind,
+    // every pixel sample gets its own sampler derived from this seed, so a
+    // render with the same seed and settings is reproducible
+    pub seed: u64,
+    // path tracing also stores the first hit albedo and normal of every
+    // pixel in the guide buffers of the render target, for denoisers
+    pub guide_buffers: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum SamplerKind {
+    #[default]
+    Uniform,
+    // scrambled halton points, less noise than uniform at low spp
+    Halton,
+}
+
+// sampler of one pixel sample, the sample index of a pixel is its wave
+#[derive(Clone)]
+enum PixelSampler {
+    Uniform(UniformSampler),
+    Halton(HaltonSampler),
+}
+
+impl PixelSampler {
+    fn new(kind: SamplerKind, seed: u64, pixel: u64, sample_index: u64) -> PixelSampler {
+        match kind {
+            SamplerKind::Uniform => {
+                PixelSampler::Uniform(UniformSampler::new(seed ^ (pixel << 24) ^ sample_index))
+            }
+            SamplerKind::Halton => {
+                PixelSampler::Halton(HaltonSampler::new(sample_index, Some(seed ^ pixel)))
+            }
+        }
+    }
+}
+
+impl Sampler for PixelSampler {
+    fn next_1d(&mut self) -> f64 {
+        match self {
+            PixelSampler::Uniform(sampler) => sampler.next_1d(),
+            PixelSampler::Halton(sampler) => sampler.next_1d(),
+        }
+    }
+
+    fn set_dimension(&mut self, dimension: usize) {
+        match self {
+            PixelSampler::Uniform(sampler) => sampler.set_dimension(dimension),
+            PixelSampler::Halton(sampler) => sampler.set_dimension(dimension),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum RenderMode {
+    #[default]
+    PathTracing,
+    // normal/view based studio lighting for material authoring
+    MaterialPreview,
+    // a single debug channel of the first hit instead of the final color
+    Aov(Aov),
+}
+
+// keep sampling a pixel until the variance of its estimate drops below
+// `variance_threshold`, taking at least `min_spp` and at most `max_spp`
+pub struct AdaptiveSampling {
+    pub min_spp: u32,
+    pub max_spp: u32,
+    pub variance_threshold: f64,
+}
+
+impl AdaptiveSampling {
+    pub fn new(min_spp: u32, max_spp: u32, variance_threshold: f64) -> AdaptiveSampling {
+        AdaptiveSampling {
+            min_spp,
+            max_spp,
+            variance_threshold,
+        }
+    }
+
+    pub fn needs_sample(&self, variance: &PixelVariance, max_spp: u32) -> bool {
+        let n_samples = variance.n_samples;
+        if n_samples >= max_spp {
+            return false;
+        }
+        if n_samples < u32::max(self.min_spp, 2) {
+            return true;
+        }
+        variance.mean_variance() > self.variance_threshold
+    }
+}
+
+// spp falls off from the scene spp at the gaze point down to `min_spp`
+// in the periphery, used for VR / interactive previews
+pub struct FoveatedSampling {
+    pub gaze_x: f64,
+    pub gaze_y: f64,
+    pub fovea_radius: f64,
+    pub falloff_radius: f64,
+    pub min_spp: u32,
+}
+
+impl FoveatedSampling {
+    pub fn new(
+        gaze_x: f64,
+        gaze_y: f64,
+        fovea_radius: f64,
+        falloff_radius: f64,
+        min_spp: u32,
+    ) -> FoveatedSampling {
+        FoveatedSampling {
+            gaze_x,
+            gaze_y,
+            fovea_radius,
+            falloff_radius,
+            min_spp,
+        }
+    }
+
+    pub fn sample_count(&self, x: u32, y: u32, max_spp: u32) -> u32 {
+        let min_spp = u32::min(u32::max(self.min_spp, 1), max_spp);
+        let dx = x as f64 + 0.5 - self.gaze_x;
+        let dy = y as f64 + 0.5 - self.gaze_y;
+        let distance = f64::sqrt(dx * dx + dy * dy);
+        if distance <= self.fovea_radius {
+            return max_spp;
+        }
+        if self.falloff_radius <= 0.0 {
+            return min_spp;
+        }
+
+        // smoothstep between the fovea edge and the periphery
+        let t = f64::clamp(
+            (distance - self.fovea_radius) / self.falloff_radius,
+            0.0,
+            1.0,
+        );
+        let weight = 1.0 - t * t * (3.0 - 2.0 * t);
+        let spp = min_spp as f64 + (max_spp - min_spp) as f64 * weight;
+        u32::clamp(spp.round() as u32, min_spp, max_spp)
+    }
 }
 
 struct RenderMessage {
     pub x: u32,
     pub y: u32,
     pub color: Vector3f,
+    pub alpha: f64,
+    pub film_x: f64,
+    pub film_y: f64,
+    // first hit albedo and normal when guide buffers are stored
+    pub guides: Option<(Vector3f, Vector3f)>,
+}
+
+// primary ray of a work item and its closest hit, kept for the shading
+// pass of deferred rendering together with the sampler that continues
+// the path
+struct FirstHit {
+    pub ray: Ray,
+    pub film_x: f64,
+    pub film_y: f64,
+    pub inter: Intersection,
+    pub sampler: PixelSampler,
 }
 
+// work items whose primary rays a deferred task traces in one go
+const FIRST_HIT_CHUNK: usize = 256;
+
 impl Renderer {
     pub fn new() -> Renderer {
-        Renderer { fbo: None }
+        Renderer {
+            fbo: None,
+            foveation: None,
+            adaptive: None,
+            filter: PixelFilter::Box,
+            mode: RenderMode::PathTracing,
+            tile_size: 16,
+            crop: None,
+            stats: None,
+            deferred: false,
+            sampler: SamplerKind::Uniform,
+            seed: 0,
+            guide_buffers: false,
+        }
+    }
+
+    // the crop window clamped to the image, (x0, y0, x1, y1) with the upper
+    // bounds exclusive
+    pub fn crop_window(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        match self.crop {
+            Some((x0, y0, x1, y1)) => {
+                let x0 = u32::min(x0, width);
+                let y0 = u32::min(y0, height);
+                (x0, y0, x1.clamp(x0, width), y1.clamp(y0, height))
+            }
+            None => (0, 0, width, height),
+        }
     }
 
-    pub fn render(&mut self, scene: Arc<Scene>, n_threads: u32) -> Result<(), &'static str> {
+    // the maximum number of samples a pixel may receive
+    pub fn sample_count(&self, scene: &Scene, x: u32, y: u32) -> u32 {
+        let max_spp = match &self.adaptive {
+            Some(adaptive) => adaptive.max_spp,
+            None => scene.sample_per_pixel,
+        };
+        match &self.foveation {
+            Some(foveation) => foveation.sample_count(x, y, max_spp),
+            None => max_spp,
+        }
+    }
+
+    // samples are taken in waves of 1 spp over the whole image, `preview`
+    // is invoked with the converging image after every wave. Setting
+    // `cancel` stops scheduling new tiles and returns Err("cancelled") with
+    // the samples taken so far left in the FBO. `n_threads` render workers
+    // are used, None takes one per available core
+    pub fn render(
+        &mut self,
+        scene: Arc<Scene>,
+        n_threads: Option<u32>,
+        preview: Option<&dyn Fn(&RenderTexture)>,
+        cancel: Option<Arc<AtomicBool>>,
+    ) -> Result<(), &'static str> {
+        let pool = Self::create_pool(n_threads)?;
+        self.render_in_pool(&pool, scene, preview, cancel)
+    }
+
+    // renders `n_frames` frames of an animation into `dir/frame_0001.png`,
+    // ... and returns the written paths. `update` moves the camera (or
+    // anything else that needs no bvh rebuild) to the given frame before
+    // it is rendered, all frames share one thread pool
+    pub fn render_sequence(
+        &mut self,
+        scene: Scene,
+        n_frames: u32,
+        update: &mut dyn FnMut(&mut Scene, u32),
+        n_threads: Option<u32>,
+        dir: &str,
+        cancel: Option<Arc<AtomicBool>>,
+    ) -> Result<Vec<String>, String> {
+        if self.fbo.is_none() {
+            return Err(String::from("FBO not set"));
+        }
+        let pool = Self::create_pool(n_threads)?;
+        let mut scene = scene;
+        let mut paths = vec![];
+        for frame in 0..n_frames {
+            println!("[Renderer] frame {} of {}", frame + 1, n_frames);
+            update(&mut scene, frame);
+            self.fbo.as_mut().unwrap().get_render_target().clear();
+
+            let shared = Arc::new(scene);
+            self.render_in_pool(&pool, Arc::clone(&shared), None, cancel.clone())?;
+            // the render threads are done, so this is the only reference left
+            scene = Arc::into_inner(shared).ok_or("scene still shared after render")?;
+
+            let path = format!("{}/frame_{:04}.png", dir, frame + 1);
+            let rt = self.fbo.as_mut().unwrap().get_render_target();
+            rt.dump_to_png(&path)
+                .map_err(|err| format!("cannot write {}: {}", path, err))?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+
+    // number of render workers for `n_threads`, None detects the available
+    // parallelism and falls back to a single worker where it is unknown
+    pub fn worker_count(n_threads: Option<u32>) -> Result<u32, &'static str> {
+        match n_threads {
+            Some(0) => Err("thread count must be at least 1"),
+            Some(n) => Ok(n),
+            None => Ok(std::thread::available_parallelism().map_or(1, |n| n.get() as u32)),
+        }
+    }
+
+    // the workers plus one thread for the reducer that writes finished
+    // tiles into the render target. it spends most of the render blocked on
+    // the channel, so it is not taken out of the worker budget
+    pub fn create_pool(n_threads: Option<u32>) -> Result<rayon::ThreadPool, &'static str> {
+        let workers = Self::worker_count(n_threads)?;
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(workers as usize + 1)
+            .build()
+            .map_err(|_| "cannot create the render thread pool")
+    }
+
+    fn render_in_pool(
+        &mut self,
+        pool: &rayon::ThreadPool,
+        scene: Arc<Scene>,
+        preview: Option<&dyn Fn(&RenderTexture)>,
+        cancel: Option<Arc<AtomicBool>>,
+    ) -> Result<(), &'static str> {
         if self.fbo.is_none() {
             return Err("FBO not set");
         }
 
-        let scale = f64::tan(Math::radian(scene.fov * 0.5));
+        let start_time = Instant::now();
         let aspect = scene.width as f64 / scene.height as f64;
-        let eye_pos = Vector3f::new(278.0, 273.0, -800.0);
+        let (x0, y0, x1, y1) = self.crop_window(scene.width, scene.height);
+        let work_items: Vec<_> = (y0..y1)
+            .flat_map(|y| (x0..x1).map(move |x| (x, y)))
+            .map(|(x, y)| (x, y, self.sample_count(&scene, x, y)))
+            .collect();
+        let sample_budget: u64 = work_items.iter().map(|item| item.2 as u64).sum();
+        let n_waves = work_items.iter().map(|item| item.2).max().unwrap_or(0);
+
         let fbo = self.fbo.as_mut().unwrap();
         let rt = fbo.get_render_target();
         println!(
@@ -42,57 +348,319 @@ impl Renderer {
             scene.sample_per_pixel
         );
 
-        let work_items: Vec<_> = (0..scene.height)
-            .flat_map(|y| (0..scene.width).map(move |x| (x, y)))
-            .collect();
+        if self.crop.is_some() {
+            println!(
+                "[Renderer] crop window ({}, {}) - ({}, {}), {} pixels",
+                x0,
+                y0,
+                x1,
+                y1,
+                work_items.len()
+            );
+        }
 
-        let pool = rayon::ThreadPoolBuilder::new()
-            .num_threads(n_threads as usize + 1) // 1 extra thread for reducing
-            .build()
-            .unwrap();
-        pool.scope(|s| {
-            let (tx, rx) = mpsc::channel::<RenderMessage>();
-
-            s.spawn(|_| {
-                // progress bar
-                let m_style = ProgressStyle::with_template(
-                    "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}",
-                )
-                .unwrap()
-                .progress_chars("##-");
-                let m = ProgressBar::new(work_items.len() as _).with_style(m_style);
-
-                m.println(format!("ray tracing using {n_threads} threads..."));
-
-                for received in rx {
-                    rt.set(
-                        received.x,
-                        received.y,
-                        received.color,
-                        RenderTextureSetMode::Add,
-                    );
-                    m.inc(1);
-                }
-            });
+        if self.foveation.is_some() {
+            println!(
+                "[Renderer] foveated sampling, sample budget {} ({:.2} spp avg)",
+                sample_budget,
+                sample_budget as f64 / work_items.len() as f64
+            );
+        }
+
+        // progress bar
+        let m_style = ProgressStyle::with_template(
+            "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}",
+        )
+        .unwrap()
+        .progress_chars("##-");
+        let m = ProgressBar::new(sample_budget).with_style(m_style);
+        m.println(format!(
+            "ray tracing using {} threads...",
+            pool.current_num_threads() - 1
+        ));
+
+        let adaptive = self.adaptive.as_ref();
+        let filter = self.filter;
+        let mode = self.mode;
+        let deferred = self.deferred && mode == RenderMode::PathTracing;
+        let guide_buffers = self.guide_buffers && mode == RenderMode::PathTracing;
+        if guide_buffers {
+            rt.enable_guides();
+        }
+        let (sampler_kind, seed) = (self.sampler, self.seed);
+        let is_cancelled = || {
+            cancel
+                .as_ref()
+                .is_some_and(|flag| flag.load(Ordering::Relaxed))
+        };
+        let mut samples_taken: u64 = 0;
+        // summed from the per-thread counters once a tile is done
+        let rays_cast = AtomicU64::new(0);
+        let node_visits = AtomicU64::new(0);
+        // sampler of the `wave`-th sample of pixel (i, j)
+        let pixel_sampler = |i: u32, j: u32, wave: u32| -> PixelSampler {
+            let pixel = j as u64 * scene.width as u64 + i as u64;
+            PixelSampler::new(sampler_kind, seed, pixel, wave as u64)
+        };
+        // primary ray through a jittered position in pixel (i, j), with the
+        // film position it was shot through
+        let primary_ray = |i: u32, j: u32, sampler: &mut PixelSampler| -> (Ray, f64, f64) {
+            // box filtering keeps shooting through the pixel center,
+            // wider filters need jittered samples to reconstruct from
+            let (jitter_x, jitter_y) = if filter == PixelFilter::Box {
+                (0.5, 0.5)
+            } else {
+                sampler.set_dimension(DIMENSION_JITTER);
+                sampler.next_2d()
+            };
+            let film_x = i as f64 + jitter_x;
+            let film_y = j as f64 + jitter_y;
+            let x = 2.0 * film_x / scene.width as f64 - 1.0;
+            let y = 1.0 - 2.0 * film_y / scene.height as f64;
+            let (offset, dir) = scene.projection.camera_ray(x, y, aspect);
+            let time = if scene.camera_end.is_some() {
+                sampler.set_dimension(DIMENSION_TIME);
+                sampler.next_1d()
+            } else {
+                0.0
+            };
+            let camera = scene.camera_at(time);
+            let ray = Ray::new(
+                &(camera.position + camera.to_world(&offset)),
+                &camera.to_world(&dir),
+                0.0,
+            );
+            (ray, film_x, film_y)
+        };
+        for wave in 0..n_waves {
+            if is_cancelled() {
+                break;
+            }
+            let wave_items: Vec<_> = work_items
+                .iter()
+                .filter(|point| match adaptive {
+                    Some(adaptive) => {
+                        adaptive.needs_sample(rt.get_variance(point.0, point.1), point.2)
+                    }
+                    None => point.2 > wave,
+                })
+                .collect();
+            if wave_items.is_empty() {
+                break;
+            }
+            samples_taken += wave_items.len() as u64;
 
-            work_items.par_iter().for_each(|point| {
-                let (i, j) = *point;
-
-                let x = (2.0 * (i as f64 + 0.5) / scene.width as f64 - 1.0) * aspect * scale;
-                let y = (1.0 - 2.0 * (j as f64 + 0.5) / scene.height as f64) * scale;
-                let dir = Vector3f::new(-x, y, 1.0).normalize();
-                let ray = Ray::new(&eye_pos, &dir, 0.0);
-                let mut color = Vector3f::zero();
-                for _ in 0..scene.sample_per_pixel {
-                    let (sample_color, _) = scene.cast_ray(&ray).unwrap_or_else(|err| {
-                        panic!("scene cast error {}", err);
-                    });
-                    color += sample_color / scene.sample_per_pixel;
+            // deferred rendering traces every primary ray of the wave before
+            // shading, indexed like `wave_items`
+            let first_hits: Vec<FirstHit> = if deferred {
+                pool.install(|| {
+                    wave_items
+                        .par_chunks(FIRST_HIT_CHUNK)
+                        .flat_map_iter(|chunk| {
+                            let rays_before = Scene::rays_cast();
+                            let visits_before = BVH::node_visits();
+                            let hits: Vec<_> = chunk
+                                .iter()
+                                .map(|point| {
+                                    let mut sampler = pixel_sampler(point.0, point.1, wave);
+                                    let (ray, film_x, film_y) =
+                                        primary_ray(point.0, point.1, &mut sampler);
+                                    let inter = scene.first_hit(&ray).unwrap_or_else(|err| {
+                                        panic!("scene cast error {}", err);
+                                    });
+                                    FirstHit {
+                                        ray,
+                                        film_x,
+                                        film_y,
+                                        inter,
+                                        sampler,
+                                    }
+                                })
+                                .collect();
+                            rays_cast
+                                .fetch_add(Scene::rays_cast() - rays_before, Ordering::Relaxed);
+                            node_visits
+                                .fetch_add(BVH::node_visits() - visits_before, Ordering::Relaxed);
+                            hits
+                        })
+                        .collect()
+                })
+            } else {
+                vec![]
+            };
+
+            // shades the work item `index` of the wave at pixel (i, j)
+            let trace = |index: usize, i: u32, j: u32| -> RenderMessage {
+                let mut guides = None;
+                let (color, hit, film_x, film_y) = match first_hits.get(index) {
+                    Some(first) => {
+                        let mut sampler = first.sampler.clone();
+                        let (color, hit) =
+                            scene.shade_first_hit(&first.ray, &first.inter, &mut sampler);
+                        if guide_buffers {
+                            guides = Some(Scene::guides_at(&first.inter));
+                        }
+                        (color, hit, first.film_x, first.film_y)
+                    }
+                    None => {
+                        let mut sampler = pixel_sampler(i, j, wave);
+                        let (ray, film_x, film_y) = primary_ray(i, j, &mut sampler);
+                        let (color, hit) = match mode {
+                            RenderMode::PathTracing => scene.first_hit(&ray).map(|inter| {
+                                if guide_buffers {
+                                    guides = Some(Scene::guides_at(&inter));
+                                }
+                                scene.shade_first_hit(&ray, &inter, &mut sampler)
+                            }),
+                            RenderMode::MaterialPreview => scene.cast_preview_ray(&ray),
+                            RenderMode::Aov(aov) => scene.cast_aov_ray(&ray, aov),
+                        }
+                        .unwrap_or_else(|err| {
+                            panic!("scene cast error {}", err);
+                        });
+                        (color, hit, film_x, film_y)
+                    }
+                };
+                let alpha = if hit { 1.0 } else { 0.0 };
+                RenderMessage {
+                    x: i,
+                    y: j,
+                    color,
+                    alpha,
+                    film_x,
+                    film_y,
+                    guides,
                 }
-                tx.send(RenderMessage { x: i, y: j, color })
-                    .expect("renderer message send failure");
+            };
+
+            // every task renders one tile and reports it as a single batch,
+            // filtered samples are also splatted into a per-task filter tile
+            // which is merged once the wave is done
+            let tile_size = u32::max(self.tile_size, 1);
+            let tiles_x = scene.width.div_ceil(tile_size);
+            let tiles_y = scene.height.div_ceil(tile_size);
+            let mut tile_items = vec![vec![]; (tiles_x * tiles_y) as usize];
+            for (index, point) in wave_items.iter().enumerate() {
+                let tile_index = (point.1 / tile_size) * tiles_x + point.0 / tile_size;
+                tile_items[tile_index as usize].push((index, *point));
+            }
+            let tiles: Vec<_> = tile_items
+                .into_iter()
+                .enumerate()
+                .filter(|(_, items)| !items.is_empty())
+                .map(|(tile_index, items)| {
+                    let filter_tile = if filter == PixelFilter::Box {
+                        None
+                    } else {
+                        let tile_x = tile_index as u32 % tiles_x * tile_size;
+                        let tile_y = tile_index as u32 / tiles_x * tile_size;
+                        Some(rt.create_tile(tile_x, tile_y, tile_size, tile_size, &filter))
+                    };
+                    (filter_tile, items)
+                })
+                .collect();
+
+            let tiles = pool.scope(|s| {
+                let (tx, rx) = mpsc::channel::<Vec<RenderMessage>>();
+
+                s.spawn(|_| {
+                    for batch in rx {
+                        // one progress update per tile, the bar is shared
+                        // with the drawing thread and too slow to tick per pixel
+                        m.inc(batch.len() as u64);
+                        for received in batch {
+                            // blend into the running mean of the pixel's samples
+                            rt.add_variance_sample(received.x, received.y, &received.color);
+                            let n_samples = rt.get_variance(received.x, received.y).n_samples;
+                            if filter == PixelFilter::Box {
+                                rt.set(
+                                    received.x,
+                                    received.y,
+                                    received.color,
+                                    RenderTextureSetMode::Blend(1.0 / n_samples as f64),
+                                );
+                            }
+                            rt.set_alpha(
+                                received.x,
+                                received.y,
+                                received.alpha,
+                                RenderTextureSetMode::Blend(1.0 / n_samples as f64),
+                            );
+                            if let Some((albedo, normal)) = &received.guides {
+                                rt.add_guide_sample(
+                                    received.x, received.y, albedo, normal, n_samples,
+                                );
+                            }
+                        }
+                    }
+                });
+
+                tiles
+                    .into_par_iter()
+                    .filter_map(|(mut filter_tile, items)| {
+                        if is_cancelled() {
+                            return None;
+                        }
+                        let rays_before = Scene::rays_cast();
+                        let visits_before = BVH::node_visits();
+                        let batch: Vec<_> = items
+                            .iter()
+                            .map(|(index, point)| {
+                                let message = trace(*index, point.0, point.1);
+                                if let Some(tile) = filter_tile.as_mut() {
+                                    tile.splat(
+                                        message.film_x,
+                                        message.film_y,
+                                        &message.color,
+                                        &filter,
+                                    );
+                                }
+                                message
+                            })
+                            .collect();
+                        rays_cast.fetch_add(Scene::rays_cast() - rays_before, Ordering::Relaxed);
+                        node_visits
+                            .fetch_add(BVH::node_visits() - visits_before, Ordering::Relaxed);
+                        tx.send(batch).expect("renderer message send failure");
+                        filter_tile
+                    })
+                    .collect::<Vec<_>>()
             });
-        });
+
+            for tile in tiles.iter() {
+                rt.merge_tile(tile);
+            }
+            if filter != PixelFilter::Box {
+                rt.resolve_filter();
+            }
+            if let Some(callback) = preview {
+                callback(rt);
+            }
+        }
+        let stats = RenderStats {
+            wall_time: start_time.elapsed(),
+            pixels: work_items.len() as u64,
+            samples: samples_taken,
+            rays_cast: rays_cast.into_inner(),
+            bvh_node_visits: node_visits.into_inner(),
+            peak_memory_bytes: RenderStats::peak_memory(),
+        };
+        self.stats = Some(stats.clone());
+        if is_cancelled() {
+            m.abandon_with_message("cancelled");
+            return Err("cancelled");
+        }
+        m.finish();
+
+        if adaptive.is_some() {
+            println!(
+                "[Renderer] adaptive sampling used {} of {} samples ({:.2} spp avg)",
+                samples_taken,
+                sample_budget,
+                samples_taken as f64 / work_items.len() as f64
+            );
+        }
+        println!("{}", stats);
         Ok(())
     }
 }
@@ -102,3 +670,302 @@ impl Default for Renderer {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::material::material::LitMaterial;
+    use crate::mesh::model::Model;
+    use crate::renderer::texture::Bitmap2D;
+    use crate::scene::scene::EstimatorStrategy;
+
+    // the cornell box of main at a tiny resolution
+    fn cornell_scene(size: u32, spp: u32) -> Scene {
+        let mut scene = Scene::new(
+            size,
+            size,
+            40.0,
+            Vector3f::new(0.235294, 0.67451, 0.843137),
+            EstimatorStrategy::RussianRoulette(0.8),
+            spp,
+        );
+        let white = Arc::new(LitMaterial::new(
+            &Vector3f::new(0.725, 0.71, 0.68),
+            &Vector3f::zero(),
+        ));
+        let light = Arc::new(LitMaterial::new(
+            &Vector3f::scalar(0.65),
+            &Vector3f::new(47.0, 38.0, 31.0),
+        ));
+        for name in ["floor", "shortbox", "tallbox", "left", "right"] {
+            let path = format!("./resource/cornellbox/{}.obj", name);
+            scene.add(Arc::new(Model::new(&path, white.clone()).unwrap()));
+        }
+        let path = "./resource/cornellbox/light.obj";
+        scene.add(Arc::new(Model::new(path, light).unwrap()));
+        scene.build_bvh();
+        scene
+    }
+
+    // renders into a fresh FBO with one worker and returns the image
+    fn render(renderer: &mut Renderer, scene: Arc<Scene>) -> Bitmap2D {
+        renderer.fbo = Some(FrameBuffer::new(scene.width, scene.height));
+        renderer.render(scene, Some(1), None, None).unwrap();
+        let rt = renderer.fbo.as_mut().unwrap().get_render_target();
+        rt.get_color_attachment().clone()
+    }
+
+    fn max_difference(a: &Bitmap2D, b: &Bitmap2D) -> f64 {
+        a.iter()
+            .flatten()
+            .zip(b.iter().flatten())
+            .map(|(a, b)| (*a - *b).length())
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn foveated_sample_count_falls_off_from_the_gaze_point() {
+        let foveation = FoveatedSampling::new(50.0, 50.0, 10.0, 40.0, 4);
+        assert_eq!(foveation.sample_count(49, 49, 64), 64);
+        assert_eq!(foveation.sample_count(55, 49, 64), 64);
+
+        // fewer samples the further out along the falloff
+        let mut previous = 64;
+        for x in 60..100 {
+            let spp = foveation.sample_count(x, 49, 64);
+            assert!(spp <= previous);
+            assert!(spp >= 4);
+            previous = spp;
+        }
+        let halfway = foveation.sample_count(79, 49, 64);
+        assert!(halfway > 4 && halfway < 64);
+        assert_eq!(foveation.sample_count(0, 0, 64), 4);
+    }
+
+    #[test]
+    fn foveated_sample_count_stays_within_the_spp_range() {
+        let no_minimum = FoveatedSampling::new(0.0, 0.0, 1.0, 1.0, 0);
+        assert_eq!(no_minimum.sample_count(100, 100, 16), 1);
+        let minimum_above_max = FoveatedSampling::new(0.0, 0.0, 1.0, 1.0, 32);
+        assert_eq!(minimum_above_max.sample_count(100, 100, 16), 16);
+    }
+
+    #[test]
+    fn preview_fires_once_per_wave_and_converges_to_the_full_render() {
+        let scene = Arc::new(cornell_scene(8, 4));
+        let expected = render(&mut Renderer::new(), Arc::clone(&scene));
+        assert!(expected.iter().flatten().any(|c| c.length() > 0.0));
+
+        let mut renderer = Renderer::new();
+        renderer.fbo = Some(FrameBuffer::new(8, 8));
+        let calls = Cell::new(0);
+        let last = Cell::new(f64::MAX);
+        let preview = |rt: &RenderTexture| {
+            calls.set(calls.get() + 1);
+            last.set(max_difference(&rt.resolved(), &expected));
+        };
+        renderer
+            .render(scene, Some(1), Some(&preview), None)
+            .unwrap();
+        assert_eq!(calls.get(), 4);
+        assert!(last.get() < 1e-9);
+    }
+
+    // samples pushed into a pixel until adaptive sampling stops it
+    fn adaptive_samples(adaptive: &AdaptiveSampling, sample: impl Fn(u32) -> f64) -> u32 {
+        let mut variance = PixelVariance::new();
+        while adaptive.needs_sample(&variance, adaptive.max_spp) {
+            variance.push(&Vector3f::scalar(sample(variance.n_samples)));
+        }
+        variance.n_samples
+    }
+
+    #[test]
+    fn adaptive_sampling_stops_flat_pixels_early() {
+        let adaptive = AdaptiveSampling::new(4, 256, 1e-4);
+        assert_eq!(adaptive_samples(&adaptive, |_| 0.5), 4);
+        // an edge pixel alternating between dark and bright samples
+        let edge = adaptive_samples(&adaptive, |i| (i % 2) as f64);
+        assert!(edge > 64);
+        assert!(edge <= 256);
+    }
+
+    #[test]
+    fn tile_size_does_not_change_the_image() {
+        // 10 is not a multiple of 7, so the last row and column of tiles are partial
+        let scene = Arc::new(cornell_scene(10, 2));
+        let mut renderer = Renderer::new();
+        renderer.seed = 7;
+        renderer.tile_size = 1;
+        let expected = render(&mut renderer, Arc::clone(&scene));
+        assert!(expected.iter().flatten().any(|c| c.length() > 0.0));
+        for tile_size in [7, 64] {
+            renderer.tile_size = tile_size;
+            let image = render(&mut renderer, Arc::clone(&scene));
+            assert!(max_difference(&image, &expected) < 1e-12);
+        }
+    }
+
+    #[test]
+    fn parallel_render_matches_a_single_worker() {
+        let scene = Arc::new(cornell_scene(10, 2));
+        let mut renderer = Renderer::new();
+        renderer.tile_size = 3;
+        let expected = render(&mut renderer, Arc::clone(&scene));
+
+        renderer.fbo = Some(FrameBuffer::new(10, 10));
+        renderer.render(scene, Some(4), None, None).unwrap();
+        let rt = renderer.fbo.as_mut().unwrap().get_render_target();
+        assert!(max_difference(rt.get_color_attachment(), &expected) < 1e-12);
+    }
+
+    #[test]
+    fn preset_cancel_returns_before_sampling() {
+        // minutes of work if the flag were ignored
+        let scene = Arc::new(cornell_scene(64, 4096));
+        let mut renderer = Renderer::new();
+        renderer.fbo = Some(FrameBuffer::new(64, 64));
+        let cancel = Arc::new(AtomicBool::new(true));
+        let start = Instant::now();
+        let result = renderer.render(scene, Some(2), None, Some(cancel));
+        assert_eq!(result, Err("cancelled"));
+        assert!(start.elapsed().as_secs() < 5);
+        assert_eq!(renderer.stats.as_ref().unwrap().samples, 0);
+    }
+
+    #[test]
+    fn crop_leaves_the_outside_pixels_untouched() {
+        let scene = Arc::new(cornell_scene(8, 1));
+        let mut renderer = Renderer::new();
+        renderer.crop = Some((2, 3, 6, 5));
+        renderer.fbo = Some(FrameBuffer::new(8, 8));
+        let sentinel = Vector3f::new(-1.0, 2.0, -3.0);
+        let rt = renderer.fbo.as_mut().unwrap().get_render_target();
+        for y in 0..8 {
+            for x in 0..8 {
+                rt.set(x, y, sentinel, RenderTextureSetMode::Overwrite);
+            }
+        }
+        renderer.render(scene, Some(1), None, None).unwrap();
+
+        let rt = renderer.fbo.as_mut().unwrap().get_render_target();
+        for (y, row) in rt.get_color_attachment().iter().enumerate() {
+            for (x, color) in row.iter().enumerate() {
+                let inside = (2..6).contains(&x) && (3..5).contains(&y);
+                assert_eq!(*color == sentinel, !inside);
+            }
+        }
+    }
+
+    #[test]
+    fn stats_are_filled_in_after_a_render() {
+        let scene = Arc::new(cornell_scene(6, 3));
+        let mut renderer = Renderer::new();
+        assert!(renderer.stats.is_none());
+        render(&mut renderer, scene);
+        let stats = renderer.stats.as_ref().unwrap();
+        assert_eq!(stats.pixels, 36);
+        assert_eq!(stats.samples, 36 * 3);
+        // at least one primary ray per sample, each testing the bvh root
+        assert!(stats.rays_cast >= stats.samples);
+        assert!(stats.bvh_node_visits >= stats.rays_cast);
+        assert!(stats.wall_time.as_nanos() > 0);
+    }
+
+    #[test]
+    fn deferred_shading_matches_immediate_shading() {
+        let scene = Arc::new(cornell_scene(8, 2));
+        let mut renderer = Renderer::new();
+        renderer.seed = 3;
+        let immediate = render(&mut renderer, Arc::clone(&scene));
+        renderer.deferred = true;
+        let deferred = render(&mut renderer, scene);
+        assert!(immediate.iter().flatten().any(|c| c.length() > 0.0));
+        assert!(max_difference(&deferred, &immediate) < 1e-12);
+    }
+
+    #[test]
+    fn sequence_writes_one_numbered_png_per_frame() {
+        let dir = std::env::temp_dir().join("path_tracing_sequence");
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.to_str().unwrap();
+        let scene = cornell_scene(8, 1);
+        let mut renderer = Renderer::new();
+        renderer.fbo = Some(FrameBuffer::new(scene.width, scene.height));
+        // a camera sliding sideways a little further every frame
+        let mut pan = |scene: &mut Scene, frame: u32| scene.camera.position.x += 40.0 * frame as f64;
+        let paths = renderer.render_sequence(scene, 3, &mut pan, Some(1), dir, None).unwrap();
+
+        let names: Vec<_> = (1..=3).map(|i| format!("{}/frame_{:04}.png", dir, i)).collect();
+        assert!(paths == names);
+        let frames: Vec<_> = paths.iter().map(|path| std::fs::read(path).unwrap()).collect();
+        std::fs::remove_dir_all(dir).unwrap();
+        assert!(frames[0] != frames[1] && frames[1] != frames[2] && frames[0] != frames[2]);
+    }
+
+    #[test]
+    fn worker_count_rejects_zero_and_detects_the_default() {
+        assert!(Renderer::worker_count(Some(0)).is_err());
+        assert_eq!(Renderer::worker_count(Some(3)), Ok(3));
+        let detected = std::thread::available_parallelism().map_or(1, |n| n.get() as u32);
+        assert_eq!(Renderer::worker_count(None), Ok(detected));
+        // the pool adds the reducer on top of the workers
+        let pool = Renderer::create_pool(None).unwrap();
+        assert_eq!(pool.current_num_threads(), detected as usize + 1);
+        assert!(Renderer::create_pool(Some(0)).is_err());
+    }
+
+    #[test]
+    fn progress_only_grows_and_max_color_is_the_buffer_max() {
+        let scene = Arc::new(cornell_scene(8, 4));
+        let mut renderer = Renderer::new();
+        renderer.fbo = Some(FrameBuffer::new(8, 8));
+        // samples blended in so far, seen after every wave of a parallel render
+        let progress = std::cell::RefCell::new(vec![]);
+        let preview = |rt: &RenderTexture| {
+            let samples: u32 = (0..8).flat_map(|y| (0..8).map(move |x| (x, y)))
+                .map(|(x, y)| rt.get_variance(x, y).n_samples)
+                .sum();
+            progress.borrow_mut().push(samples);
+        };
+        renderer.render(scene, Some(4), Some(&preview), None).unwrap();
+        assert!(*progress.borrow() == [64, 128, 192, 256]);
+
+        let rt = renderer.fbo.as_mut().unwrap().get_render_target();
+        let expected = rt.get_color_attachment().iter()
+            .flatten()
+            .flat_map(|c| [c.x, c.y, c.z])
+            .fold(0.0, f64::max);
+        assert!(expected > 0.0);
+        assert_eq!(rt.max_color(), expected);
+    }
+
+    #[test]
+    fn guides_hold_the_first_hit_albedo_and_normal() {
+        let mut scene = Scene::new(4, 4, 40.0, Vector3f::zero(), EstimatorStrategy::MaximumBounces(2), 2);
+        // a wall filling the view, facing the camera down -z
+        let albedo = Vector3f::new(0.2, 0.4, 0.6);
+        let vertices = [Vector3f::new(-1e4, -1e4, 0.0), Vector3f::new(-1e4, 1e4, 0.0), Vector3f::new(1e4, 1e4, 0.0), Vector3f::new(1e4, -1e4, 0.0)];
+        let material = Arc::new(LitMaterial::new(&albedo, &Vector3f::zero()));
+        scene.add(Arc::new(Model::from_triangles("wall", &vertices, &[], &[0, 1, 2, 0, 2, 3], material)));
+        // a light behind the camera, out of view
+        let light = [Vector3f::new(0.0, 0.0, -900.0), Vector3f::new(10.0, 0.0, -900.0), Vector3f::new(0.0, 10.0, -900.0)];
+        let emissive = Arc::new(LitMaterial::new(&Vector3f::scalar(0.5), &Vector3f::scalar(10.0)));
+        scene.add(Arc::new(Model::from_triangles("light", &light, &[], &[0, 1, 2], emissive)));
+        scene.build_bvh();
+
+        let mut renderer = Renderer::new();
+        renderer.guide_buffers = true;
+        render(&mut renderer, Arc::new(scene));
+        let rt = renderer.fbo.as_mut().unwrap().get_render_target();
+        let guides = rt.get_guides().unwrap();
+        for (albedos, normals) in guides.albedo.iter().zip(guides.normal.iter()) {
+            for (pixel_albedo, pixel_normal) in albedos.iter().zip(normals.iter()) {
+                assert!(pixel_albedo.approx_eq(&albedo, 1e-12));
+                assert!(pixel_normal.approx_eq(&Vector3f::new(0.0, 0.0, -1.0), 1e-12));
+            }
+        }
+    }
+}
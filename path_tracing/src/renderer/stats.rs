@@ -0,0 +1,67 @@
+use core::fmt;
+use std::fmt::Display;
+use std::time::Duration;
+
+// counters of a finished (or cancelled) render, for comparing bvh builds
+// and other optimizations
+#[derive(Clone, Default)]
+pub struct RenderStats {
+    pub wall_time: Duration,
+    pub pixels: u64,
+    pub samples: u64,
+    // every ray traced through the scene bvh, primary, shadow and bounce
+    pub rays_cast: u64,
+    pub bvh_node_visits: u64,
+    // peak resident set size of the process, None where it is not available
+    pub peak_memory_bytes: Option<u64>,
+}
+
+impl RenderStats {
+    pub fn rays_per_pixel(&self) -> f64 {
+        if self.pixels == 0 {
+            return 0.0;
+        }
+        self.rays_cast as f64 / self.pixels as f64
+    }
+
+    pub fn rays_per_second(&self) -> f64 {
+        let seconds = self.wall_time.as_secs_f64();
+        if seconds <= 0.0 {
+            return 0.0;
+        }
+        self.rays_cast as f64 / seconds
+    }
+
+    // high water mark of the resident memory, read from procfs on linux
+    pub fn peak_memory() -> Option<u64> {
+        let status = std::fs::read_to_string("/proc/self/status").ok()?;
+        let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+        let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+        Some(kilobytes * 1024)
+    }
+}
+
+impl Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "[Renderer] wall time {:.3}s", self.wall_time.as_secs_f64())?;
+        writeln!(
+            f,
+            "[Renderer] {} rays over {} pixels, {:.2} rays per pixel, {:.0} rays/s",
+            self.rays_cast,
+            self.pixels,
+            self.rays_per_pixel(),
+            self.rays_per_second()
+        )?;
+        writeln!(
+            f,
+            "[Renderer] {} samples, {} bvh node visits ({:.2} per ray)",
+            self.samples,
+            self.bvh_node_visits,
+            self.bvh_node_visits as f64 / u64::max(self.rays_cast, 1) as f64
+        )?;
+        match self.peak_memory_bytes {
+            Some(bytes) => write!(f, "[Renderer] peak memory {:.1} MiB", bytes as f64 / (1024.0 * 1024.0)),
+            None => write!(f, "[Renderer] peak memory unknown"),
+        }
+    }
+}
@@ -1,16 +1,212 @@
 use std::{fs::File, io::Write};
+use image::{Rgb, RgbImage};
+use crate::color::Color;
 use crate::math::vector::Vector3f;
 
+use super::filter::PixelFilter;
+
 pub type Bitmap2D = Vec<Vec<Vector3f>>;
 
+// sample depth of the binary PPM output
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum PpmBitDepth {
+    #[default]
+    Eight,
+    // maxval 65535, every sample takes two bytes, most significant first
+    Sixteen,
+}
+
+// stylized effects on the tone mapped image, see `RenderTexture::post_process`
+#[derive(Clone, Copy, Default)]
+pub struct PostEffects {
+    // darkening from halfway out to the corners, 1 takes the corners to black
+    pub vignette: f64,
+    // outward shift of red and inward shift of blue at the corners, in pixels
+    pub chromatic_aberration: f64,
+}
+
+// Welford running mean / variance of the luminance of a pixel's samples
+#[derive(Clone)]
+pub struct PixelVariance {
+    pub n_samples: u32,
+    pub mean: f64,
+    pub m2: f64,
+}
+
+impl PixelVariance {
+    pub fn new() -> PixelVariance {
+        PixelVariance {
+            n_samples: 0,
+            mean: 0.0,
+            m2: 0.0,
+        }
+    }
+
+    pub fn push(&mut self, color: &Vector3f) {
+        let luminance = Color::luminance(color);
+        self.n_samples += 1;
+        let delta = luminance - self.mean;
+        self.mean += delta / self.n_samples as f64;
+        self.m2 += delta * (luminance - self.mean);
+    }
+
+    pub fn variance(&self) -> f64 {
+        if self.n_samples < 2 {
+            return f64::MAX;
+        }
+        self.m2 / (self.n_samples - 1) as f64
+    }
+
+    // variance of the pixel estimate itself, shrinks as samples are added
+    pub fn mean_variance(&self) -> f64 {
+        if self.n_samples < 2 {
+            return f64::MAX;
+        }
+        self.variance() / self.n_samples as f64
+    }
+}
+
+impl Default for PixelVariance {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub enum RenderTextureSetMode {
     Overwrite,
     Add,
-    // Blend
+    // lerp from the current value towards the color by the factor
+    Blend(f64),
+}
+
+// weighted color accumulation over a rectangular region of the image, each
+// worker splats into its own tile and tiles are summed afterwards, so the
+// reconstruction filter never has to lock the render texture
+pub struct FilterTile {
+    pub x0: u32,
+    pub y0: u32,
+    pub width: u32,
+    pub height: u32,
+    sum: Bitmap2D,
+    weight: Vec<Vec<f64>>,
+}
+
+impl FilterTile {
+    pub fn new(x0: u32, y0: u32, width: u32, height: u32) -> FilterTile {
+        FilterTile {
+            x0,
+            y0,
+            width,
+            height,
+            sum: vec![vec![Vector3f::zero(); width as usize]; height as usize],
+            weight: vec![vec![0.0; width as usize]; height as usize],
+        }
+    }
+
+    // accumulates a sample taken at film position (fx, fy) into every pixel
+    // of the tile under the filter footprint
+    pub fn splat(&mut self, fx: f64, fy: f64, color: &Vector3f, filter: &PixelFilter) {
+        if self.width == 0 || self.height == 0 {
+            return;
+        }
+        let radius = filter.radius();
+        if f64::floor(fx + radius) < self.x0 as f64 || f64::floor(fy + radius) < self.y0 as f64 {
+            return;
+        }
+        let x0 = f64::max(self.x0 as f64, f64::floor(fx - radius)) as u32;
+        let y0 = f64::max(self.y0 as f64, f64::floor(fy - radius)) as u32;
+        let x1 = f64::min((self.x0 + self.width - 1) as f64, f64::floor(fx + radius)) as u32;
+        let y1 = f64::min((self.y0 + self.height - 1) as f64, f64::floor(fy + radius)) as u32;
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let weight = filter.weight(x as f64 + 0.5 - fx, y as f64 + 0.5 - fy);
+                if weight <= 0.0 {
+                    continue;
+                }
+                let (tx, ty) = ((x - self.x0) as usize, (y - self.y0) as usize);
+                self.sum[ty][tx] += color * weight;
+                self.weight[ty][tx] += weight;
+            }
+        }
+    }
+
+    // adds the overlapping part of `other` into this tile
+    pub fn merge(&mut self, other: &FilterTile) {
+        let x0 = u32::max(self.x0, other.x0);
+        let y0 = u32::max(self.y0, other.y0);
+        let x1 = u32::min(self.x0 + self.width, other.x0 + other.width);
+        let y1 = u32::min(self.y0 + self.height, other.y0 + other.height);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let (sx, sy) = ((x - self.x0) as usize, (y - self.y0) as usize);
+                let (ox, oy) = ((x - other.x0) as usize, (y - other.y0) as usize);
+                self.sum[sy][sx] += other.sum[oy][ox];
+                self.weight[sy][sx] += other.weight[oy][ox];
+            }
+        }
+    }
+
+    pub fn get_sum(&self, x: u32, y: u32) -> &Vector3f {
+        &self.sum[(y - self.y0) as usize][(x - self.x0) as usize]
+    }
+
+    pub fn get_weight(&self, x: u32, y: u32) -> f64 {
+        self.weight[(y - self.y0) as usize][(x - self.x0) as usize]
+    }
+}
+
+// display encoding of the 8 and 16 bit output
+#[derive(Clone, Copy, PartialEq)]
+pub enum TransferFunction {
+    // a single power of 1 / gamma, 2.2 approximates sRGB
+    Gamma(f64),
+    // the exact piecewise sRGB curve, linear near black where a plain power
+    // is too steep
+    Srgb,
+}
+
+// first hit albedo and world space normal of every pixel, averaged over
+// its samples with a box filter whatever the color filter is. denoisers
+// take them as guides next to the noisy color
+pub struct GuideBuffers {
+    pub albedo: Bitmap2D,
+    pub normal: Bitmap2D,
+}
+
+impl GuideBuffers {
+    pub fn new(width: u32, height: u32) -> GuideBuffers {
+        GuideBuffers {
+            albedo: vec![vec![Vector3f::zero(); width as usize]; height as usize],
+            normal: vec![vec![Vector3f::zero(); width as usize]; height as usize],
+        }
+    }
+}
+
+// how RGB is stored next to alpha in RGBA output
+#[derive(Clone, Copy, PartialEq)]
+pub enum AlphaMode {
+    Straight,
+    Premultiplied,
 }
 
 pub struct RenderTexture {
     buffer: Bitmap2D,
+    // number of `Add` writes per pixel, used by `resolved` to average
+    sample_count: Vec<Vec<u32>>,
+    alpha: Vec<Vec<f64>>,
+    filter_accum: FilterTile,
+    variance: Vec<Vec<PixelVariance>>,
+    // only allocated once `enable_guides` is called
+    guides: Option<GuideBuffers>,
+    pub alpha_mode: AlphaMode,
+    // the buffer holds linear radiance and samples are accumulated on it as
+    // is. the 8 and 16 bit images are exposed, tone mapped, post processed
+    // and encoded by the transfer function, in that order, when written
+    pub transfer: TransferFunction,
+    // linear scale of the radiance, applied on output right before tone
+    // mapping so the accumulated samples and the EXR stay unscaled
+    pub exposure: f64,
+    pub post_effects: PostEffects,
     width: u32,
     height: u32
 }
@@ -20,10 +216,27 @@ impl RenderTexture {
         RenderTexture {
             width,
             height,
-            buffer: vec![vec![Vector3f::zero(); width as usize]; height as usize]
+            buffer: vec![vec![Vector3f::zero(); width as usize]; height as usize],
+            sample_count: vec![vec![0; width as usize]; height as usize],
+            alpha: vec![vec![0.0; width as usize]; height as usize],
+            filter_accum: FilterTile::new(0, 0, width, height),
+            alpha_mode: AlphaMode::Straight,
+            transfer: TransferFunction::Gamma(2.2),
+            exposure: 1.0,
+            post_effects: PostEffects::default(),
+            guides: None,
+            variance: vec![vec![PixelVariance::new(); width as usize]; height as usize]
         }
     }
 
+    pub fn add_variance_sample(&mut self, x: u32, y: u32, color: &Vector3f) {
+        self.variance[y as usize][x as usize].push(color);
+    }
+
+    pub fn get_variance(&self, x: u32, y: u32) -> &PixelVariance {
+        &self.variance[y as usize][x as usize]
+    }
+
     pub fn set(&mut self, x: u32, y: u32, color: Vector3f, mode: RenderTextureSetMode) {
         match mode {
             RenderTextureSetMode::Overwrite => {
@@ -31,9 +244,246 @@ impl RenderTexture {
             }
             RenderTextureSetMode::Add => {
                 self.buffer[y as usize][x as usize] += color;
+                self.sample_count[y as usize][x as usize] += 1;
+            }
+            RenderTextureSetMode::Blend(factor) => {
+                let pixel = &mut self.buffer[y as usize][x as usize];
+                *pixel = *pixel * (1.0 - factor) + color * factor;
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        let (width, height) = (self.width as usize, self.height as usize);
+        self.buffer = vec![vec![Vector3f::zero(); width]; height];
+        self.sample_count = vec![vec![0; width]; height];
+        self.alpha = vec![vec![0.0; width]; height];
+        self.filter_accum = FilterTile::new(0, 0, self.width, self.height);
+        self.variance = vec![vec![PixelVariance::new(); width]; height];
+        if self.guides.is_some() {
+            self.guides = Some(GuideBuffers::new(self.width, self.height));
+        }
+    }
+
+    pub fn enable_guides(&mut self) {
+        if self.guides.is_none() {
+            self.guides = Some(GuideBuffers::new(self.width, self.height));
+        }
+    }
+
+    pub fn get_guides(&self) -> Option<&GuideBuffers> {
+        self.guides.as_ref()
+    }
+
+    // blends the `n_samples`-th sample of pixel (x, y) into the running
+    // mean of its guides, ignored while guides are disabled
+    pub fn add_guide_sample(&mut self, x: u32, y: u32, albedo: &Vector3f, normal: &Vector3f, n_samples: u32) {
+        if let Some(guides) = self.guides.as_mut() {
+            let factor = 1.0 / u32::max(n_samples, 1) as f64;
+            let pixel_albedo = &mut guides.albedo[y as usize][x as usize];
+            *pixel_albedo = *pixel_albedo * (1.0 - factor) + albedo * factor;
+            let pixel_normal = &mut guides.normal[y as usize][x as usize];
+            *pixel_normal = *pixel_normal * (1.0 - factor) + normal * factor;
+        }
+    }
+
+    pub fn get_sample_count(&self, x: u32, y: u32) -> u32 {
+        self.sample_count[y as usize][x as usize]
+    }
+
+    // the buffer with every accumulated pixel divided by its own sample
+    // count, pixels never added to are returned unchanged
+    pub fn resolved(&self) -> Bitmap2D {
+        self.buffer
+            .iter()
+            .zip(self.sample_count.iter())
+            .map(|(row, counts)| {
+                row.iter()
+                    .zip(counts.iter())
+                    .map(|(color, &count)| if count > 0 { color / count as f64 } else { *color })
+                    .collect()
+            })
+            .collect()
+    }
+
+    // brightest channel in the buffer, found in one pass when asked for
+    // rather than tracked on every write
+    pub fn max_color(&self) -> f64 {
+        self.buffer.iter()
+            .flatten()
+            .fold(0.0, |max, pixel| f64::max(max, f64::max(pixel.x, f64::max(pixel.y, pixel.z))))
+    }
+
+    // alpha is the coverage of the pixel, 0 where only the background was seen
+    pub fn set_alpha(&mut self, x: u32, y: u32, alpha: f64, mode: RenderTextureSetMode) {
+        let pixel = &mut self.alpha[y as usize][x as usize];
+        match mode {
+            RenderTextureSetMode::Overwrite => *pixel = alpha,
+            RenderTextureSetMode::Add => *pixel += alpha,
+            RenderTextureSetMode::Blend(factor) => *pixel = *pixel * (1.0 - factor) + alpha * factor,
+        }
+    }
+
+    // accumulates a sample taken at film position (fx, fy) into every pixel
+    // under the filter footprint, `resolve_filter` normalizes into the buffer
+    pub fn splat(&mut self, fx: f64, fy: f64, color: &Vector3f, filter: &PixelFilter) {
+        self.filter_accum.splat(fx, fy, color, filter);
+    }
+
+    // a tile covering the given pixels plus the margin samples inside them
+    // can reach with `filter`, clamped to the texture
+    pub fn create_tile(&self, x0: u32, y0: u32, width: u32, height: u32, filter: &PixelFilter) -> FilterTile {
+        let margin = f64::ceil(filter.radius()) as u32;
+        let tx0 = x0.saturating_sub(margin);
+        let ty0 = y0.saturating_sub(margin);
+        let tx1 = u32::min(self.width, x0 + width + margin);
+        let ty1 = u32::min(self.height, y0 + height + margin);
+        FilterTile::new(tx0, ty0, tx1 - tx0, ty1 - ty0)
+    }
+
+    pub fn merge_tile(&mut self, tile: &FilterTile) {
+        self.filter_accum.merge(tile);
+    }
+
+    pub fn get_filter_weight(&self, x: u32, y: u32) -> f64 {
+        self.filter_accum.get_weight(x, y)
+    }
+
+    pub fn resolve_filter(&mut self) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let weight = self.filter_accum.get_weight(x, y);
+                if weight > 0.0 {
+                    self.buffer[y as usize][x as usize] = self.filter_accum.get_sum(x, y) / weight;
+                }
+            }
+        }
+    }
+
+    // glow around bright pixels: the part of every channel above `threshold`
+    // is blurred by a gaussian reaching `radius` pixels and added back
+    // scaled by `intensity`. works on the linear buffer of a finished render,
+    // before anything is tone mapped for output
+    pub fn apply_bloom(&mut self, threshold: f64, radius: u32, intensity: f64) {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let bright: Bitmap2D = self.buffer.iter()
+            .map(|row| row.iter()
+                .map(|c| Vector3f::new(
+                    f64::max(c.x - threshold, 0.0),
+                    f64::max(c.y - threshold, 0.0),
+                    f64::max(c.z - threshold, 0.0)))
+                .collect())
+            .collect();
+
+        // the kernel ends at 3 sigma, taps outside the texture are dropped
+        // and the rest renormalized
+        let sigma = f64::max(radius as f64 / 3.0, 1e-3);
+        let r = radius as i64;
+        let kernel: Vec<f64> = (-r..=r)
+            .map(|i| f64::exp(-(i * i) as f64 / (2.0 * sigma * sigma)))
+            .collect();
+        let blur = |src: &Bitmap2D, dx: i64, dy: i64| -> Bitmap2D {
+            (0..height as i64).map(|y| {
+                (0..width as i64).map(|x| {
+                    let mut sum = Vector3f::zero();
+                    let mut weight = 0.0;
+                    for (k, w) in (-r..=r).zip(kernel.iter()) {
+                        let (sx, sy) = (x + k * dx, y + k * dy);
+                        if sx >= 0 && sy >= 0 && sx < width as i64 && sy < height as i64 {
+                            sum += src[sy as usize][sx as usize] * *w;
+                            weight += w;
+                        }
+                    }
+                    sum / weight
+                }).collect()
+            }).collect()
+        };
+        let glow = blur(&blur(&bright, 1, 0), 0, 1);
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                self.buffer[y as usize][x as usize] += glow[y as usize][x as usize] * intensity;
+            }
+        }
+    }
+
+    // `post_effects` on a tone mapped image, run by the writers between tone
+    // mapping and the transfer function so a vignette darkens the displayed
+    // values and not the radiance
+    fn post_process(&self, display: &mut Bitmap2D) {
+        let effects = &self.post_effects;
+        let center_x = self.width as f64 * 0.5;
+        let center_y = self.height as f64 * 0.5;
+        let half_diagonal = f64::max(f64::sqrt(center_x * center_x + center_y * center_y), 1e-6);
+        // offset of pixel (x, y) from the center, 1 at the corners
+        let radial = |x: u32, y: u32| -> (f64, f64) {
+            ((x as f64 + 0.5 - center_x) / half_diagonal, (y as f64 + 0.5 - center_y) / half_diagonal)
+        };
+
+        if effects.chromatic_aberration != 0.0 {
+            let source = display.clone();
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    let (dx, dy) = radial(x, y);
+                    let shift_x = dx * effects.chromatic_aberration;
+                    let shift_y = dy * effects.chromatic_aberration;
+                    let fx = x as f64 + 0.5;
+                    let fy = y as f64 + 0.5;
+                    let pixel = &mut display[y as usize][x as usize];
+                    pixel.x = Self::sample_bilinear(&source, fx - shift_x, fy - shift_y).x;
+                    pixel.z = Self::sample_bilinear(&source, fx + shift_x, fy + shift_y).z;
+                }
+            }
+        }
+
+        if effects.vignette != 0.0 {
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    let (dx, dy) = radial(x, y);
+                    let t = f64::clamp((f64::sqrt(dx * dx + dy * dy) - 0.5) / 0.5, 0.0, 1.0);
+                    let factor = 1.0 - effects.vignette * t * t * (3.0 - 2.0 * t);
+                    let pixel = &mut display[y as usize][x as usize];
+                    *pixel = *pixel * f64::max(factor, 0.0);
+                }
+            }
+        }
+    }
+
+    // film position (fx, fy) interpolated between the four nearest pixel
+    // centers, clamped to the edges
+    fn sample_bilinear(bitmap: &Bitmap2D, fx: f64, fy: f64) -> Vector3f {
+        let height = bitmap.len();
+        let width = bitmap[0].len();
+        let x = f64::clamp(fx - 0.5, 0.0, (width - 1) as f64);
+        let y = f64::clamp(fy - 0.5, 0.0, (height - 1) as f64);
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = (usize::min(x0 + 1, width - 1), usize::min(y0 + 1, height - 1));
+        let (tx, ty) = (x - x0 as f64, y - y0 as f64);
+        let top = bitmap[y0][x0] * (1.0 - tx) + bitmap[y0][x1] * tx;
+        let bottom = bitmap[y1][x0] * (1.0 - tx) + bitmap[y1][x1] * tx;
+        top * (1.0 - ty) + bottom * ty
+    }
+
+    pub fn get_alpha(&self, x: u32, y: u32) -> f64 {
+        self.alpha[y as usize][x as usize]
+    }
+
+    // separates the color rendered over `background` into RGB and alpha
+    // according to `alpha_mode`
+    pub fn get_rgba(&self, x: u32, y: u32, background: &Vector3f) -> (Vector3f, f64) {
+        let alpha = f64::clamp(self.get_alpha(x, y), 0.0, 1.0);
+        let color = &self.buffer[y as usize][x as usize];
+        let premultiplied = color - &(background * (1.0 - alpha));
+        match self.alpha_mode {
+            AlphaMode::Premultiplied => (premultiplied, alpha),
+            AlphaMode::Straight => {
+                if alpha > 0.0 {
+                    (premultiplied / alpha, alpha)
+                } else {
+                    (Vector3f::zero(), alpha)
+                }
             }
         }
-        
     }
 
     pub fn get_color_attachment(&mut self) -> &mut Bitmap2D {
@@ -48,27 +498,463 @@ impl RenderTexture {
         self.height
     }
 
-    pub fn dump_to_file(&self, path: &str) -> std::io::Result<()> {
+    // writes a binary PPM (P6), every line of `comment` becomes a # comment
+    // in the header
+    pub fn dump_to_file(&self, path: &str, bit_depth: PpmBitDepth, comment: Option<&str>) -> std::io::Result<()> {
         let mut file = File::create(path)?;
-        let head = format!("P6\n{} {}\n255\n", self.width, self.height);
+        let mut head = String::from("P6\n");
+        for line in comment.unwrap_or_default().lines() {
+            head += &format!("# {}\n", line);
+        }
+        let max_value = match bit_depth {
+            PpmBitDepth::Eight => 255,
+            PpmBitDepth::Sixteen => 65535,
+        };
+        head += &format!("{} {}\n{}\n", self.width, self.height, max_value);
         file.write_all(head.as_bytes())?;
+        let display = self.display(&self.buffer);
         for y in 0..self.height {
             for x in 0..self.width {
-                let colors = &self.buffer[y as usize][x as usize];
-                let buf: [u8; 3] = [
-                    self.encode_color_component(colors.x),
-                    self.encode_color_component(colors.y),
-                    self.encode_color_component(colors.z)
+                let colors = &display[y as usize][x as usize];
+                match bit_depth {
+                    PpmBitDepth::Eight => {
+                        let buf: [u8; 3] = [
+                            self.encode_color_component(colors.x, x, y),
+                            self.encode_color_component(colors.y, x, y),
+                            self.encode_color_component(colors.z, x, y)
+                        ];
+                        file.write_all(&buf)?;
+                    }
+                    PpmBitDepth::Sixteen => {
+                        for c in [colors.x, colors.y, colors.z] {
+                            file.write_all(&self.encode_color_component_16(c).to_be_bytes())?;
+                        }
+                    }
+                }
+            }   
+        }
+        Ok(())
+    }
+
+    // writes an 8-bit PNG with the same tone curve and dithering as the PPM
+    pub fn dump_to_png(&self, path: &str) -> std::io::Result<()> {
+        let display = self.display(&self.buffer);
+        let image = RgbImage::from_fn(self.width, self.height, |x, y| {
+            let colors = &display[y as usize][x as usize];
+            Rgb([
+                self.encode_color_component(colors.x, x, y),
+                self.encode_color_component(colors.y, x, y),
+                self.encode_color_component(colors.z, x, y)
+            ])
+        });
+        image.save(path).map_err(std::io::Error::other)
+    }
+
+    // writes a PAM (P7) RGB_ALPHA image
+    pub fn dump_rgba_to_file(&self, path: &str, background: &Vector3f) -> std::io::Result<()> {
+        let mut file = File::create(path)?;
+        let head = format!(
+            "P7\nWIDTH {}\nHEIGHT {}\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n",
+            self.width, self.height
+        );
+        file.write_all(head.as_bytes())?;
+        let colors: Bitmap2D = (0..self.height)
+            .map(|y| (0..self.width).map(|x| self.get_rgba(x, y, background).0).collect())
+            .collect();
+        let display = self.display(&colors);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let colors = &display[y as usize][x as usize];
+                let alpha = self.get_rgba(x, y, background).1;
+                let buf: [u8; 4] = [
+                    self.encode_color_component(colors.x, x, y),
+                    self.encode_color_component(colors.y, x, y),
+                    self.encode_color_component(colors.z, x, y),
+                    (255.0 * alpha) as u8
                 ];
                 file.write_all(&buf)?;
-            }   
+            }
         }
         Ok(())
     }
 
-    fn encode_color_component(&self, c: f64) -> u8 {
-        let val = f64::clamp(c, 0.0, 1.0);
-        let result = 255.0 * f64::powf(val, 0.6);
-        result as u8
+    // writes the linear radiance as 32-bit float OpenEXR, without tone
+    // mapping or clamping
+    pub fn dump_to_exr(&self, path: &str) -> std::io::Result<()> {
+        Self::write_exr(path, &self.buffer)
+    }
+
+    // writes the albedo guide as 32-bit float OpenEXR
+    pub fn dump_albedo_to_file(&self, path: &str) -> std::io::Result<()> {
+        match &self.guides {
+            Some(guides) => Self::write_exr(path, &guides.albedo),
+            None => Err(std::io::Error::other("guide buffers not enabled")),
+        }
+    }
+
+    // writes the normal guide as 32-bit float OpenEXR, components stay in
+    // [-1, 1] as denoisers expect
+    pub fn dump_normal_to_file(&self, path: &str) -> std::io::Result<()> {
+        match &self.guides {
+            Some(guides) => Self::write_exr(path, &guides.normal),
+            None => Err(std::io::Error::other("guide buffers not enabled")),
+        }
+    }
+
+    fn write_exr(path: &str, bitmap: &Bitmap2D) -> std::io::Result<()> {
+        let height = bitmap.len();
+        let width = bitmap.first().map_or(0, |row| row.len());
+        exr::prelude::write_rgb_file(path, width, height, |x, y| {
+            let [r, g, b] = bitmap[y][x].to32();
+            (r, g, b)
+        })
+        .map_err(std::io::Error::other)
+    }
+
+    // the displayable [0, 1] image of the linear `source`: exposed, tone
+    // mapped and post processed, ready for the transfer function
+    fn display(&self, source: &Bitmap2D) -> Bitmap2D {
+        let map = |c: f64| Self::tone_map(self.expose(c));
+        let mut display: Bitmap2D = source.iter()
+            .map(|row| row.iter().map(|c| Vector3f::new(map(c.x), map(c.y), map(c.z))).collect())
+            .collect();
+        self.post_process(&mut display);
+        display
+    }
+
+    // quantizes a display value against a 4x4 Bayer threshold instead of
+    // truncating, which spreads the error of smooth gradients into a fine
+    // pattern rather than bands while keeping the average value
+    fn encode_color_component(&self, c: f64, x: u32, y: u32) -> u8 {
+        let result = 255.0 * self.encode_transfer(c);
+        f64::min(result + bayer_threshold(x, y), 255.0) as u8
+    }
+
+    // same curve as `encode_color_component`, 16 bits leave no visible
+    // banding to dither away
+    fn encode_color_component_16(&self, c: f64) -> u16 {
+        (65535.0 * self.encode_transfer(c)).round() as u16
+    }
+
+    fn expose(&self, c: f64) -> f64 {
+        c * self.exposure
+    }
+
+    // linear radiance to the displayable [0, 1] range
+    fn tone_map(c: f64) -> f64 {
+        f64::clamp(c, 0.0, 1.0)
+    }
+
+    // display encoding of a tone mapped value, the last step before
+    // quantizing
+    fn encode_transfer(&self, c: f64) -> f64 {
+        match self.transfer {
+            TransferFunction::Gamma(gamma) => f64::powf(c, 1.0 / gamma),
+            TransferFunction::Srgb => {
+                if c <= 0.0031308 {
+                    12.92 * c
+                } else {
+                    1.055 * f64::powf(c, 1.0 / 2.4) - 0.055
+                }
+            }
+        }
+    }
+}
+
+const BAYER_4X4: [[u8; 4]; 4] = [
+    [0, 8, 2, 10],
+    [12, 4, 14, 6],
+    [3, 11, 1, 9],
+    [15, 7, 13, 5],
+];
+
+// ordered dither threshold of pixel (x, y) in (0, 1)
+fn bayer_threshold(x: u32, y: u32) -> f64 {
+    (f64::from(BAYER_4X4[(y % 4) as usize][(x % 4) as usize]) + 0.5) / 16.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exposure_scales_the_tone_mapper_input() {
+        let mut texture = RenderTexture::new(1, 1);
+        texture.set(0, 0, Vector3f::scalar(0.2), RenderTextureSetMode::Overwrite);
+        texture.exposure = 2.0;
+        assert_eq!(texture.expose(0.2), 0.4);
+        assert_eq!(texture.display(&texture.buffer)[0][0].x, 0.4);
+        // the accumulated radiance itself stays unscaled
+        assert_eq!(texture.buffer[0][0].x, 0.2);
+    }
+
+    #[test]
+    fn vignette_darkens_the_corners_only() {
+        let mut texture = RenderTexture::new(5, 5);
+        for y in 0..5 {
+            for x in 0..5 {
+                texture.set(x, y, Vector3f::scalar(0.5), RenderTextureSetMode::Overwrite);
+            }
+        }
+        texture.post_effects.vignette = 0.8;
+        let display = texture.display(&texture.buffer);
+        assert_eq!(display[2][2].x, 0.5);
+        assert!(display[0][0].x < 0.5);
+        assert!(display[4][4].y < 0.5);
+    }
+
+    #[test]
+    fn post_effects_run_after_tone_mapping() {
+        // a bright corner is clamped to 1 first, so the vignette still
+        // darkens it instead of scaling radiance that gets clamped anyway
+        let mut texture = RenderTexture::new(5, 5);
+        texture.set(0, 0, Vector3f::scalar(10.0), RenderTextureSetMode::Overwrite);
+        texture.post_effects.vignette = 0.5;
+        let display = texture.display(&texture.buffer);
+        assert!(display[0][0].x < 1.0);
+    }
+
+    #[test]
+    fn straight_and_premultiplied_rgba_round_trip() {
+        let background = Vector3f::new(0.0, 0.0, 1.0);
+        let red = Vector3f::new(1.0, 0.0, 0.0);
+        let mut texture = RenderTexture::new(1, 1);
+        // half of the pixel covered by a red edge over a blue background
+        texture.set(0, 0, red * 0.5 + background * 0.5, RenderTextureSetMode::Overwrite);
+        texture.set_alpha(0, 0, 0.5, RenderTextureSetMode::Overwrite);
+
+        let (straight, alpha) = texture.get_rgba(0, 0, &background);
+        assert!(straight.approx_eq(&red, 1e-12) && alpha == 0.5);
+        texture.alpha_mode = AlphaMode::Premultiplied;
+        let (premultiplied, alpha) = texture.get_rgba(0, 0, &background);
+        assert!(premultiplied.approx_eq(&(red * 0.5), 1e-12) && alpha == 0.5);
+
+        // compositing either form over the background gives the pixel back
+        let over = |rgb: Vector3f| rgb + background * (1.0 - alpha);
+        assert!(over(straight * alpha).approx_eq(&texture.buffer[0][0], 1e-12));
+        assert!(over(premultiplied).approx_eq(&texture.buffer[0][0], 1e-12));
+    }
+
+    #[test]
+    fn centered_tent_sample_stays_in_its_pixel() {
+        let mut texture = RenderTexture::new(3, 3);
+        texture.splat(1.5, 1.5, &Vector3f::scalar(1.0), &PixelFilter::Tent(1.0));
+        assert_eq!(texture.get_filter_weight(1, 1), 1.0);
+        for (x, y) in [(0, 1), (2, 1), (1, 0), (1, 2), (0, 0), (2, 2)] {
+            assert_eq!(texture.get_filter_weight(x, y), 0.0);
+        }
+    }
+
+    #[test]
+    fn filter_weights_normalise() {
+        let color = Vector3f::new(0.2, 0.4, 0.8);
+        for filter in [PixelFilter::Tent(1.5), PixelFilter::Gaussian(2.0, 2.0)] {
+            let mut texture = RenderTexture::new(4, 4);
+            for i in 0..64 {
+                let (fx, fy) = ((i % 8) as f64 * 0.5 + 0.1, (i / 8) as f64 * 0.5 + 0.3);
+                texture.splat(fx, fy, &color, &filter);
+            }
+            texture.resolve_filter();
+            for pixel in texture.buffer.iter().flatten() {
+                assert!(pixel.approx_eq(&color, 1e-12));
+            }
+        }
+    }
+
+    #[test]
+    fn box_filter_averages_the_samples_of_a_pixel() {
+        let mut texture = RenderTexture::new(3, 3);
+        let samples = [(1.1, 1.2, 0.3), (1.5, 1.5, 0.6), (1.9, 1.8, 0.9)];
+        for (fx, fy, c) in samples {
+            texture.splat(fx, fy, &Vector3f::scalar(c), &PixelFilter::Box);
+        }
+        texture.resolve_filter();
+        assert!(f64::abs(texture.buffer[1][1].x - 0.6) < 1e-12);
+        assert_eq!(texture.get_filter_weight(0, 1), 0.0);
+        assert_eq!(texture.get_filter_weight(2, 2), 0.0);
+    }
+
+    #[test]
+    fn overlapping_tiles_merge_to_the_direct_splat() {
+        let filter = PixelFilter::Tent(1.5);
+        let mut direct = RenderTexture::new(8, 4);
+        let mut tiled = RenderTexture::new(8, 4);
+        // the left and right halves share a margin of two columns
+        let mut left = tiled.create_tile(0, 0, 4, 4, &filter);
+        let mut right = tiled.create_tile(4, 0, 4, 4, &filter);
+        assert!(left.x0 + left.width > right.x0);
+        for i in 0..32 {
+            let (fx, fy) = ((i % 8) as f64 + 0.25 * (i % 3) as f64, (i / 8) as f64 + 0.4);
+            let color = Vector3f::new(fx, fy, 1.0);
+            direct.splat(fx, fy, &color, &filter);
+            let tile = if fx < 4.0 { &mut left } else { &mut right };
+            tile.splat(fx, fy, &color, &filter);
+        }
+        tiled.merge_tile(&left);
+        tiled.merge_tile(&right);
+        for y in 0..4 {
+            for x in 0..8 {
+                assert!(f64::abs(tiled.get_filter_weight(x, y) - direct.get_filter_weight(x, y)) < 1e-12);
+                assert!(tiled.filter_accum.get_sum(x, y).approx_eq(direct.filter_accum.get_sum(x, y), 1e-12));
+            }
+        }
+    }
+
+    #[test]
+    fn each_texture_reports_its_own_max() {
+        let mut bright = RenderTexture::new(2, 2);
+        let mut dim = RenderTexture::new(2, 2);
+        bright.set(1, 0, Vector3f::new(0.1, 7.0, 0.2), RenderTextureSetMode::Overwrite);
+        dim.set(0, 1, Vector3f::new(0.3, 0.1, 0.5), RenderTextureSetMode::Overwrite);
+        assert_eq!(bright.max_color(), 7.0);
+        assert_eq!(dim.max_color(), 0.5);
+        assert_eq!(RenderTexture::new(2, 2).max_color(), 0.0);
+    }
+
+    #[test]
+    fn resolved_averages_uneven_sample_counts() {
+        let mut texture = RenderTexture::new(3, 1);
+        for c in [1.0, 2.0, 6.0] {
+            texture.set(0, 0, Vector3f::scalar(c), RenderTextureSetMode::Add);
+        }
+        texture.set(1, 0, Vector3f::scalar(0.5), RenderTextureSetMode::Add);
+        let resolved = texture.resolved();
+        assert_eq!((texture.get_sample_count(0, 0), texture.get_sample_count(1, 0)), (3, 1));
+        assert!(resolved[0][0].approx_eq(&Vector3f::scalar(3.0), 1e-12));
+        assert!(resolved[0][1].approx_eq(&Vector3f::scalar(0.5), 1e-12));
+        assert!(resolved[0][2] == Vector3f::zero());
+
+        // a cleared texture starts the next pass from scratch
+        texture.clear();
+        texture.set(0, 0, Vector3f::scalar(4.0), RenderTextureSetMode::Add);
+        assert_eq!(texture.get_sample_count(0, 0), 1);
+        assert_eq!(texture.get_sample_count(1, 0), 0);
+        assert!(texture.resolved()[0][0].approx_eq(&Vector3f::scalar(4.0), 1e-12));
+        assert!(texture.resolved()[0][1] == Vector3f::zero());
+    }
+
+    #[test]
+    fn exr_keeps_values_above_one() {
+        let mut texture = RenderTexture::new(2, 1);
+        texture.set(0, 0, Vector3f::new(4.5, 1.25, 0.0), RenderTextureSetMode::Overwrite);
+        texture.set(1, 0, Vector3f::new(0.25, 100.0, 2.0), RenderTextureSetMode::Overwrite);
+        texture.exposure = 0.5;
+        let path = std::env::temp_dir().join("path_tracing_exr_keeps_values_above_one.exr");
+        let path = path.to_str().unwrap();
+        texture.dump_to_exr(path).unwrap();
+
+        let image = exr::prelude::read_first_rgba_layer_from_file(
+            path,
+            |resolution, _| vec![vec![Vector3f::zero(); resolution.width()]; resolution.height()],
+            |pixels: &mut Bitmap2D, position, (r, g, b, _): (f32, f32, f32, f32)| {
+                pixels[position.y()][position.x()] = Vector3f::new(r as f64, g as f64, b as f64);
+            },
+        ).unwrap();
+        std::fs::remove_file(path).unwrap();
+        // neither exposed nor tone mapped
+        assert!(image.layer_data.channel_data.pixels == texture.buffer);
+    }
+
+    #[test]
+    fn dithering_keeps_the_mean_of_a_gradient() {
+        let mut texture = RenderTexture::new(1, 1);
+        texture.transfer = TransferFunction::Gamma(1.0);
+        // a ramp a few levels high over 64 pixels, bands when truncated.
+        // it steps once per 4x4 block so every block sees all 16 thresholds
+        let value = |x: u32| 0.4 + 0.02 * (x / 4) as f64 / 15.0;
+        let mut patterned = false;
+        for block in 0..16 {
+            let mut sum = 0.0;
+            let mut expected = 0.0;
+            let mut levels = vec![];
+            for y in 0..4 {
+                for x in block * 4..block * 4 + 4 {
+                    let level = texture.encode_color_component(value(x), x, y);
+                    levels.push(level);
+                    sum += f64::from(level);
+                    expected += 255.0 * value(x);
+                }
+            }
+            assert!(f64::abs(sum - expected) / 16.0 <= 1.0 / 32.0 + 1e-9);
+            patterned |= levels.iter().any(|level| *level != levels[0]);
+        }
+        assert!(patterned);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn sixteen_bit_ppm_has_comments_and_big_endian_samples() {
+        let mut texture = RenderTexture::new(2, 1);
+        texture.transfer = TransferFunction::Gamma(1.0);
+        texture.set(0, 0, Vector3f::new(1.0, 0.0, 0.5), RenderTextureSetMode::Overwrite);
+        texture.set(1, 0, Vector3f::new(0.25, 2.0, 0.0), RenderTextureSetMode::Overwrite);
+        let path = std::env::temp_dir().join("path_tracing_sixteen_bit.ppm");
+        let path = path.to_str().unwrap();
+        texture.dump_to_file(path, PpmBitDepth::Sixteen, Some("spp 4\nseed 7")).unwrap();
+        let bytes = std::fs::read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let header = "P6\n# spp 4\n# seed 7\n2 1\n65535\n";
+        assert_eq!(&bytes[..header.len()], header.as_bytes());
+        let samples: Vec<u16> = bytes[header.len()..]
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
+        // 0.5 * 65535 rounds up, values above 1 are clamped
+        assert_eq!(samples, [65535, 0, 32768, 16384, 65535, 0]);
+    }
+
+    #[test]
+    fn bloom_leaves_dim_pixels_alone() {
+        let mut texture = RenderTexture::new(16, 16);
+        for y in 0..16 {
+            for x in 0..16 {
+                texture.set(x, y, Vector3f::new(0.1 * (x % 8) as f64, 0.9, 0.05 * y as f64), RenderTextureSetMode::Overwrite);
+            }
+        }
+        // nothing reaches the threshold, so there is nothing to spread
+        let dim = texture.buffer.clone();
+        texture.apply_bloom(1.0, 3, 0.5);
+        assert!(texture.buffer == dim);
+
+        // one bright pixel only glows within the blur radius
+        texture.set(4, 4, Vector3f::scalar(9.0), RenderTextureSetMode::Overwrite);
+        let before = texture.buffer.clone();
+        texture.apply_bloom(1.0, 3, 0.5);
+        for (y, (row, row_before)) in texture.buffer.iter().zip(before.iter()).enumerate() {
+            for (x, (c, c_before)) in row.iter().zip(row_before.iter()).enumerate() {
+                let near = x.abs_diff(4) <= 3 && y.abs_diff(4) <= 3;
+                assert_eq!(c != c_before, near);
+            }
+        }
+    }
+
+    #[test]
+    fn gamma_one_leaves_values_alone() {
+        let mut texture = RenderTexture::new(1, 1);
+        texture.transfer = TransferFunction::Gamma(1.0);
+        for c in [0.0, 0.01, 0.25, 0.5, 0.9, 1.0] {
+            assert_eq!(texture.encode_transfer(c), c);
+        }
+        assert_eq!(texture.encode_color_component_16(0.5), 32768);
+        // the default curve brightens the midtones
+        texture.transfer = TransferFunction::Gamma(2.2);
+        assert!(f64::abs(texture.encode_transfer(0.5) - 0.729740) < 1e-6);
+    }
+
+    #[test]
+    fn srgb_matches_reference_values_around_the_breakpoint() {
+        let mut texture = RenderTexture::new(1, 1);
+        texture.transfer = TransferFunction::Srgb;
+        let reference = [(0.0, 0.0), (0.001, 0.01292), (0.0031308, 0.0404500), (0.18, 0.4613561), (0.5, 0.7353570), (1.0, 1.0)];
+        for (linear, encoded) in reference {
+            assert!(f64::abs(texture.encode_transfer(linear) - encoded) < 1e-6);
+        }
+        // the two segments meet at the breakpoint
+        let below = texture.encode_transfer(0.0031308);
+        let above = texture.encode_transfer(0.0031308 + 1e-12);
+        assert!(f64::abs(above - below) < 1e-7);
+        // near black the curve is linear where a plain power is far steeper
+        texture.transfer = TransferFunction::Gamma(2.4);
+        assert!(texture.encode_transfer(0.001) > 0.05);
+    }
+}
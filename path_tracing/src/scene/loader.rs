@@ -0,0 +1,199 @@
+use std::sync::Arc;
+
+use crate::{material::material::{LitMaterial, Material}, math::{vector::Vector3f, Math}, mesh::model::Model};
+
+use super::scene::{Camera, EstimatorStrategy, Projection, Scene};
+
+// column-major, the layout glTF stores node matrices in
+type Matrix = [[f64; 4]; 4];
+
+const IDENTITY: Matrix = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+fn mul(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut m = [[0.0; 4]; 4];
+    for (col, column) in m.iter_mut().enumerate() {
+        for (row, value) in column.iter_mut().enumerate() {
+            *value = (0..4).map(|k| a[k][row] * b[col][k]).sum();
+        }
+    }
+    m
+}
+
+// `w` is 1 for points and 0 for directions
+fn transform(m: &Matrix, v: &Vector3f, w: f64) -> Vector3f {
+    Vector3f::new(
+        m[0][0] * v.x + m[1][0] * v.y + m[2][0] * v.z + m[3][0] * w,
+        m[0][1] * v.x + m[1][1] * v.y + m[2][1] * v.z + m[3][1] * w,
+        m[0][2] * v.x + m[1][2] * v.y + m[2][2] * v.z + m[3][2] * w,
+    )
+}
+
+fn determinant3(m: &Matrix) -> f64 {
+    m[0][0] * (m[1][1] * m[2][2] - m[2][1] * m[1][2])
+        - m[1][0] * (m[0][1] * m[2][2] - m[2][1] * m[0][2])
+        + m[2][0] * (m[0][1] * m[1][2] - m[1][1] * m[0][2])
+}
+
+impl Scene {
+    // loads the default scene of a glTF file, every mesh primitive becomes a
+    // model in world space and the first camera found replaces the default one.
+    // only the base color and emission of a material are used, metallic and
+    // roughness have no counterpart in LitMaterial yet
+    pub fn from_gltf(path: &str,
+                     width: u32,
+                     height: u32,
+                     camera_background_color: Vector3f,
+                     estimator_strategy: EstimatorStrategy,
+                     sample_per_pixel: u32) -> Result<Scene, String> {
+        let (document, buffers, _) = gltf::import(path)
+            .map_err(|err| format!("cannot load glTF file {}: {}", path, err))?;
+        let mut scene = Scene::new(width, height, 40.0, camera_background_color, estimator_strategy, sample_per_pixel);
+
+        let materials: Vec<Arc<dyn Material>> = document.materials()
+            .map(|material| Self::gltf_material(&material))
+            .collect();
+        let default_material: Arc<dyn Material> = Arc::new(LitMaterial::new(&Vector3f::new(1.0, 1.0, 1.0), &Vector3f::zero()));
+
+        let gltf_scene = document.default_scene()
+            .or_else(|| document.scenes().next())
+            .ok_or_else(|| format!("glTF file {} has no scene", path))?;
+        let mut camera_found = false;
+        let mut stack: Vec<(gltf::Node, Matrix)> = gltf_scene.nodes()
+            .map(|node| (node, IDENTITY))
+            .collect();
+        while let Some((node, parent)) = stack.pop() {
+            let world = mul(&parent, &node.transform().matrix().map(|column| column.map(f64::from)));
+            for child in node.children() {
+                stack.push((child, world));
+            }
+
+            if let (Some(camera), false) = (node.camera(), camera_found) {
+                camera_found = true;
+                scene.projection = match camera.projection() {
+                    gltf::camera::Projection::Perspective(perspective) => Projection::Perspective {
+                        fov: Math::degree(f64::from(perspective.yfov()))
+                    },
+                    gltf::camera::Projection::Orthographic(orthographic) => Projection::Orthographic {
+                        height: 2.0 * f64::from(orthographic.ymag())
+                    },
+                };
+                // glTF cameras look down their local -z
+                scene.camera = Camera {
+                    position: transform(&world, &Vector3f::zero(), 1.0),
+                    right: transform(&world, &Vector3f::new(1.0, 0.0, 0.0), 0.0).normalize(),
+                    up: transform(&world, &Vector3f::new(0.0, 1.0, 0.0), 0.0).normalize(),
+                    forward: transform(&world, &Vector3f::new(0.0, 0.0, -1.0), 0.0).normalize(),
+                };
+            }
+
+            let Some(mesh) = node.mesh() else {
+                continue;
+            };
+            // mirroring transforms flip the triangles inside out
+            let flip_winding = determinant3(&world) < 0.0;
+            for (i, primitive) in mesh.primitives().enumerate() {
+                let name = format!("{}#{}/{}", path, mesh.name().unwrap_or("mesh"), i);
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    return Err(format!("{}: only triangle primitives are supported", name));
+                }
+                let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+                let vertices: Vec<Vector3f> = reader.read_positions()
+                    .ok_or_else(|| format!("{}: primitive has no positions", name))?
+                    .map(|p| transform(&world, &Vector3f::new(f64::from(p[0]), f64::from(p[1]), f64::from(p[2])), 1.0))
+                    .collect();
+                let uvs: Vec<Vector3f> = match reader.read_tex_coords(0) {
+                    Some(uvs) => uvs.into_f32()
+                        .map(|uv| Vector3f::new(f64::from(uv[0]), f64::from(uv[1]), 0.0))
+                        .collect(),
+                    None => vec![],
+                };
+                let mut indices: Vec<u32> = match reader.read_indices() {
+                    Some(indices) => indices.into_u32().collect(),
+                    None => (0..vertices.len() as u32).collect(),
+                };
+                if !indices.len().is_multiple_of(3) || indices.iter().any(|&index| index as usize >= vertices.len()) {
+                    return Err(format!("{}: invalid triangle indices", name));
+                }
+                if flip_winding {
+                    for triangle in indices.chunks_exact_mut(3) {
+                        triangle.swap(1, 2);
+                    }
+                }
+                let material = match primitive.material().index() {
+                    Some(index) => Arc::clone(&materials[index]),
+                    None => Arc::clone(&default_material),
+                };
+                scene.add(Arc::new(Model::from_triangles(&name, &vertices, &uvs, &indices, material)));
+            }
+        }
+        Ok(scene)
+    }
+
+    fn gltf_material(material: &gltf::Material) -> Arc<dyn Material> {
+        let base_color = material.pbr_metallic_roughness().base_color_factor();
+        let emissive = material.emissive_factor();
+        let strength = f64::from(material.emissive_strength().unwrap_or(1.0));
+        let mut lit = LitMaterial::new(
+            &Vector3f::new(f64::from(base_color[0]), f64::from(base_color[1]), f64::from(base_color[2])),
+            &(Vector3f::new(f64::from(emissive[0]), f64::from(emissive[1]), f64::from(emissive[2])) * strength),
+        );
+        lit.double_sided = material.double_sided();
+        lit.two_sided = material.double_sided();
+        Arc::new(lit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::domain::Ray;
+
+    // one double sided triangle at z = 2 with a red base color, the
+    // positions are embedded as a base64 buffer
+    const TRIANGLE_GLTF: &str = r#"{
+        "asset": { "version": "2.0" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [{ "mesh": 0 }],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "material": 0 }] }],
+        "materials": [{
+            "pbrMetallicRoughness": { "baseColorFactor": [0.8, 0.1, 0.2, 1.0] },
+            "doubleSided": true
+        }],
+        "accessors": [{
+            "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+            "min": [-1.0, -1.0, 2.0], "max": [1.0, 1.0, 2.0]
+        }],
+        "bufferViews": [{ "buffer": 0, "byteLength": 36 }],
+        "buffers": [{
+            "byteLength": 36,
+            "uri": "data:application/octet-stream;base64,AACAvwAAgL8AAABAAACAPwAAgL8AAABAAAAAAAAAgD8AAABA"
+        }]
+    }"#;
+
+    #[test]
+    fn embedded_triangle_loads_with_its_base_color() {
+        let path = std::env::temp_dir().join("path_tracing_embedded_triangle.gltf");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, TRIANGLE_GLTF).unwrap();
+        let scene = Scene::from_gltf(path, 4, 4, Vector3f::zero(), EstimatorStrategy::MaximumBounces(1), 1);
+        std::fs::remove_file(path).unwrap();
+        let mut scene = scene.unwrap();
+
+        assert_eq!(scene.models.len(), 1);
+        assert_eq!(scene.models[0].triangles.len(), 1);
+        let base_color = Vector3f::new(0.8, 0.1, 0.2);
+        assert!(scene.models[0].material.get_albedo().approx_eq(&base_color, 1e-6));
+
+        scene.build_bvh();
+        let ray = Ray::new(&Vector3f::new(0.0, -0.2, 0.0), &Vector3f::new(0.0, 0.0, 1.0), 0.0);
+        let inter = scene.first_hit(&ray).unwrap();
+        assert!(inter.hit);
+        assert!(f64::abs(inter.distance - 2.0) < 1e-6);
+    }
+}
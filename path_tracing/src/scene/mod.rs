@@ -1,3 +1,4 @@
 #[allow(clippy::module_inception)]
 pub mod scene;
+pub mod loader;
 pub use scene::*;
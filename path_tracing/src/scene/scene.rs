@@ -1,7 +1,31 @@
 use core::panic;
+use std::cell::Cell;
 use std::sync::Arc;
 
-use crate::{math::{vector::Vector3f, Math}, mesh::{model::Model, object::Object}, bvh::bvh::BVH, domain::domain::{Ray, Intersection}};
+use crate::{color::Color, material::preview::preview_shade, math::{vector::Vector3f, Math, Sampler}, mesh::{instance::Instance, model::Model, object::Object, sphere::Sphere}, bvh::bvh::BVH, domain::domain::{Ray, Intersection}};
+
+// grazing samples below this density would blow up the indirect estimate
+const PDF_EPSILON: f64 = 1e-6;
+
+// sampler dimensions of a path, the camera takes the first ones and every
+// bounce after it a block of its own, so the same decision draws from the
+// same dimension in every sample of a pixel
+pub const DIMENSION_JITTER: usize = 0;
+pub const DIMENSION_TIME: usize = 2;
+const DIMENSION_WAVELENGTH: usize = 3;
+const CAMERA_DIMENSIONS: usize = 4;
+// offsets inside a bounce block, a light sample takes the light, a leaf of
+// its bvh and a point on that leaf
+const BOUNCE_SPECULAR: usize = 0;
+const BOUNCE_LIGHT: usize = 1;
+const BOUNCE_ROULETTE: usize = 5;
+const BOUNCE_BSDF: usize = 6;
+const BOUNCE_DIMENSIONS: usize = 8;
+
+thread_local! {
+    // rays traced through the scene bvh on this thread
+    static RAYS_CAST: Cell<u64> = const { Cell::new(0) };
+}
 
 #[derive(PartialEq)]
 pub enum EstimatorStrategy {
@@ -10,10 +34,10 @@ pub enum EstimatorStrategy {
 }
 
 impl EstimatorStrategy {
-    fn determine(&self, depth: usize) -> bool {
+    fn determine(&self, depth: usize, sampler: &mut dyn Sampler) -> bool {
         match self {
             EstimatorStrategy::RussianRoulette(probability) => {
-                Math::sample_uniform_distribution(0.0, 1.0) < *probability
+                sampler.next_1d() < *probability
             },
             EstimatorStrategy::MaximumBounces(max_depth) => depth < *max_depth,
         }
@@ -27,14 +51,138 @@ impl EstimatorStrategy {
     }
 }
 
+// how `shade` gathers the light arriving at a diffuse hit
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum LightingMode {
+    // next event estimation, a light sample plus a bsdf bounce that ignores
+    // the emitters it hits
+    #[default]
+    Nee,
+    // bsdf bounces only, emitters count when a bounce hits them. the
+    // unbiased reference for nee, but very noisy with small lights
+    Naive,
+    // the light sample alone, direct lighting without any bounce
+    NeeNoIndirect,
+}
+
+// how `sample_light` picks the emitter to sample
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum LightSamplingStrategy {
+    // proportionally to the emitting area
+    #[default]
+    ByArea,
+    // proportionally to area * luminance of the emission, small bright
+    // lights get more samples than large dim ones
+    ByPower,
+}
+
+// arbitrary output variables for debugging shading and geometry
+#[derive(Clone, Copy, PartialEq)]
+pub enum Aov {
+    // world space normal mapped from [-1, 1] to [0, 1]
+    Normal,
+    // distance from the camera along the ray, unnormalized
+    Depth,
+    Albedo,
+    // bvh nodes tested by the primary ray, from blue (none) to red
+    // (BVH_HEAT_MAX or more)
+    BvhHeat,
+}
+
+// node visits shown as the hottest color of the bvh heatmap
+const BVH_HEAT_MAX: f64 = 128.0;
+
+pub enum Projection {
+    // vertical field of view in degrees
+    Perspective { fov: f64 },
+    // height of the view volume in world units
+    Orthographic { height: f64 },
+}
+
+impl Projection {
+    // origin offset and direction in camera space of the ray through the
+    // film position (x, y) in [-1, 1], looking down +z
+    pub fn camera_ray(&self, x: f64, y: f64, aspect: f64) -> (Vector3f, Vector3f) {
+        match self {
+            Projection::Perspective { fov } => {
+                let scale = f64::tan(Math::radian(fov * 0.5));
+                (Vector3f::zero(), Vector3f::new(x * aspect * scale, y * scale, 1.0).normalize())
+            }
+            Projection::Orthographic { height } => {
+                let half_height = height * 0.5;
+                (Vector3f::new(x * aspect * half_height, y * half_height, 0.0), Vector3f::new(0.0, 0.0, 1.0))
+            }
+        }
+    }
+}
+
+// position and orthonormal axes of the camera in world space, rays are
+// built looking down +z in camera space and then rotated into the world
+#[derive(Clone, Copy)]
+pub struct Camera {
+    pub position: Vector3f,
+    pub right: Vector3f,
+    pub up: Vector3f,
+    pub forward: Vector3f,
+}
+
+impl Camera {
+    pub fn to_world(&self, v: &Vector3f) -> Vector3f {
+        self.right * v.x + self.up * v.y + self.forward * v.z
+    }
+
+    // blends the position and the axes towards `other`, the axes are only
+    // renormalized which is close enough to a rotation for the small moves
+    // within one shutter interval
+    pub fn lerp(&self, other: &Camera, t: f64) -> Camera {
+        let blend = |a: &Vector3f, b: &Vector3f| a + &((b - a) * t);
+        Camera {
+            position: blend(&self.position, &other.position),
+            right: blend(&self.right, &other.right).normalize(),
+            up: blend(&self.up, &other.up).normalize(),
+            forward: blend(&self.forward, &other.forward).normalize(),
+        }
+    }
+}
+
+impl Default for Camera {
+    // the cornell box view, with x pointing to the left
+    fn default() -> Self {
+        Camera {
+            position: Vector3f::new(278.0, 273.0, -800.0),
+            right: Vector3f::new(-1.0, 0.0, 0.0),
+            up: Vector3f::new(0.0, 1.0, 0.0),
+            forward: Vector3f::new(0.0, 0.0, 1.0),
+        }
+    }
+}
+
 pub struct Scene {
     pub width: u32,
     pub height: u32,
-    pub fov: f64,
+    pub camera: Camera,
+    // camera at the end of the shutter interval, primary rays pick a random
+    // time in between for motion blur. None keeps the camera still
+    pub camera_end: Option<Camera>,
+    pub projection: Projection,
     pub camera_background_color: Vector3f,
     pub estimator_strategy: EstimatorStrategy,
     pub sample_per_pixel: u32,
-    models: Vec<Arc<Model>>,
+    // trace every path at a single sampled wavelength and weight its radiance
+    // by the color matching functions, needed for dispersion
+    pub spectral: bool,
+    // estimator of `shade`, switch to Naive to check nee for bias
+    pub lighting_mode: LightingMode,
+    pub light_sampling: LightSamplingStrategy,
+    // secondary rays start this far off the surface along the geometric
+    // normal so they cannot hit the surface they leave
+    pub ray_epsilon: f64,
+    pub(super) models: Vec<Arc<Model>>,
+    spheres: Vec<Arc<Sphere>>,
+    instances: Vec<Arc<Instance>>,
+    // emissive objects with the running sums of their areas and powers,
+    // built with the bvh
+    lights: Vec<(Arc<dyn Object>, f64, f64)>,
     bvh: Option<BVH>
 }
 
@@ -48,85 +196,259 @@ impl Scene {
         Scene { 
             width, 
             height, 
-            fov, 
+            camera: Camera::default(),
+            camera_end: None,
+            projection: Projection::Perspective { fov }, 
             camera_background_color, 
             estimator_strategy,
             sample_per_pixel,
+            spectral: false,
+            lighting_mode: LightingMode::Nee,
+            light_sampling: LightSamplingStrategy::ByArea,
+            ray_epsilon: 1e-4,
             models: vec![],
+            spheres: vec![],
+            instances: vec![],
+            lights: vec![],
             bvh: None
         }
     }
 
+    // camera at `time` in [0, 1] of the shutter interval
+    pub fn camera_at(&self, time: f64) -> Camera {
+        match &self.camera_end {
+            Some(end) => self.camera.lerp(end, time),
+            None => self.camera,
+        }
+    }
+
     pub fn add(&mut self, model: Arc<Model>) {
         self.models.push(model);
     }
 
+    pub fn add_sphere(&mut self, sphere: Arc<Sphere>) {
+        self.spheres.push(sphere);
+    }
+
+    // the model of an instance is usually shared with other instances and
+    // is not added to the scene itself
+    pub fn add_instance(&mut self, instance: Arc<Instance>) {
+        self.instances.push(instance);
+    }
+
     pub fn build_bvh(&mut self) {
         println!("[Scene] Generating BVH...");
         let models = self.models.iter()
             .map(|model| model.clone() as Arc<dyn Object>)
+            .chain(self.spheres.iter().map(|sphere| sphere.clone() as Arc<dyn Object>))
+            .chain(self.instances.iter().map(|instance| instance.clone() as Arc<dyn Object>))
             .collect();
         let mut bvh = BVH::new(models);
         bvh.build();
         self.bvh = Some(bvh);
+        self.build_light_table();
+    }
+
+    // a single bvh over the triangles of every model instead of a bvh over
+    // the models, rays no longer descend through a second tree per model.
+    // the models keep their own bvh for light sampling. instances stay
+    // single leaves, flattening them would copy the triangles they share
+    pub fn build_flat_bvh(&mut self) {
+        println!("[Scene] Generating flat BVH...");
+        let primitives = self.models.iter()
+            .flat_map(|model| model.triangles.iter().map(|triangle| triangle.clone() as Arc<dyn Object>))
+            .chain(self.spheres.iter().map(|sphere| sphere.clone() as Arc<dyn Object>))
+            .chain(self.instances.iter().map(|instance| instance.clone() as Arc<dyn Object>))
+            .collect();
+        let mut bvh = BVH::new(primitives);
+        bvh.build();
+        self.bvh = Some(bvh);
+        self.build_light_table();
+    }
+
+    fn build_light_table(&mut self) {
+        let mut area_sum = 0.0;
+        let mut power_sum = 0.0;
+        self.lights = self.emissive_objects()
+            .map(|(obj, emission)| {
+                area_sum += obj.get_area();
+                power_sum += obj.get_area() * Color::luminance(&emission);
+                (obj, area_sum, power_sum)
+            })
+            .collect();
+    }
+
+    // emitters that light sampling can pick, known once the bvh is built
+    pub fn light_count(&self) -> usize {
+        self.lights.len()
+    }
+
+    pub fn rays_cast() -> u64 {
+        RAYS_CAST.with(|rays| rays.get())
+    }
+
+    fn intersect(&self, ray: &Ray) -> Intersection {
+        RAYS_CAST.with(|rays| rays.set(rays.get() + 1));
+        self.bvh.as_ref().unwrap().intersect(ray)
+    }
+
+    // whether anything blocks `ray` within its range, cheaper than
+    // `intersect` as the first occluder found is enough
+    fn occluded(&self, ray: &Ray) -> bool {
+        RAYS_CAST.with(|rays| rays.set(rays.get() + 1));
+        self.bvh.as_ref().unwrap().intersect_any(ray)
+    }
+
+    // every random decision along the path is drawn from `sampler`
+    pub fn cast_ray(&self, ray: &Ray, sampler: &mut dyn Sampler) -> Result<(Vector3f, bool), &'static str> {
+        let inter = self.first_hit(ray)?;
+        Ok(self.shade_first_hit(ray, &inter, sampler))
     }
 
-    pub fn cast_ray(&self, ray: &Ray) -> Result<(Vector3f, bool), &'static str> {
+    // closest intersection of a camera ray, split from `shade_first_hit` so
+    // the renderer can trace all primary rays before shading any of them
+    pub fn first_hit(&self, ray: &Ray) -> Result<Intersection, &'static str> {
         if self.bvh.is_none() {
             return Err("bvh not generated");
         }
-        let inter = self.bvh.as_ref().unwrap().intersect(ray);
+        Ok(self.intersect(ray))
+    }
+
+    // radiance towards the camera from the first hit of `ray`, the bounces
+    // after it are traced recursively
+    pub fn shade_first_hit(&self, ray: &Ray, inter: &Intersection, sampler: &mut dyn Sampler) -> (Vector3f, bool) {
         if !inter.hit {
-            return Ok((self.camera_background_color.clone(), false));
+            return (self.camera_background_color, false);
         }
         let re_dir = -&ray.direction;
-        Ok((self.shade(&inter, &re_dir, 0), true))
+        let radiance = if self.spectral {
+            sampler.set_dimension(DIMENSION_WAVELENGTH);
+            let (lambda, pdf) = Color::sample_wavelength(sampler.next_1d());
+            let radiance = self.shade(inter, &re_dir, 0, Some(lambda), sampler);
+            &radiance * &Color::wavelength_to_rgb(lambda, 1.0) / pdf
+        } else {
+            self.shade(inter, &re_dir, 0, None, sampler)
+        };
+        (radiance, true)
+    }
+
+    // shades the first hit with the built-in studio lighting, ignoring the
+    // lights in the scene
+    pub fn cast_preview_ray(&self, ray: &Ray) -> Result<(Vector3f, bool), &'static str> {
+        if self.bvh.is_none() {
+            return Err("bvh not generated");
+        }
+        let inter = self.intersect(ray);
+        if !inter.hit {
+            return Ok((self.camera_background_color, false));
+        }
+        let material = inter.material.as_ref().unwrap();
+        let view = -&ray.direction;
+        Ok((preview_shade(material.as_ref(), &inter.normal, &view), true))
+    }
+
+    pub fn cast_aov_ray(&self, ray: &Ray, aov: Aov) -> Result<(Vector3f, bool), &'static str> {
+        if self.bvh.is_none() {
+            return Err("bvh not generated");
+        }
+        let visits = BVH::node_visits();
+        let inter = self.intersect(ray);
+        let color = match aov {
+            Aov::BvhHeat => Self::heatmap(f64::min((BVH::node_visits() - visits) as f64 / BVH_HEAT_MAX, 1.0)),
+            _ if !inter.hit => Vector3f::zero(),
+            Aov::Normal => &inter.normal * 0.5 + Vector3f::new(0.5, 0.5, 0.5),
+            Aov::Depth => Vector3f::new(inter.distance, inter.distance, inter.distance),
+            Aov::Albedo => inter.material.as_ref().unwrap().get_albedo(),
+        };
+        Ok((color, inter.hit))
+    }
+
+    // albedo and world space shading normal of a first hit, the guide
+    // images of a denoiser. misses give zero for both
+    pub fn guides_at(inter: &Intersection) -> (Vector3f, Vector3f) {
+        let material = match &inter.material {
+            Some(material) if inter.hit => material,
+            _ => return (Vector3f::zero(), Vector3f::zero()),
+        };
+        let albedo = match &inter.color {
+            Some(color) => material.albedo_with_color(color),
+            None => material.get_albedo(),
+        };
+        (albedo, material.shading_normal(&inter.tcoords, &inter.normal, &inter.tangent))
+    }
+
+    // blue through green to red for t in [0, 1]
+    fn heatmap(t: f64) -> Vector3f {
+        let r = f64::clamp(2.0 * t - 1.0, 0.0, 1.0);
+        let b = f64::clamp(1.0 - 2.0 * t, 0.0, 1.0);
+        Vector3f::new(r, 1.0 - r - b, b)
     }
 
-    fn shade(&self, hit: &Intersection, wo: &Vector3f, depth: usize) -> Vector3f {
+    fn shade(&self, hit: &Intersection, wo: &Vector3f, depth: usize, lambda: Option<f64>, sampler: &mut dyn Sampler) -> Vector3f {
         if let Some(material) = &hit.material {
             if material.has_emission() {
                 return material.get_emission();
             }
         }
 
-        let (inter_light, pdf) = self.sample_light();
-        let light_normal = &inter_light.normal;
-        let ws = (&inter_light.coords - &hit.coords).normalize();
-        let cosine_theta = ws.dot(&hit.normal);
-        let cosine_theta_prime = (-&ws).dot(light_normal);
+        assert!(hit.material.is_some());
+        let hit_mat = hit.material.as_ref().unwrap();
+        sampler.set_dimension(Self::bounce_dimension(depth, BOUNCE_SPECULAR));
+        if let Some((dir, throughput)) = hit_mat.sample_specular(&-wo, &hit.normal, hit.front_face, lambda, sampler) {
+            return self.shade_specular(hit, &dir, &throughput, depth, lambda, sampler);
+        }
+        let normal = hit_mat.shading_normal(&hit.tcoords, &hit.normal, &hit.tangent);
 
         // directional lighting
         let mut l_dir = Vector3f::zero();
-        assert!(hit.material.is_some());
-        let hit_mat = hit.material.as_ref().unwrap();
-        let hit_to_light_dis = inter_light.coords.distance_sq(&hit.coords);
-        let shadow_check_inter = self.bvh.as_ref().unwrap().intersect(
-            &Ray::new(&hit.coords, &ws, 0.0)
-        );
-        let occluder_dis = shadow_check_inter.distance * shadow_check_inter.distance;
-        if occluder_dis - hit_to_light_dis > -1e-3 {
-            // not in shadow
-            let f_r = hit_mat.eval(&ws, wo, &hit.normal);
-            l_dir = &inter_light.emit // L_i
-                    * &f_r 
-                    * cosine_theta
-                    * cosine_theta_prime
-                    / hit_to_light_dis
-                    / pdf;
+        if self.lighting_mode != LightingMode::Naive {
+            sampler.set_dimension(Self::bounce_dimension(depth, BOUNCE_LIGHT));
+            let (inter_light, pdf) = self.sample_light(sampler);
+            let ws = (inter_light.coords - hit.coords).normalize();
+            let cosine_theta = ws.dot(&normal);
+            let cosine_theta_prime = Self::light_cosine(&inter_light, &ws);
+
+            let hit_to_light_dis = inter_light.coords.distance_sq(&hit.coords);
+            // aimed from the offset origin so the light sample is hit
+            // exactly at `light_dis`
+            let shadow_origin = self.offset_origin(hit, &ws);
+            let shadow_dir = (inter_light.coords - shadow_origin).normalize();
+            let mut shadow_ray = Ray::new(&shadow_origin, &shadow_dir, 0.0);
+            // the light itself sits at the end of the ray and does not occlude
+            shadow_ray.t_max = f64::sqrt(inter_light.coords.distance_sq(&shadow_origin)) - self.ray_epsilon;
+            // light samples behind the surface would add negative radiance
+            if cosine_theta > 0.0 && !self.occluded(&shadow_ray) {
+                // not in shadow
+                let f_r = Self::eval_at(hit, &ws, wo, &normal);
+                l_dir = &inter_light.emit // L_i
+                        * &f_r 
+                        * cosine_theta
+                        * cosine_theta_prime
+                        / hit_to_light_dis
+                        / pdf;
+            }
         }
 
         // indirectional lighting
         let mut l_indir = Vector3f::zero();
-        if self.estimator_strategy.determine(depth) {
-            let sample_dir = hit_mat.sample(&-wo, &hit.normal).normalize();
-            let indirect_inter = self.bvh.as_ref().unwrap().intersect(&Ray::new(&hit.coords, &sample_dir, 0.0));
-            if indirect_inter.hit && !indirect_inter.material.as_ref().unwrap().has_emission() {
-                let indirect_pdf = hit_mat.pdf(&-wo, &sample_dir, &hit.normal);
-                let f_r = hit_mat.eval(&sample_dir, wo, &hit.normal);
-                l_indir = (&self.shade(&indirect_inter, &-&sample_dir, depth + 1)
+        sampler.set_dimension(Self::bounce_dimension(depth, BOUNCE_ROULETTE));
+        if self.lighting_mode != LightingMode::NeeNoIndirect && self.estimator_strategy.determine(depth, sampler) {
+            sampler.set_dimension(Self::bounce_dimension(depth, BOUNCE_BSDF));
+            let sample_dir = hit_mat.sample(&-wo, &normal, sampler).normalize();
+            let indirect_inter = self.intersect(&Ray::new(&self.offset_origin(hit, &sample_dir), &sample_dir, 0.0));
+            let indirect_pdf = hit_mat.pdf(&-wo, &sample_dir, &normal);
+            // emitters were already sampled by nee, without it they count
+            // when hit from their emitting side
+            let counts = indirect_inter.hit && if indirect_inter.material.as_ref().unwrap().has_emission() {
+                self.lighting_mode == LightingMode::Naive && Self::light_cosine(&indirect_inter, &sample_dir) > 0.0
+            } else {
+                true
+            };
+            if indirect_pdf > PDF_EPSILON && counts {
+                let f_r = Self::eval_at(hit, &sample_dir, wo, &normal);
+                l_indir = (&self.shade(&indirect_inter, &-&sample_dir, depth + 1, lambda, sampler)
                             * &f_r
-                            * sample_dir.dot(&hit.normal)
+                            * sample_dir.dot(&normal)
                             / indirect_pdf)
                             * self.estimator_strategy.compensation();
             }
@@ -134,25 +456,375 @@ impl Scene {
         l_dir + l_indir
     }
 
-    fn sample_light(&self) -> (Intersection, f64) {
-        let mut emit_area_sum: f64 = 0.0;
-        for obj in self.models.iter() {
-            if obj.material.has_emission() {
-                emit_area_sum += obj.get_area();
-            }
+    // follows a specular bounce, emitters seen through it count directly as
+    // no light sample could reach them
+    fn shade_specular(&self, hit: &Intersection, dir: &Vector3f, throughput: &Vector3f, depth: usize, lambda: Option<f64>, sampler: &mut dyn Sampler) -> Vector3f {
+        sampler.set_dimension(Self::bounce_dimension(depth, BOUNCE_ROULETTE));
+        if !self.estimator_strategy.determine(depth, sampler) {
+            return Vector3f::zero();
+        }
+        let inter = self.intersect(&Ray::new(&self.offset_origin(hit, dir), dir, 0.0));
+        if !inter.hit {
+            return Vector3f::zero();
         }
+        &self.shade(&inter, &-dir, depth + 1, lambda, sampler) * throughput * self.estimator_strategy.compensation()
+    }
 
-        let p = Math::sample_uniform_distribution(0.0, 1.0) * emit_area_sum;
-        emit_area_sum = 0.0;
-        for obj in self.models.iter() {
-            if obj.material.has_emission() {
-                emit_area_sum += obj.get_area();
-                if emit_area_sum >= p {
-                    return obj.sample();
-                }
+    // first sampler dimension of `offset` in the block of bounce `depth`
+    fn bounce_dimension(depth: usize, offset: usize) -> usize {
+        CAMERA_DIMENSIONS + depth * BOUNCE_DIMENSIONS + offset
+    }
+
+    // bsdf of the material at `hit`, with its vertex color if it has one
+    fn eval_at(hit: &Intersection, ws: &Vector3f, wo: &Vector3f, normal: &Vector3f) -> Vector3f {
+        let material = hit.material.as_ref().unwrap();
+        match &hit.color {
+            Some(color) => material.eval_with_color(ws, wo, normal, color),
+            None => material.eval(ws, wo, normal),
+        }
+    }
+
+    // start of a ray leaving `hit` towards `dir`, pushed off the surface on
+    // the side `dir` points to, so refracted rays go through
+    fn offset_origin(&self, hit: &Intersection, dir: &Vector3f) -> Vector3f {
+        let offset = if dir.dot(&hit.normal) < 0.0 { -self.ray_epsilon } else { self.ray_epsilon };
+        hit.coords + hit.normal * offset
+    }
+
+    // cosine at the light sample towards the shading point, zero behind a
+    // one sided emitter
+    fn light_cosine(inter_light: &Intersection, ws: &Vector3f) -> f64 {
+        let cosine = (-ws).dot(&inter_light.normal);
+        let two_sided = inter_light.material.as_ref().is_some_and(|material| material.is_two_sided());
+        if two_sided {
+            f64::abs(cosine)
+        } else {
+            f64::max(cosine, 0.0)
+        }
+    }
+
+    // emitters without area, like a model made only of degenerate faces,
+    // have nothing to sample and stay out of the light table
+    fn emissive_objects(&self) -> impl Iterator<Item = (Arc<dyn Object>, Vector3f)> + '_ {
+        self.models.iter()
+            .filter(|model| model.material.has_emission())
+            .map(|model| (model.clone() as Arc<dyn Object>, model.material.get_emission()))
+            .chain(self.spheres.iter()
+                .filter(|sphere| sphere.material.has_emission())
+                .map(|sphere| (sphere.clone() as Arc<dyn Object>, sphere.material.get_emission())))
+            .chain(self.instances.iter()
+                .filter(|instance| instance.model.material.has_emission())
+                .map(|instance| (instance.clone() as Arc<dyn Object>, instance.model.material.get_emission())))
+            .filter(|(obj, _)| obj.get_area() > 0.0)
+    }
+
+    // picks a light from the cdf in `lights` that matches `light_sampling`,
+    // the area density of the point is scaled by the chance of the pick
+    fn sample_light(&self, sampler: &mut dyn Sampler) -> (Intersection, f64) {
+        let cdf = |light: &(Arc<dyn Object>, f64, f64)| -> f64 {
+            match self.light_sampling {
+                LightSamplingStrategy::ByArea => light.1,
+                LightSamplingStrategy::ByPower => light.2,
             }
+        };
+        let total = cdf(self.lights.last().expect("no emissive objects in the scene"));
+        let p = sampler.next_1d() * total;
+        let index = self.lights.partition_point(|light| cdf(light) < p);
+        if let Some(light) = self.lights.get(index) {
+            let previous = if index == 0 { 0.0 } else { cdf(&self.lights[index - 1]) };
+            let (inter, pdf) = light.0.sample(sampler);
+            return (inter, pdf * (cdf(light) - previous) / total);
         }
 
         panic!("impossible");
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::material::{LitMaterial, Material};
+    use crate::math::UniformSampler;
+
+    fn test_scene() -> Scene {
+        Scene::new(4, 4, 40.0, Vector3f::zero(), EstimatorStrategy::MaximumBounces(2), 1)
+    }
+
+    fn emissive() -> Arc<dyn Material> {
+        Arc::new(LitMaterial::new(&Vector3f::scalar(0.5), &Vector3f::scalar(1.0)))
+    }
+
+    #[test]
+    fn emitters_without_area_are_not_lights() {
+        let vertices = [Vector3f::new(0.0, 0.0, 0.0), Vector3f::new(1.0, 0.0, 0.0), Vector3f::new(0.0, 1.0, 0.0), Vector3f::new(2.0, 0.0, 0.0)];
+        let mut scene = test_scene();
+        scene.add(Arc::new(Model::from_triangles("flat", &vertices, &[], &[0, 1, 3], emissive())));
+        scene.build_bvh();
+        assert_eq!(scene.light_count(), 0);
+
+        scene.add(Arc::new(Model::from_triangles("triangle", &vertices, &[], &[0, 1, 2], emissive())));
+        scene.build_bvh();
+        assert_eq!(scene.light_count(), 1);
+        let mut sampler = UniformSampler::new(3);
+        for _ in 0..64 {
+            let (inter, pdf) = scene.sample_light(&mut sampler);
+            assert!(pdf > 0.0 && inter.coords.x + inter.coords.y <= 1.0 + 1e-9);
+        }
+    }
+
+    #[test]
+    fn preview_ignores_light_placement() {
+        let wall = [Vector3f::new(-2.0, -2.0, 5.0), Vector3f::new(2.0, -2.0, 5.0), Vector3f::new(0.0, 2.0, 5.0)];
+        let diffuse: Arc<dyn Material> = Arc::new(LitMaterial::new(&Vector3f::new(0.7, 0.4, 0.2), &Vector3f::zero()));
+        let ray = Ray::new(&Vector3f::zero(), &Vector3f::new(0.1, 0.1, 1.0).normalize(), 0.0);
+        let shade_with_light = |light: Option<[Vector3f; 3]>| {
+            let mut scene = test_scene();
+            scene.add(Arc::new(Model::from_triangles("wall", &wall, &[], &[0, 2, 1], diffuse.clone())));
+            if let Some(light) = light {
+                scene.add(Arc::new(Model::from_triangles("light", &light, &[], &[0, 1, 2], emissive())));
+            }
+            scene.build_bvh();
+            let (color, hit) = scene.cast_preview_ray(&ray).unwrap();
+            assert!(hit);
+            color
+        };
+
+        let unlit = shade_with_light(None);
+        let above = shade_with_light(Some([Vector3f::new(-1.0, 3.0, 3.0), Vector3f::new(1.0, 3.0, 3.0), Vector3f::new(0.0, 3.0, 4.0)]));
+        let beside = shade_with_light(Some([Vector3f::new(-3.0, -1.0, 2.0), Vector3f::new(-3.0, 1.0, 2.0), Vector3f::new(-3.0, 0.0, 1.0)]));
+        assert!(unlit.length() > 0.0);
+        assert!(above.approx_eq(&unlit, 1e-12));
+        assert!(beside.approx_eq(&unlit, 1e-12));
+    }
+
+    #[test]
+    fn one_sided_lights_only_emit_from_their_front() {
+        // a ceiling light facing down
+        let light = |two_sided: bool| {
+            let mut material = LitMaterial::new(&Vector3f::scalar(0.5), &Vector3f::scalar(1.0));
+            material.two_sided = two_sided;
+            let mut inter = Intersection::new();
+            inter.normal = Vector3f::new(0.0, -1.0, 0.0);
+            inter.material = Some(Arc::new(material));
+            inter
+        };
+        let from_below = Vector3f::new(0.6, 0.8, 0.0);
+        let from_above = Vector3f::new(0.6, -0.8, 0.0);
+        assert!(f64::abs(Scene::light_cosine(&light(false), &from_below) - 0.8) < 1e-12);
+        assert_eq!(Scene::light_cosine(&light(false), &from_above), 0.0);
+        assert!(f64::abs(Scene::light_cosine(&light(true), &from_below) - 0.8) < 1e-12);
+        assert!(f64::abs(Scene::light_cosine(&light(true), &from_above) - 0.8) < 1e-12);
+    }
+
+    #[test]
+    fn orthographic_primary_rays_are_parallel() {
+        let projection = Projection::Orthographic { height: 4.0 };
+        // a camera tilted away from the world axes
+        let forward = Vector3f::new(1.0, -1.0, 2.0).normalize();
+        let right = Vector3f::new(0.0, 1.0, 0.0).cross(&forward).normalize();
+        let camera = Camera { position: Vector3f::new(1.0, 2.0, 3.0), right, up: forward.cross(&right), forward };
+        let ray = |x: f64, y: f64| {
+            let (offset, dir) = projection.camera_ray(x, y, 1.5);
+            (camera.position + camera.to_world(&offset), camera.to_world(&dir))
+        };
+        let (center, center_dir) = ray(0.0, 0.0);
+        assert!(center_dir.approx_eq(&forward, 1e-12));
+        for (x, y) in [(-1.0, -1.0), (1.0, -1.0), (0.3, 0.8), (-1.0, 1.0)] {
+            let (origin, dir) = ray(x, y);
+            assert!(dir.approx_eq(&center_dir, 1e-12));
+            // origins spread over the film plane, which faces the view direction
+            assert!(f64::abs((origin - center).dot(&forward)) < 1e-12);
+            assert!(f64::abs((origin - center).dot(&camera.up) - 2.0 * y) < 1e-12);
+            assert!(f64::abs((origin - center).dot(&right) - 3.0 * x) < 1e-12);
+        }
+    }
+
+    // a triangle of `area` at x = `x`, lit with `emission`
+    fn light_triangle(x: f64, area: f64, emission: f64) -> Arc<Model> {
+        let vertices = [Vector3f::new(x, 0.0, 0.0), Vector3f::new(x + area, 0.0, 0.0), Vector3f::new(x, 2.0, 0.0)];
+        let material = Arc::new(LitMaterial::new(&Vector3f::scalar(0.5), &Vector3f::scalar(emission)));
+        Arc::new(Model::from_triangles("light", &vertices, &[], &[0, 1, 2], material))
+    }
+
+    #[test]
+    fn lights_are_picked_in_proportion_to_their_area() {
+        let mut scene = test_scene();
+        scene.add(light_triangle(0.0, 1.0, 1.0));
+        scene.add(light_triangle(10.0, 3.0, 1.0));
+        scene.build_bvh();
+        let mut sampler = UniformSampler::new(17);
+        let n = 40000;
+        let mut large = 0;
+        for _ in 0..n {
+            let (inter, pdf) = scene.sample_light(&mut sampler);
+            // uniform over the summed area of both lights
+            assert!(f64::abs(pdf - 0.25) < 1e-9);
+            large += (inter.coords.x > 5.0) as u32;
+        }
+        assert!(f64::abs(large as f64 / n as f64 - 0.75) < 0.01);
+    }
+
+    #[test]
+    fn power_sampling_favors_bright_lights_without_bias() {
+        let mut scene = test_scene();
+        scene.add(light_triangle(0.0, 2.0, 1.0));
+        scene.add(light_triangle(10.0, 2.0, 3.0));
+        scene.light_sampling = LightSamplingStrategy::ByPower;
+        scene.build_bvh();
+        let mut sampler = UniformSampler::new(23);
+        let n = 40000;
+        let mut bright = 0;
+        let mut power = 0.0;
+        for _ in 0..n {
+            let (inter, pdf) = scene.sample_light(&mut sampler);
+            let is_bright = inter.coords.x > 5.0;
+            bright += is_bright as u32;
+            power += if is_bright { 3.0 } else { 1.0 } / pdf;
+        }
+        assert!(f64::abs(bright as f64 / n as f64 - 0.75) < 0.01);
+        // the emission integrated over both lights, 2 * 1 + 2 * 3
+        assert!(f64::abs(power / n as f64 - 8.0) < 1e-9);
+    }
+
+    #[test]
+    fn normal_and_depth_aovs_of_a_known_hit() {
+        let mut scene = test_scene();
+        // facing +z, seen from 3 units above
+        scene.add(light_triangle(0.0, 2.0, 1.0));
+        scene.build_bvh();
+        let ray = Ray::new(&Vector3f::new(0.5, 0.5, 3.0), &Vector3f::new(0.0, 0.0, -1.0), 0.0);
+        let (normal, hit) = scene.cast_aov_ray(&ray, Aov::Normal).unwrap();
+        assert!(hit);
+        assert!(normal.approx_eq(&Vector3f::new(0.5, 0.5, 1.0), 1e-9));
+        let (depth, _) = scene.cast_aov_ray(&ray, Aov::Depth).unwrap();
+        assert!(depth.approx_eq(&Vector3f::scalar(3.0), 1e-9));
+
+        let miss = Ray::new(&Vector3f::new(5.0, 5.0, 3.0), &Vector3f::new(0.0, 0.0, -1.0), 0.0);
+        let (depth, hit) = scene.cast_aov_ray(&miss, Aov::Depth).unwrap();
+        assert!(!hit && depth == Vector3f::zero());
+    }
+
+    #[test]
+    fn naive_and_nee_converge_to_the_same_radiance() {
+        let corners = |y: f64| [-2.0, 2.0].map(|z| [-2.0, 2.0].map(|x| Vector3f::new(x, y, z)));
+        let quad = |name: &str, y: f64, indices: &[u32], material: Arc<dyn Material>| {
+            let [[v0, v1], [v3, v2]] = corners(y);
+            Arc::new(Model::from_triangles(name, &[v0, v1, v2, v3], &[], indices, material))
+        };
+        let radiance = |mode: LightingMode| {
+            let mut scene = test_scene();
+            scene.lighting_mode = mode;
+            // a white floor under a large ceiling light, the two face each other
+            let white = Arc::new(LitMaterial::new(&Vector3f::scalar(0.5), &Vector3f::zero()));
+            scene.add(quad("floor", 0.0, &[0, 2, 1, 0, 3, 2], white));
+            scene.add(quad("light", 1.0, &[0, 1, 2, 0, 2, 3], emissive()));
+            scene.build_bvh();
+            let ray = Ray::new(&Vector3f::new(0.3, 0.5, 0.2), &Vector3f::new(0.0, -1.0, 0.0), 0.0);
+            let mut sampler = UniformSampler::new(41);
+            let n = 40000;
+            let sum = (0..n).fold(Vector3f::zero(), |sum, _| sum + scene.cast_ray(&ray, &mut sampler).unwrap().0);
+            sum / n as f64
+        };
+
+        let nee = radiance(LightingMode::Nee);
+        let naive = radiance(LightingMode::Naive);
+        assert!(nee.x > 0.1);
+        assert!(f64::abs(naive.x - nee.x) < 0.02 * nee.x);
+    }
+
+    #[test]
+    fn flat_bvh_matches_the_two_level_one() {
+        let cornell = |flat: bool| {
+            let mut scene = test_scene();
+            let white = Arc::new(LitMaterial::new(&Vector3f::scalar(0.7), &Vector3f::zero()));
+            for name in ["floor", "shortbox", "tallbox", "left", "right", "light"] {
+                let path = format!("./resource/cornellbox/{}.obj", name);
+                scene.add(Arc::new(Model::new(&path, white.clone()).unwrap()));
+            }
+            if flat {
+                scene.build_flat_bvh();
+            } else {
+                scene.build_bvh();
+            }
+            scene
+        };
+        let (two_level, flat) = (cornell(false), cornell(true));
+
+        let mut sampler = UniformSampler::new(8);
+        let origin = Vector3f::new(278.0, 273.0, -800.0);
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let (x, y) = sampler.next_2d();
+            let target = Vector3f::new(x * 600.0 - 20.0, y * 600.0 - 20.0, sampler.next_1d() * 560.0);
+            let ray = Ray::new(&origin, &(target - origin).normalize(), 0.0);
+            let (a, b) = (two_level.first_hit(&ray).unwrap(), flat.first_hit(&ray).unwrap());
+            assert_eq!(a.hit, b.hit);
+            assert_eq!(a.distance, b.distance);
+            assert!(a.coords == b.coords && a.normal == b.normal);
+            hits += a.hit as u32;
+        }
+        assert!(hits > 1000);
+    }
+
+    #[test]
+    fn shutter_cameras_only_move_when_the_end_differs() {
+        let same = |a: &Camera, b: &Camera| {
+            a.position == b.position && a.right == b.right && a.up == b.up && a.forward == b.forward
+        };
+        let mut scene = test_scene();
+        let still = scene.camera;
+        scene.camera_end = Some(still);
+        for time in [0.0, 0.25, 0.5, 1.0] {
+            assert!(same(&scene.camera_at(time), &still));
+        }
+
+        // a camera panning sideways while turning a little to the right
+        let mut end = still;
+        end.position = still.position + Vector3f::new(-20.0, 0.0, 0.0);
+        end.forward = Vector3f::new(-0.1, 0.0, 1.0).normalize();
+        end.right = end.forward.cross(&still.up);
+        scene.camera_end = Some(end);
+        assert!(same(&scene.camera_at(0.0), &still));
+        assert!(scene.camera_at(1.0).position.approx_eq(&end.position, 1e-12));
+        assert!(scene.camera_at(1.0).forward.approx_eq(&end.forward, 1e-12));
+        let origins: Vec<_> = [0.0, 0.25, 0.5, 0.75, 1.0].iter().map(|t| scene.camera_at(*t).position).collect();
+        for pair in origins.windows(2) {
+            assert!((pair[1] - pair[0]).approx_eq(&Vector3f::new(-5.0, 0.0, 0.0), 1e-9));
+        }
+        let halfway = scene.camera_at(0.5);
+        assert!(f64::abs(halfway.forward.length() - 1.0) < 1e-12);
+        assert!(f64::abs(halfway.right.length() - 1.0) < 1e-12);
+    }
+
+    #[test]
+    fn rays_leaving_coplanar_triangles_do_not_hit_them_again() {
+        // a tilted double sided quad split along its diagonal, rays leaving
+        // it are not culled by either of its triangles
+        let mut material = LitMaterial::new(&Vector3f::scalar(0.5), &Vector3f::zero());
+        material.double_sided = true;
+        let tilted = |x: f64, y: f64| Vector3f::new(x, y, 0.37 * x + 0.21 * y + 0.5);
+        let vertices = [tilted(-1.0, -1.0), tilted(1.0, -1.0), tilted(1.0, 1.0), tilted(-1.0, 1.0)];
+        let mut scene = test_scene();
+        scene.add(Arc::new(Model::from_triangles("quad", &vertices, &[], &[0, 1, 2, 0, 2, 3], Arc::new(material))));
+        scene.build_bvh();
+
+        let self_hits = |scene: &Scene| {
+            let mut count = 0;
+            for i in 0..256 {
+                let (x, y) = ((i % 16) as f64 / 8.0 - 0.97, (i / 16) as f64 / 8.0 - 0.93);
+                let hit = scene.intersect(&Ray::new(&Vector3f::new(x, y, 3.0), &Vector3f::new(0.0, 0.0, -1.0), 0.0));
+                assert!(hit.hit);
+                // towards a light above, the way the shadow rays leave
+                let dir = Vector3f::new(0.3, -0.2, 1.0).normalize();
+                if scene.intersect(&Ray::new(&scene.offset_origin(&hit, &dir), &dir, 0.0)).hit {
+                    count += 1;
+                }
+            }
+            count
+        };
+        assert_eq!(self_hits(&scene), 0);
+        // without the offset some rays start just under the surface
+        scene.ray_epsilon = 0.0;
+        assert!(self_hits(&scene) > 0);
+    }
+}
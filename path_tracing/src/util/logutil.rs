@@ -1,4 +1,5 @@
 use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 pub struct LogUtil;
 
@@ -7,4 +8,28 @@ impl LogUtil {
         print!("\r\x1B[KProgress: {} - {}%", text, (progress * 100.0) as u8);
         std::io::stdout().flush().unwrap();
     }
+
+    // current time as "YYYY-MM-DD HH:MM:SS UTC"
+    pub fn utc_timestamp() -> String {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let (days, time) = (seconds / 86400, seconds % 86400);
+
+        // civil date from days since 1970-01-01, in eras of 400 years
+        // starting on March 1st so leap days fall at the end of a year
+        let z = days as i64 + 719468;
+        let era = z.div_euclid(146097);
+        let day_of_era = z.rem_euclid(146097);
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+        format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            year, month, day, time / 3600, time % 3600 / 60, time % 60
+        )
+    }
 }
\ No newline at end of file
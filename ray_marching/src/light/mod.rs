@@ -0,0 +1,165 @@
+use crate::math::{Math, Vector3f};
+
+pub enum Light {
+    // parallel light travelling along `direction`
+    Directional {
+        direction: Vector3f,
+        radiance: Vector3f,
+    },
+    // omnidirectional light with inverse square falloff, cut to zero at
+    // `radius` so points further away can skip it entirely. the cut is a
+    // step, pick a radius where the light is already dim. an infinite radius
+    // keeps the plain inverse square law
+    Point {
+        position: Vector3f,
+        intensity: Vector3f,
+        radius: f64,
+    },
+    Spot(SpotLight),
+}
+
+impl Light {
+    // unit direction from p towards the light, the radiance arriving at p
+    // ignoring occlusion and the distance a shadow ray has to clear. a point
+    // right at a light has no direction and receives nothing
+    pub fn incident(&self, p: &Vector3f) -> (Vector3f, Vector3f, f64) {
+        match self {
+            Light::Directional {
+                direction,
+                radiance,
+            } => (-&direction.normalize(), *radiance, f64::INFINITY),
+            Light::Point {
+                position,
+                intensity,
+                radius,
+            } => {
+                let to_light = *position - *p;
+                let distance = to_light.length();
+                if distance <= 0.0 {
+                    return (Vector3f::zero(), Vector3f::zero(), 0.0);
+                }
+                if distance >= *radius {
+                    return (to_light / distance, Vector3f::zero(), distance);
+                }
+                let distance_sq = f64::max(distance * distance, 1e-4);
+                (to_light / distance, *intensity / distance_sq, distance)
+            }
+            Light::Spot(spot) => {
+                let to_light = spot.position - *p;
+                let distance = to_light.length();
+                if distance <= 0.0 {
+                    return (Vector3f::zero(), Vector3f::zero(), 0.0);
+                }
+                (to_light / distance, spot.radiance(p), distance)
+            }
+        }
+    }
+}
+
+// point light restricted to a cone, the cone angles are stored as cosines
+// so the falloff is a smoothstep over a dot product
+pub struct SpotLight {
+    pub position: Vector3f,
+    pub direction: Vector3f,
+    pub cos_inner: f64,
+    pub cos_outer: f64,
+    pub color: Vector3f,
+}
+
+impl SpotLight {
+    // `inner_angle` and `outer_angle` are half angles in degrees
+    pub fn new(
+        position: Vector3f,
+        direction: Vector3f,
+        inner_angle: f64,
+        outer_angle: f64,
+        color: Vector3f,
+    ) -> SpotLight {
+        SpotLight {
+            position,
+            direction: direction.normalize(),
+            cos_inner: f64::cos(Math::radian(inner_angle)),
+            cos_outer: f64::cos(Math::radian(outer_angle)),
+            color,
+        }
+    }
+
+    // 1 inside the inner cone, 0 outside the outer cone
+    pub fn cone_attenuation(&self, p: &Vector3f) -> f64 {
+        let cos_angle = (p - &self.position).normalize().dot(&self.direction);
+        if self.cos_inner <= self.cos_outer {
+            return if cos_angle >= self.cos_outer {
+                1.0
+            } else {
+                0.0
+            };
+        }
+        let t = f64::clamp(
+            (cos_angle - self.cos_outer) / (self.cos_inner - self.cos_outer),
+            0.0,
+            1.0,
+        );
+        t * t * (3.0 - 2.0 * t)
+    }
+
+    // radiance arriving at p, ignoring occlusion
+    pub fn radiance(&self, p: &Vector3f) -> Vector3f {
+        let to_light = self.position - *p;
+        let distance_sq = f64::max(to_light.dot(&to_light), 1e-4);
+        self.color * (self.cone_attenuation(p) / distance_sq)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point_light(radius: f64) -> Light {
+        Light::Point {
+            position: Vector3f::zero(),
+            intensity: Vector3f::scalar(8.0),
+            radius,
+        }
+    }
+
+    #[test]
+    fn point_light_beyond_its_radius_is_zero() {
+        let (_, radiance, distance) = point_light(3.0).incident(&Vector3f::new(0.0, 4.0, 0.0));
+        assert_eq!(radiance.x, 0.0);
+        assert_eq!(distance, 4.0);
+        let (_, radiance, _) = point_light(3.0).incident(&Vector3f::new(3.0, 0.0, 0.0));
+        assert_eq!(radiance.x, 0.0);
+    }
+
+    #[test]
+    fn point_light_inside_its_radius_falls_off_with_the_square() {
+        let (direction, radiance, distance) =
+            point_light(3.0).incident(&Vector3f::new(0.0, 2.0, 0.0));
+        assert_eq!(radiance.x, 2.0);
+        assert_eq!(distance, 2.0);
+        assert_eq!(direction.y, -1.0);
+        let (_, unbounded, _) = point_light(f64::INFINITY).incident(&Vector3f::new(0.0, 2.0, 0.0));
+        assert_eq!(unbounded.x, radiance.x);
+    }
+
+    #[test]
+    fn point_at_the_light_receives_nothing() {
+        let (direction, radiance, _) = point_light(3.0).incident(&Vector3f::zero());
+        assert_eq!(direction.length(), 0.0);
+        assert_eq!(radiance.length(), 0.0);
+    }
+
+    #[test]
+    fn spot_light_stores_the_cosines_of_its_half_angles() {
+        let spot = SpotLight::new(
+            Vector3f::zero(),
+            Vector3f::new(0.0, -2.0, 0.0),
+            20.0,
+            35.0,
+            Vector3f::scalar(1.0),
+        );
+        assert!(f64::abs(spot.cos_inner - f64::cos(20f64.to_radians())) < 1e-12);
+        assert!(f64::abs(spot.cos_outer - f64::cos(35f64.to_radians())) < 1e-12);
+        assert!(spot.direction == Vector3f::new(0.0, -1.0, 0.0));
+    }
+}
@@ -1,10 +1,10 @@
-#![feature(trait_upcasting)]
 use std::rc::Rc;
 
 use material::PBRMaterial;
 use math::Vector3f;
 use minifb::{Key, Window, WindowOptions};
 use sdf::{
+    loader::SceneFile,
     primitive::{Cube, Helix, Sphere, Torus},
     Scene,
 };
@@ -12,15 +12,23 @@ use sdf::{
 use crate::renderer::{framebuffer::FrameBuffer, rendering::Renderer};
 
 pub mod domain;
+pub mod light;
 pub mod material;
 pub mod math;
 pub mod renderer;
 pub mod sdf;
 
-fn render(show_window: bool) {
+fn render(show_window: bool, scene_path: Option<String>) {
     let dpi = 1.5;
-    let width = (720.0 * dpi) as usize;
-    let height = (405.0 * dpi) as usize;
+    let scene_file = scene_path.map(|path| {
+        SceneFile::from_json(&path).unwrap_or_else(|err| {
+            panic!("[Main] load scene error {}", err);
+        })
+    });
+    let (width, height) = match &scene_file {
+        Some(file) => (file.width as usize, file.height as usize),
+        None => ((720.0 * dpi) as usize, (405.0 * dpi) as usize),
+    };
     let mut window = Window::new("Ray Marching", width, height, WindowOptions::default())
         .unwrap_or_else(|e| {
             panic!("[Main] cannot create native window {}", e);
@@ -30,42 +38,49 @@ fn render(show_window: bool) {
         window.update();
     }
 
-    // rotation
-    let eye = Vector3f::new(-0.3, 4.0, -9.5);
-    let rotation = Vector3f::new(32.0, 0.0, 0.0);
-    let scene = Scene::new(
-        width as u32,
-        height as u32,
-        60.0,
-        1,
-        Vector3f::new(0.235294, 0.67451, 0.843137),
-    );
+    let scene = match &scene_file {
+        Some(file) => file.create_scene(),
+        None => Scene::new(
+            width as u32,
+            height as u32,
+            60.0,
+            1,
+            Vector3f::new(0.235294, 0.67451, 0.843137),
+        ),
+    };
 
     // Cube Frame
-    add_models_to_scene(&scene);
+    match &scene_file {
+        Some(file) => file.populate(&scene).unwrap_or_else(|err| {
+            panic!("[Main] load scene error {}", err);
+        }),
+        None => add_models_to_scene(&scene),
+    }
 
     // renderer
     let mut renderer = Renderer::new();
     let fbo = FrameBuffer::new(scene.width, scene.height);
     renderer.fbo = Some(fbo);
 
-    renderer
-        .render(eye, rotation, &scene, false)
-        .unwrap_or_else(|err| {
-            panic!("[Main] renderer error {}", err);
-        });
-
-    let fbo = renderer.fbo.as_mut().unwrap();
-    let rt = fbo.get_render_target();
+    renderer.render(&scene, false).unwrap_or_else(|err| {
+        panic!("[Main] renderer error {}", err);
+    });
 
-    // show in window
+    // show in window, refining the image with a new frame per update
     if show_window {
-        let buffer = &rt.get_buffer(false);
         while window.is_open() && !window.is_key_down(Key::Escape) {
-            window.update_with_buffer(buffer, width, height).unwrap();
+            let rt = renderer.fbo.as_mut().unwrap().get_render_target();
+            let buffer = rt.get_buffer(false);
+            window.update_with_buffer(&buffer, width, height).unwrap();
+            renderer.render(&scene, true).unwrap_or_else(|err| {
+                panic!("[Main] renderer error {}", err);
+            });
         }
     }
 
+    let fbo = renderer.fbo.as_mut().unwrap();
+    let rt = fbo.get_render_target();
+
     // dump to file
     rt.dump_to_file("out/result.ppm").unwrap_or_else(|err| {
         panic!("[Main] dump rt to file error {}", err);
@@ -167,5 +182,5 @@ fn add_models_to_scene<'a>(scene: &'a Scene<'a>) {
 }
 
 fn main() {
-    render(true);
+    render(true, std::env::args().nth(1));
 }
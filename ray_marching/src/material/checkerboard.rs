@@ -0,0 +1,80 @@
+use crate::math::{lerp, Vector3f};
+
+// checker pattern on the xz plane, box filtered over the footprint of the
+// sample so distant cells converge to the average of both colors
+pub struct Checkerboard {
+    pub cell_size: f64,
+    pub color_a: Vector3f,
+    pub color_b: Vector3f,
+}
+
+impl Checkerboard {
+    pub fn new(cell_size: f64, color_a: Vector3f, color_b: Vector3f) -> Checkerboard {
+        Checkerboard {
+            cell_size,
+            color_a,
+            color_b,
+        }
+    }
+
+    // `footprint` is the world space width covered by the sample, 0 gives
+    // the unfiltered pattern
+    pub fn sample(&self, p: &Vector3f, footprint: f64) -> Vector3f {
+        let w = f64::max(footprint / self.cell_size, 1e-4);
+        let ix = Self::filtered_square_wave(p.x / self.cell_size, w);
+        let iz = Self::filtered_square_wave(p.z / self.cell_size, w);
+        lerp(self.color_a, self.color_b, 0.5 - 0.5 * ix * iz)
+    }
+
+    // square wave of period 2 in [-1, 1], averaged over [x - w / 2, x + w / 2]
+    // through the difference of its integral, a triangle wave
+    fn filtered_square_wave(x: f64, w: f64) -> f64 {
+        let triangle = |x: f64| f64::abs(x * 0.5 - f64::floor(x * 0.5) - 0.5);
+        2.0 * (triangle(x - 0.5 * w) - triangle(x + 0.5 * w)) / w
+    }
+}
+
+impl Default for Checkerboard {
+    fn default() -> Self {
+        Checkerboard::new(
+            2.0,
+            Vector3f::new(1.0, 1.0, 1.0) * 0.8,
+            Vector3f::new(1.0, 1.0, 1.0) * 0.3,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn black_and_white() -> Checkerboard {
+        Checkerboard::new(2.0, Vector3f::scalar(1.0), Vector3f::zero())
+    }
+
+    #[test]
+    fn adjacent_cells_have_distinct_colors() {
+        let board = black_and_white();
+        let a = Vector3f::new(1.0, 0.0, 1.0);
+        let b = Vector3f::new(3.0, 0.0, 1.0);
+        let c = Vector3f::new(3.0, 0.0, 3.0);
+        assert!(board.sample(&a, 0.0).approx_eq(&board.color_a, 1e-9));
+        assert!(board.sample(&b, 0.0).approx_eq(&board.color_b, 1e-9));
+        assert!(board.sample(&c, 0.0).approx_eq(&board.color_a, 1e-9));
+        // a footprint inside the cell does not blur its center
+        assert!(board.sample(&a, 1.0).approx_eq(&board.color_a, 1e-9));
+    }
+
+    #[test]
+    fn wide_footprints_converge_to_the_average() {
+        let board = black_and_white();
+        let p = Vector3f::new(1.0, 0.0, 1.0);
+        let filtered = |footprint: f64| f64::abs(board.sample(&p, footprint).x - 0.5);
+        // the unfiltered pattern is fully white here, blurring pulls it in
+        assert!(f64::abs(filtered(0.0) - 0.5) < 1e-9);
+        assert!(filtered(6.0) < 0.5);
+        assert!(filtered(100.0) < 0.02);
+        let far = Vector3f::new(123.4, 0.0, -56.7);
+        assert!(f64::abs(board.sample(&far, 1000.0).x - 0.5) < 0.01);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::math::Vector3f;
 
+pub mod checkerboard;
 pub mod pbr;
 
 pub struct PBRMaterial {
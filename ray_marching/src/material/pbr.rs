@@ -1,7 +1,4 @@
-use std::{
-    f64::{consts::PI, EPSILON},
-    rc::Rc,
-};
+use std::{f64::consts::PI, rc::Rc};
 
 use crate::{
     math::{lerp, Vector3f},
@@ -72,8 +69,8 @@ fn geometry_simth(normal: &Vector3f, view: &Vector3f, light: &Vector3f, roughnes
     ggx1 * ggx2
 }
 
-fn fresnel_schlick(cos_theta: f64, f0: &Vector3f) -> Vector3f {
-    let f1 = (&Vector3f::scalar(1.0) - f0) * f64::powf(1.0 - cos_theta + EPSILON, 5.0);
+pub fn fresnel_schlick(cos_theta: f64, f0: &Vector3f) -> Vector3f {
+    let f1 = (&Vector3f::scalar(1.0) - f0) * f64::powf(1.0 - cos_theta + f64::EPSILON, 5.0);
     f0 + &f1
 }
 
@@ -0,0 +1,271 @@
+use std::{fmt::Display, ops};
+
+use super::Vector3f;
+
+// row-major 4x4 matrix acting on column vectors, p' = M * p
+#[derive(Copy, Clone, PartialEq)]
+pub struct Matrix4f {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Matrix4f {
+    pub fn new(m: [[f64; 4]; 4]) -> Matrix4f {
+        Matrix4f { m }
+    }
+
+    pub fn identity() -> Matrix4f {
+        Matrix4f {
+            m: [
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn from_translation(t: &Vector3f) -> Matrix4f {
+        let mut mat = Matrix4f::identity();
+        mat.m[0][3] = t.x;
+        mat.m[1][3] = t.y;
+        mat.m[2][3] = t.z;
+        mat
+    }
+
+    pub fn from_scale(s: &Vector3f) -> Matrix4f {
+        let mut mat = Matrix4f::identity();
+        mat.m[0][0] = s.x;
+        mat.m[1][1] = s.y;
+        mat.m[2][2] = s.z;
+        mat
+    }
+
+    pub fn from_rotation_x(radian: f64) -> Matrix4f {
+        let (s, c) = f64::sin_cos(radian);
+        let mut mat = Matrix4f::identity();
+        mat.m[1][1] = c;
+        mat.m[1][2] = -s;
+        mat.m[2][1] = s;
+        mat.m[2][2] = c;
+        mat
+    }
+
+    pub fn from_rotation_y(radian: f64) -> Matrix4f {
+        let (s, c) = f64::sin_cos(radian);
+        let mut mat = Matrix4f::identity();
+        mat.m[0][0] = c;
+        mat.m[0][2] = s;
+        mat.m[2][0] = -s;
+        mat.m[2][2] = c;
+        mat
+    }
+
+    pub fn from_rotation_z(radian: f64) -> Matrix4f {
+        let (s, c) = f64::sin_cos(radian);
+        let mut mat = Matrix4f::identity();
+        mat.m[0][0] = c;
+        mat.m[0][1] = -s;
+        mat.m[1][0] = s;
+        mat.m[1][1] = c;
+        mat
+    }
+
+    // roll around x, then pitch around y, then yaw around z (radians),
+    // the same convention as nalgebra's Rotation3::from_euler_angles
+    pub fn from_euler(roll: f64, pitch: f64, yaw: f64) -> Matrix4f {
+        Matrix4f::from_rotation_z(yaw)
+            * Matrix4f::from_rotation_y(pitch)
+            * Matrix4f::from_rotation_x(roll)
+    }
+
+    // camera-to-world transform of a camera at `eye` whose local +z looks at
+    // `target`, matching the +z forward rays of the renderer
+    pub fn look_at(eye: &Vector3f, target: &Vector3f, up: &Vector3f) -> Matrix4f {
+        let forward = (target - eye).normalize();
+        let right = up.cross(&forward).normalize();
+        let up = forward.cross(&right);
+        Matrix4f {
+            m: [
+                [right.x, up.x, forward.x, eye.x],
+                [right.y, up.y, forward.y, eye.y],
+                [right.z, up.z, forward.z, eye.z],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    // left-handed projection looking down +z, maps [near, far] to [0, 1]
+    pub fn perspective(fov_y: f64, aspect: f64, near: f64, far: f64) -> Matrix4f {
+        let f = 1.0 / f64::tan(fov_y * 0.5);
+        Matrix4f {
+            m: [
+                [f / aspect, 0.0, 0.0, 0.0],
+                [0.0, f, 0.0, 0.0],
+                [0.0, 0.0, far / (far - near), -near * far / (far - near)],
+                [0.0, 0.0, 1.0, 0.0],
+            ],
+        }
+    }
+
+    pub fn transpose(&self) -> Matrix4f {
+        let mut mat = Matrix4f::identity();
+        for (i, row) in mat.m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        mat
+    }
+
+    pub fn inverse(&self) -> Option<Matrix4f> {
+        // Gauss-Jordan elimination with partial pivoting
+        let mut a = self.m;
+        let mut inv = Matrix4f::identity().m;
+        for col in 0..4 {
+            let mut pivot = col;
+            for row in col + 1..4 {
+                if f64::abs(a[row][col]) > f64::abs(a[pivot][col]) {
+                    pivot = row;
+                }
+            }
+            if f64::abs(a[pivot][col]) < f64::EPSILON {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let scale = 1.0 / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= scale;
+                inv[col][j] *= scale;
+            }
+            for row in 0..4 {
+                if row == col {
+                    continue;
+                }
+                let factor = a[row][col];
+                for j in 0..4 {
+                    a[row][j] -= factor * a[col][j];
+                    inv[row][j] -= factor * inv[col][j];
+                }
+            }
+        }
+        Some(Matrix4f { m: inv })
+    }
+
+    pub fn transform_point(&self, p: &Vector3f) -> Vector3f {
+        let m = &self.m;
+        let x = m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3];
+        let y = m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3];
+        let z = m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3];
+        let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+        if w != 1.0 && w != 0.0 {
+            Vector3f::new(x / w, y / w, z / w)
+        } else {
+            Vector3f::new(x, y, z)
+        }
+    }
+
+    pub fn transform_vector(&self, v: &Vector3f) -> Vector3f {
+        let m = &self.m;
+        Vector3f::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+}
+
+impl ops::Mul<Matrix4f> for Matrix4f {
+    type Output = Matrix4f;
+
+    fn mul(self, rhs: Matrix4f) -> Self::Output {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        Matrix4f { m }
+    }
+}
+
+impl Default for Matrix4f {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Display for Matrix4f {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let m = &self.m;
+        write!(f, "[{:?}, {:?}, {:?}, {:?}]", m[0], m[1], m[2], m[3])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx(a: &Matrix4f, b: &Matrix4f) -> bool {
+        (0..4).all(|i| (0..4).all(|j| f64::abs(a.m[i][j] - b.m[i][j]) < 1e-9))
+    }
+
+    fn sample_transform() -> Matrix4f {
+        Matrix4f::from_translation(&Vector3f::new(1.0, -2.0, 3.0))
+            * Matrix4f::from_euler(0.3, -0.7, 1.1)
+            * Matrix4f::from_scale(&Vector3f::new(2.0, 0.5, 1.5))
+    }
+
+    #[test]
+    fn inverse_times_matrix_is_identity() {
+        let m = sample_transform();
+        let inv = m.inverse().unwrap();
+        assert!(approx(&(inv * m), &Matrix4f::identity()));
+        assert!(approx(&(m * inv), &Matrix4f::identity()));
+        assert!(Matrix4f::from_scale(&Vector3f::new(1.0, 0.0, 1.0))
+            .inverse()
+            .is_none());
+    }
+
+    #[test]
+    fn multiplication_is_associative() {
+        let a = sample_transform();
+        let b = Matrix4f::from_rotation_x(0.4) * Matrix4f::from_translation(&Vector3f::scalar(2.0));
+        let c = Matrix4f::perspective(1.0, 1.5, 0.1, 100.0);
+        assert!(approx(&((a * b) * c), &(a * (b * c))));
+    }
+
+    #[test]
+    fn look_at_produces_an_orthonormal_basis() {
+        let eye = Vector3f::new(1.0, 2.0, -5.0);
+        let target = Vector3f::new(-0.5, 0.3, 2.0);
+        let m = Matrix4f::look_at(&eye, &target, &Vector3f::new(0.0, 1.0, 0.0));
+        let basis: Vec<Vector3f> = (0..3)
+            .map(|j| Vector3f::new(m.m[0][j], m.m[1][j], m.m[2][j]))
+            .collect();
+        for i in 0..3 {
+            assert!(f64::abs(basis[i].length() - 1.0) < 1e-9);
+            for j in i + 1..3 {
+                assert!(f64::abs(basis[i].dot(&basis[j])) < 1e-9);
+            }
+        }
+        // local +z looks at the target and the origin maps to the eye
+        let forward = (target - eye).normalize();
+        assert!(basis[2].approx_eq(&forward, 1e-9));
+        assert!(m.transform_point(&Vector3f::zero()).approx_eq(&eye, 1e-9));
+    }
+
+    #[test]
+    fn translation_moves_points_but_not_vectors() {
+        let m = Matrix4f::from_translation(&Vector3f::new(1.0, 2.0, 3.0))
+            * Matrix4f::from_rotation_z(std::f64::consts::FRAC_PI_2);
+        let v = Vector3f::new(1.0, 0.0, 0.0);
+        assert!(m
+            .transform_point(&v)
+            .approx_eq(&Vector3f::new(1.0, 3.0, 3.0), 1e-9));
+        assert!(m
+            .transform_vector(&v)
+            .approx_eq(&Vector3f::new(0.0, 1.0, 0.0), 1e-9));
+    }
+}
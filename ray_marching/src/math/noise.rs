@@ -0,0 +1,81 @@
+use super::{lerp, Vector3f};
+
+// Perlin's improved gradient noise with the permutation table replaced by an
+// integer hash, clamped to [-1, 1]
+pub fn perlin(p: &Vector3f) -> f64 {
+    let (ix, iy, iz) = (f64::floor(p.x), f64::floor(p.y), f64::floor(p.z));
+    let (fx, fy, fz) = (p.x - ix, p.y - iy, p.z - iz);
+    let (ix, iy, iz) = (ix as i64, iy as i64, iz as i64);
+    let corner = |dx: i64, dy: i64, dz: i64| {
+        gradient(
+            hash(ix + dx, iy + dy, iz + dz),
+            fx - dx as f64,
+            fy - dy as f64,
+            fz - dz as f64,
+        )
+    };
+
+    let (u, v, w) = (fade(fx), fade(fy), fade(fz));
+    let x00 = lerp(corner(0, 0, 0), corner(1, 0, 0), u);
+    let x10 = lerp(corner(0, 1, 0), corner(1, 1, 0), u);
+    let x01 = lerp(corner(0, 0, 1), corner(1, 0, 1), u);
+    let x11 = lerp(corner(0, 1, 1), corner(1, 1, 1), u);
+    let noise = lerp(lerp(x00, x10, v), lerp(x01, x11, v), w);
+    f64::clamp(noise, -1.0, 1.0)
+}
+
+// 6t^5 - 15t^4 + 10t^3, flat first and second derivatives at the lattice
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn hash(x: i64, y: i64, z: i64) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6b343)
+        ^ (y as u32).wrapping_mul(0xd8163841)
+        ^ (z as u32).wrapping_mul(0xcb1ab31f);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1e995);
+    h ^ (h >> 15)
+}
+
+// dot product with one of the 12 cube edge directions
+fn gradient(hash: u32, x: f64, y: f64, z: f64) -> f64 {
+    match hash % 12 {
+        0 => x + y,
+        1 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x + z,
+        5 => -x + z,
+        6 => x - z,
+        7 => -x - z,
+        8 => y + z,
+        9 => -y + z,
+        10 => y - z,
+        _ => -y - z,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noise_is_bounded_and_deterministic() {
+        let mut min = f64::MAX;
+        let mut max = f64::MIN;
+        for i in 0..4000 {
+            let t = i as f64;
+            // points spread over many lattice cells, negative ones included
+            let p = Vector3f::new(t * 0.173 - 300.0, t * 0.0917, -t * 0.0531);
+            let n = perlin(&p);
+            assert!((-1.0..=1.0).contains(&n));
+            assert_eq!(n, perlin(&p));
+            min = f64::min(min, n);
+            max = f64::max(max, n);
+        }
+        assert!(min < -0.3 && max > 0.3);
+        // gradient noise vanishes on the lattice
+        assert_eq!(perlin(&Vector3f::new(3.0, -7.0, 12.0)), 0.0);
+    }
+}
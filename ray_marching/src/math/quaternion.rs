@@ -0,0 +1,184 @@
+use std::{fmt::Display, ops};
+
+use super::{Matrix4f, Vector3f};
+
+// unit quaternion rotation, w + xi + yj + zk
+//
+// rotations follow the right-hand rule like Matrix4f::from_rotation_*,
+// e.g. 90 degrees around +y takes +x to -z
+#[derive(Copy, Clone, PartialEq)]
+pub struct Quat {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Quat {
+    pub fn new(w: f64, x: f64, y: f64, z: f64) -> Quat {
+        Quat { w, x, y, z }
+    }
+
+    pub fn identity() -> Quat {
+        Quat::new(1.0, 0.0, 0.0, 0.0)
+    }
+
+    pub fn from_axis_angle(axis: &Vector3f, radian: f64) -> Quat {
+        let axis = axis.normalize();
+        let (s, c) = f64::sin_cos(radian * 0.5);
+        Quat::new(c, axis.x * s, axis.y * s, axis.z * s)
+    }
+
+    pub fn dot(&self, rhs: &Quat) -> f64 {
+        self.w * rhs.w + self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
+    }
+
+    pub fn length(&self) -> f64 {
+        f64::sqrt(self.dot(self))
+    }
+
+    pub fn normalize(&self) -> Quat {
+        let len = self.length();
+        if len > f64::EPSILON {
+            Quat::new(self.w / len, self.x / len, self.y / len, self.z / len)
+        } else {
+            *self
+        }
+    }
+
+    pub fn conjugate(&self) -> Quat {
+        Quat::new(self.w, -self.x, -self.y, -self.z)
+    }
+
+    pub fn rotate_vector(&self, v: &Vector3f) -> Vector3f {
+        // v' = v + 2w(u x v) + 2u x (u x v), u being the vector part
+        let u = Vector3f::new(self.x, self.y, self.z);
+        let t = u.cross(v) * 2.0;
+        *v + t * self.w + u.cross(&t)
+    }
+
+    // spherical interpolation along the shortest arc
+    pub fn slerp(&self, rhs: &Quat, t: f64) -> Quat {
+        let mut cos_theta = self.dot(rhs);
+        let mut end = *rhs;
+        if cos_theta < 0.0 {
+            cos_theta = -cos_theta;
+            end = Quat::new(-rhs.w, -rhs.x, -rhs.y, -rhs.z);
+        }
+
+        // nearly parallel, fall back to a normalized lerp
+        if cos_theta > 1.0 - 1e-6 {
+            return Quat::new(
+                self.w + (end.w - self.w) * t,
+                self.x + (end.x - self.x) * t,
+                self.y + (end.y - self.y) * t,
+                self.z + (end.z - self.z) * t,
+            )
+            .normalize();
+        }
+
+        let theta = f64::acos(cos_theta);
+        let sin_theta = f64::sin(theta);
+        let a = f64::sin((1.0 - t) * theta) / sin_theta;
+        let b = f64::sin(t * theta) / sin_theta;
+        Quat::new(
+            self.w * a + end.w * b,
+            self.x * a + end.x * b,
+            self.y * a + end.y * b,
+            self.z * a + end.z * b,
+        )
+    }
+
+    pub fn to_matrix(&self) -> Matrix4f {
+        let Quat { w, x, y, z } = *self;
+        Matrix4f::new([
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - w * z),
+                2.0 * (x * z + w * y),
+                0.0,
+            ],
+            [
+                2.0 * (x * y + w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - w * x),
+                0.0,
+            ],
+            [
+                2.0 * (x * z - w * y),
+                2.0 * (y * z + w * x),
+                1.0 - 2.0 * (x * x + y * y),
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+}
+
+// Hamilton product, (a * b) rotates by b first and then by a
+impl ops::Mul<Quat> for Quat {
+    type Output = Quat;
+
+    fn mul(self, rhs: Quat) -> Self::Output {
+        Quat {
+            w: self.w * rhs.w - self.x * rhs.x - self.y * rhs.y - self.z * rhs.z,
+            x: self.w * rhs.x + self.x * rhs.w + self.y * rhs.z - self.z * rhs.y,
+            y: self.w * rhs.y - self.x * rhs.z + self.y * rhs.w + self.z * rhs.x,
+            z: self.w * rhs.z + self.x * rhs.y - self.y * rhs.x + self.z * rhs.w,
+        }
+    }
+}
+
+impl Default for Quat {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Display for Quat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "({}, {}, {}, {})", self.w, self.x, self.y, self.z)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::FRAC_PI_2;
+
+    #[test]
+    fn quarter_turn_around_y_takes_x_to_minus_z() {
+        let q = Quat::from_axis_angle(&Vector3f::new(0.0, 1.0, 0.0), FRAC_PI_2);
+        let x = Vector3f::new(1.0, 0.0, 0.0);
+        let expected = Vector3f::new(0.0, 0.0, -1.0);
+        assert!(q.rotate_vector(&x).approx_eq(&expected, 1e-12));
+        // the same convention as the matrix rotations
+        let m = Matrix4f::from_rotation_y(FRAC_PI_2);
+        assert!(q
+            .to_matrix()
+            .transform_vector(&x)
+            .approx_eq(&m.transform_vector(&x), 1e-12));
+    }
+
+    #[test]
+    fn product_rotates_by_the_right_operand_first() {
+        let a = Quat::from_axis_angle(&Vector3f::new(0.0, 0.0, 1.0), 0.7);
+        let b = Quat::from_axis_angle(&Vector3f::new(1.0, 1.0, 0.0), -1.3);
+        let v = Vector3f::new(0.3, -2.0, 1.5);
+        let expected = a.rotate_vector(&b.rotate_vector(&v));
+        assert!((a * b).rotate_vector(&v).approx_eq(&expected, 1e-12));
+    }
+
+    #[test]
+    fn slerp_hits_both_ends_and_the_half_angle() {
+        let axis = Vector3f::new(0.0, 1.0, 0.0);
+        let a = Quat::from_axis_angle(&axis, 0.2);
+        let b = Quat::from_axis_angle(&axis, 1.4);
+        assert!(a.slerp(&b, 0.0) == a);
+        let end = a.slerp(&b, 1.0);
+        assert!(f64::abs(end.dot(&b) - 1.0) < 1e-12);
+        let half = a.slerp(&b, 0.5);
+        assert!(f64::abs(half.dot(&Quat::from_axis_angle(&axis, 0.8)) - 1.0) < 1e-12);
+        assert!(f64::abs(half.length() - 1.0) < 1e-12);
+    }
+}
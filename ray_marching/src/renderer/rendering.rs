@@ -1,24 +1,56 @@
 use crate::domain::Ray;
-use crate::math::Math;
+use crate::math::Matrix4f;
 use crate::renderer::texture::RenderTextureSetMode;
 use crate::sdf::Scene;
 use crate::{math::Vector3f, renderer::framebuffer::FrameBuffer};
 use indicatif::{ProgressBar, ProgressStyle};
-use nalgebra::{Rotation3, Vector3};
+use rand::Rng;
 
 pub struct Renderer {
     pub fbo: Option<FrameBuffer>,
+    // debug view of the march step count instead of the shaded color
+    pub show_march_steps: bool,
+    // frames averaged into the render target since the camera last moved
+    pub frame_count: u32,
+    last_camera: Option<Matrix4f>,
+    // only pixels with x0 <= x < x1 and y0 <= y < y1 are rendered, the rest
+    // of the render target keeps its contents
+    pub crop: Option<(u32, u32, u32, u32)>,
 }
 
 impl Renderer {
     pub fn new() -> Renderer {
-        Renderer { fbo: None }
+        Renderer {
+            fbo: None,
+            show_march_steps: false,
+            frame_count: 0,
+            last_camera: None,
+            crop: None,
+        }
+    }
+
+    // the crop window clamped to the image, (x0, y0, x1, y1) with the upper
+    // bounds exclusive
+    pub fn crop_window(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        match self.crop {
+            Some((x0, y0, x1, y1)) => {
+                let x0 = u32::min(x0, width);
+                let y0 = u32::min(y0, height);
+                (x0, y0, x1.clamp(x0, width), y1.clamp(y0, height))
+            }
+            None => (0, 0, width, height),
+        }
+    }
+
+    pub fn reset_accumulation(&mut self) {
+        self.frame_count = 0;
     }
 
+    // renders one frame and averages it with the frames before it, so
+    // repeated calls with a still camera progressively refine the image.
+    // a moved camera starts over instead of ghosting the old view
     pub fn render<'a>(
         &mut self,
-        eye: Vector3f,
-        rotation_degrees: Vector3f,
         scene: &'a Scene<'a>,
         silent: bool,
     ) -> Result<(), &'static str> {
@@ -26,13 +58,18 @@ impl Renderer {
             return Err("FBO not set");
         }
 
-        let scale = f64::tan(Math::radian(scene.fov * 0.5));
-        let aspect = scene.width as f64 / scene.height as f64;
-        let eye_pos = eye;
+        let camera_to_world = scene.camera_to_world();
+        if self.last_camera != Some(camera_to_world) {
+            self.reset_accumulation();
+            self.last_camera = Some(camera_to_world);
+        }
+        let frame_count = self.frame_count;
+
+        let (x0, y0, x1, y1) = self.crop_window(scene.width, scene.height);
         let fbo = self.fbo.as_mut().unwrap();
         let rt = fbo.get_render_target();
-        let work_items: Vec<_> = (0..scene.height)
-            .flat_map(|y| (0..scene.width).map(move |x| (x, y)))
+        let work_items: Vec<_> = (y0..y1)
+            .flat_map(|y| (x0..x1).map(move |x| (x, y)))
             .collect();
 
         let m: Option<ProgressBar>;
@@ -55,43 +92,58 @@ impl Renderer {
             m = None;
         }
 
+        let mut rng = rand::thread_rng();
         work_items.iter().for_each(|point| {
             let (i, j) = *point;
 
-            let x = (2.0 * (i as f64 + 0.5) / scene.width as f64 - 1.0) * aspect * scale;
-            let y = (1.0 - 2.0 * (j as f64 + 0.5) / scene.height as f64) * scale;
-            let mut dir = Vector3f::new(x, y, 1.0).normalize();
-            // try to rotate the ray
-            {
-                let dir_a = Vector3::new(dir.x, dir.y, dir.z);
-                let rotation = Rotation3::from_euler_angles(
-                    rotation_degrees.x.to_radians(),
-                    rotation_degrees.z.to_radians(),
-                    rotation_degrees.y.to_radians(),
-                );
-                let dir_a: nalgebra::Matrix<
-                    f64,
-                    nalgebra::Const<3>,
-                    nalgebra::Const<1>,
-                    nalgebra::ArrayStorage<f64, 3, 1>,
-                > = (rotation * dir_a).normalize();
-                dir.x = dir_a.x;
-                dir.y = dir_a.y;
-                dir.z = dir_a.z;
-            }
-            let ray = Ray::new(&eye_pos, &dir, 0.0);
             let mut color = Vector3f::zero();
             for _ in 0..scene.sample_per_pixel {
-                let sample_color = scene.cast_ray(&ray);
+                let offset = Self::sample_offset(&mut rng, scene.sample_per_pixel, frame_count);
+                let ray = Self::primary_ray(scene, &camera_to_world, i, j, offset);
+                let sample_color = if self.show_march_steps {
+                    scene.cast_ray_steps(&ray)
+                } else {
+                    scene.cast_ray(&ray)
+                };
                 color += sample_color / scene.sample_per_pixel;
             }
-            rt.set(i, j, color, RenderTextureSetMode::Add);
+            let weight = 1.0 / (frame_count + 1) as f64;
+            rt.set(i, j, color, RenderTextureSetMode::Blend(weight));
             if !silent {
                 m.as_ref().unwrap().inc(1);
             }
         });
+        self.frame_count += 1;
         Ok(())
     }
+
+    // position of a sample inside its pixel, a single sample of the first
+    // frame stays at the pixel center, more samples are spread over the
+    // pixel for supersampling
+    fn sample_offset(rng: &mut impl Rng, sample_per_pixel: u32, frame_count: u32) -> (f64, f64) {
+        if sample_per_pixel > 1 || frame_count > 0 {
+            (rng.gen::<f64>(), rng.gen::<f64>())
+        } else {
+            (0.5, 0.5)
+        }
+    }
+
+    // world space ray through the point `offset` of the pixel (i, j)
+    fn primary_ray(
+        scene: &Scene,
+        camera_to_world: &Matrix4f,
+        i: u32,
+        j: u32,
+        (dx, dy): (f64, f64),
+    ) -> Ray {
+        let aspect = scene.width as f64 / scene.height as f64;
+        let x = 2.0 * (i as f64 + dx) / scene.width as f64 - 1.0;
+        let y = 1.0 - 2.0 * (j as f64 + dy) / scene.height as f64;
+        let (offset, dir) = scene.projection.camera_ray(x, y, aspect);
+        let origin = camera_to_world.transform_point(&offset);
+        let dir = camera_to_world.transform_vector(&dir).normalize();
+        Ray::new(&origin, &dir, 0.0)
+    }
 }
 
 impl Default for Renderer {
@@ -99,3 +151,191 @@ impl Default for Renderer {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::PBRMaterial;
+    use crate::sdf::primitive::Sphere;
+    use crate::sdf::Projection;
+    use std::rc::Rc;
+
+    #[test]
+    fn jittered_samples_stay_inside_their_pixel() {
+        let mut scene = Scene::new(8, 6, 60.0, 4, Vector3f::zero());
+        scene.look_at(Vector3f::zero(), Vector3f::new(0.0, 0.0, 1.0));
+        let camera_to_world = scene.camera_to_world();
+        // film coordinates of the ray through a point of the pixel, both
+        // grow towards its bottom right corner
+        let film = |offset: (f64, f64)| {
+            let ray = Renderer::primary_ray(&scene, &camera_to_world, 5, 2, offset);
+            (
+                ray.direction.x / ray.direction.z,
+                -ray.direction.y / ray.direction.z,
+            )
+        };
+        let (x0, y0) = film((0.0, 0.0));
+        let (x1, y1) = film((1.0, 1.0));
+
+        let mut rng = rand::thread_rng();
+        let offsets: Vec<_> = (0..16)
+            .map(|_| Renderer::sample_offset(&mut rng, scene.sample_per_pixel, 0))
+            .collect();
+        for offset in &offsets {
+            let (x, y) = film(*offset);
+            assert!(x0 <= x && x < x1 && y0 <= y && y < y1);
+        }
+        // the samples of a pixel are different rays
+        assert!(offsets.iter().any(|offset| *offset != offsets[0]));
+        // a lone sample of the first frame goes through the center
+        assert_eq!(Renderer::sample_offset(&mut rng, 1, 0), (0.5, 0.5));
+    }
+
+    #[test]
+    fn moving_the_camera_restarts_the_accumulation() {
+        let white = Rc::new(PBRMaterial {
+            albedo: Vector3f::zero(),
+            emission: Vector3f::scalar(1.0),
+            metallic: 0.0,
+            roughness: 1.0,
+            ao: 0.0,
+        });
+        // an emissive sphere in an otherwise black scene, seen from `target`
+        let render = |renderer: &mut Renderer, target: Vector3f| {
+            let mut scene = Scene::new(4, 4, 60.0, 1, Vector3f::zero());
+            scene.lights.clear();
+            scene.look_at(Vector3f::zero(), target);
+            let node = scene.add_leaf_node(
+                Box::new(Sphere {
+                    center: Vector3f::new(0.0, 0.0, 5.0),
+                    radius: 3.0,
+                }),
+                white.clone(),
+            );
+            scene.add_root_node(node);
+            renderer.render(&scene, true).unwrap();
+        };
+        let center = |renderer: &mut Renderer| {
+            let rt = renderer.fbo.as_mut().unwrap().get_render_target();
+            rt.get_color_attachment()[2][2]
+        };
+
+        let mut renderer = Renderer::new();
+        renderer.fbo = Some(FrameBuffer::new(4, 4));
+        render(&mut renderer, Vector3f::new(0.0, 0.0, 1.0));
+        render(&mut renderer, Vector3f::new(0.0, 0.0, 1.0));
+        assert_eq!(renderer.frame_count, 2);
+        assert!(center(&mut renderer).x > 0.0);
+
+        // looking away replaces the sphere instead of fading it out
+        render(&mut renderer, Vector3f::new(0.0, 0.0, -1.0));
+        assert_eq!(renderer.frame_count, 1);
+        assert!(center(&mut renderer) == Vector3f::zero());
+    }
+
+    #[test]
+    fn orthographic_primary_rays_are_parallel() {
+        let mut scene = Scene::new(8, 6, 60.0, 1, Vector3f::zero());
+        scene.projection = Projection::Orthographic { height: 3.0 };
+        scene.look_at(Vector3f::new(1.0, 2.0, -3.0), Vector3f::new(-1.0, 0.5, 2.0));
+        let camera_to_world = scene.camera_to_world();
+        let forward = (scene.target - scene.eye).normalize();
+        let center = Renderer::primary_ray(&scene, &camera_to_world, 4, 3, (0.0, 0.0));
+        assert!(center.direction.approx_eq(&forward, 1e-12));
+        for (i, j) in [(0, 0), (7, 0), (2, 5), (7, 5)] {
+            let ray = Renderer::primary_ray(&scene, &camera_to_world, i, j, (0.5, 0.5));
+            assert!(ray.direction.approx_eq(&center.direction, 1e-12));
+            // the origins lie on a plane facing the view direction
+            assert!(f64::abs((ray.origin - center.origin).dot(&forward)) < 1e-12);
+            assert!((ray.origin - center.origin).length() > 0.1);
+        }
+    }
+
+    #[test]
+    fn march_steps_grow_towards_grazing_hits() {
+        let mut scene = Scene::new(8, 8, 60.0, 1, Vector3f::zero());
+        scene.lights.clear();
+        let white = Rc::new(PBRMaterial {
+            albedo: Vector3f::scalar(1.0),
+            emission: Vector3f::zero(),
+            metallic: 0.0,
+            roughness: 1.0,
+            ao: 0.0,
+        });
+        let node = scene.add_leaf_node(
+            Box::new(Sphere {
+                center: Vector3f::new(0.0, 0.0, 5.0),
+                radius: 1.0,
+            }),
+            white,
+        );
+        scene.add_root_node(node);
+
+        // parallel rays closer and closer to the silhouette of the sphere
+        let steps: Vec<u32> = [0.0, 0.9, 0.99, 0.999]
+            .iter()
+            .map(|y| {
+                let ray = Ray::new(
+                    &Vector3f::new(0.0, *y, 0.0),
+                    &Vector3f::new(0.0, 0.0, 1.0),
+                    0.0,
+                );
+                let (hit, steps) = scene.ray_march(&ray, scene.max_dist);
+                assert!(hit.shape_op.is_some());
+                steps
+            })
+            .collect();
+        assert!(steps.windows(2).all(|pair| pair[0] < pair[1]));
+
+        // the heat view colors the grazing hit further up the ramp
+        let heat = |y: f64| {
+            let ray = Ray::new(
+                &Vector3f::new(0.0, y, 0.0),
+                &Vector3f::new(0.0, 0.0, 1.0),
+                0.0,
+            );
+            scene.cast_ray_steps(&ray)
+        };
+        assert!(heat(0.999) != heat(0.0));
+    }
+
+    #[test]
+    fn crop_leaves_the_outside_pixels_untouched() {
+        let mut scene = Scene::new(6, 6, 60.0, 1, Vector3f::zero());
+        scene.look_at(Vector3f::zero(), Vector3f::new(0.0, 0.0, 1.0));
+        let node = scene.add_leaf_node(
+            Box::new(Sphere {
+                center: Vector3f::new(0.0, 0.0, 5.0),
+                radius: 2.0,
+            }),
+            Rc::new(PBRMaterial {
+                albedo: Vector3f::scalar(0.5),
+                emission: Vector3f::zero(),
+                metallic: 0.0,
+                roughness: 1.0,
+                ao: 0.0,
+            }),
+        );
+        scene.add_root_node(node);
+
+        let mut renderer = Renderer::new();
+        renderer.crop = Some((1, 2, 4, 3));
+        renderer.fbo = Some(FrameBuffer::new(6, 6));
+        let sentinel = Vector3f::new(-1.0, 2.0, -3.0);
+        let rt = renderer.fbo.as_mut().unwrap().get_render_target();
+        for y in 0..6 {
+            for x in 0..6 {
+                rt.set(x, y, sentinel, RenderTextureSetMode::Overwrite);
+            }
+        }
+        renderer.render(&scene, true).unwrap();
+
+        let rt = renderer.fbo.as_mut().unwrap().get_render_target();
+        for (y, row) in rt.get_color_attachment().iter().enumerate() {
+            for (x, color) in row.iter().enumerate() {
+                let inside = (1..4).contains(&x) && y == 2;
+                assert_eq!(*color == sentinel, !inside);
+            }
+        }
+    }
+}
@@ -1,11 +1,12 @@
-use crate::math::Vector3f;
+use crate::math::{lerp, Vector3f};
 use std::{fs::File, io::Write};
 pub type Bitmap2D = Vec<Vec<Vector3f>>;
 
 pub enum RenderTextureSetMode {
     Overwrite,
     Add,
-    // Blend
+    // moves the stored color towards the new one by the given weight
+    Blend(f64),
 }
 
 pub struct RenderTexture {
@@ -31,6 +32,10 @@ impl RenderTexture {
             RenderTextureSetMode::Add => {
                 self.buffer[y as usize][x as usize] += color;
             }
+            RenderTextureSetMode::Blend(weight) => {
+                let current = self.buffer[y as usize][x as usize];
+                self.buffer[y as usize][x as usize] = lerp(current, color, weight);
+            }
         }
     }
 
@@ -42,18 +47,18 @@ impl RenderTexture {
         let buffer: Vec<u32> = self
             .buffer
             .iter()
-            .flatten()
-            .map(|v| {
+            .enumerate()
+            .flat_map(|(y, row)| row.iter().enumerate().map(move |(x, v)| (x, y, v)))
+            .map(|(x, y, v)| {
                 let mut v = *v;
                 if need_gamma {
                     v.x = gamma(v.x);
                     v.y = gamma(v.y);
                     v.z = gamma(v.z);
                 }
-                let r = (v.x * 255.0) as u32;
-                let g = (v.y * 255.0) as u32;
-                let b = (v.z * 255.0) as u32;
-                (r << 16) | (g << 8) | b
+                let threshold = bayer_threshold(x as u32, y as u32);
+                let quantize = |c: f64| f64::clamp(c * 255.0 + threshold, 0.0, 255.0) as u32;
+                (quantize(v.x) << 16) | (quantize(v.y) << 8) | quantize(v.z)
             })
             .collect();
         buffer
@@ -75,9 +80,9 @@ impl RenderTexture {
             for x in 0..self.width {
                 let colors = &self.buffer[y as usize][x as usize];
                 let buf: [u8; 3] = [
-                    self.encode_color_component(colors.x),
-                    self.encode_color_component(colors.y),
-                    self.encode_color_component(colors.z),
+                    self.encode_color_component(colors.x, x, y),
+                    self.encode_color_component(colors.y, x, y),
+                    self.encode_color_component(colors.z, x, y),
                 ];
                 file.write_all(&buf)?;
             }
@@ -85,13 +90,53 @@ impl RenderTexture {
         Ok(())
     }
 
-    fn encode_color_component(&self, c: f64) -> u8 {
+    // quantizes against a 4x4 Bayer threshold instead of truncating, which
+    // spreads the error of smooth gradients into a fine pattern rather than
+    // bands while keeping the average value
+    fn encode_color_component(&self, c: f64, x: u32, y: u32) -> u8 {
         let val = f64::clamp(c, 0.0, 1.0);
         let result = 255.0 * f64::powf(val, 0.6);
-        result as u8
+        f64::min(result + bayer_threshold(x, y), 255.0) as u8
     }
 }
 
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+// ordered dither threshold of pixel (x, y) in (0, 1)
+fn bayer_threshold(x: u32, y: u32) -> f64 {
+    (f64::from(BAYER_4X4[(y % 4) as usize][(x % 4) as usize]) + 0.5) / 16.0
+}
+
 fn gamma(c: f64) -> f64 {
     f64::powf(c, 1.0 / 2.2)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dithering_keeps_the_mean_of_a_gradient() {
+        let texture = RenderTexture::new(1, 1);
+        // a ramp a few levels high over 64 pixels, bands when truncated.
+        // it steps once per 4x4 block so every block sees all 16 thresholds
+        let value = |x: u32| 0.4 + 0.02 * (x / 4) as f64 / 15.0;
+        let mut patterned = false;
+        for block in 0..16 {
+            let mut sum = 0.0;
+            let mut expected = 0.0;
+            let mut levels = vec![];
+            for y in 0..4 {
+                for x in block * 4..block * 4 + 4 {
+                    let level = texture.encode_color_component(value(x), x, y);
+                    levels.push(level);
+                    sum += f64::from(level);
+                    expected += 255.0 * f64::powf(value(x), 0.6);
+                }
+            }
+            assert!(f64::abs(sum - expected) / 16.0 <= 1.0 / 32.0 + 1e-9);
+            patterned |= levels.iter().any(|level| *level != levels[0]);
+        }
+        assert!(patterned);
+    }
+}
@@ -0,0 +1,63 @@
+use std::fmt::Display;
+
+use crate::math::Vector3f;
+
+#[derive(Clone, Copy)]
+pub struct Bounds3 {
+    pub p_min: Vector3f,
+    pub p_max: Vector3f,
+}
+
+impl Bounds3 {
+    pub fn new(p_min: Vector3f, p_max: Vector3f) -> Bounds3 {
+        Bounds3 { p_min, p_max }
+    }
+
+    pub fn from_center_extent(center: &Vector3f, extent: &Vector3f) -> Bounds3 {
+        Bounds3 {
+            p_min: center - extent,
+            p_max: center + extent,
+        }
+    }
+
+    pub fn union(&mut self, b: &Bounds3) {
+        self.p_min = Vector3f::min(&self.p_min, &b.p_min);
+        self.p_max = Vector3f::max(&self.p_max, &b.p_max);
+    }
+
+    pub fn center(&self) -> Vector3f {
+        self.p_min * 0.5 + self.p_max * 0.5
+    }
+
+    pub fn diagonal(&self) -> Vector3f {
+        self.p_max - self.p_min
+    }
+
+    pub fn expand(&self, delta: f64) -> Bounds3 {
+        let delta = Vector3f::scalar(delta);
+        Bounds3::new(self.p_min - delta, self.p_max + delta)
+    }
+
+    // euclidean distance from p to the box, 0 inside
+    pub fn distance(&self, p: &Vector3f) -> f64 {
+        let dx = f64::max(f64::max(self.p_min.x - p.x, p.x - self.p_max.x), 0.0);
+        let dy = f64::max(f64::max(self.p_min.y - p.y, p.y - self.p_max.y), 0.0);
+        let dz = f64::max(f64::max(self.p_min.z - p.z, p.z - self.p_max.z), 0.0);
+        f64::sqrt(dx * dx + dy * dy + dz * dz)
+    }
+
+    pub fn contains(&self, p: &Vector3f) -> bool {
+        p.x >= self.p_min.x
+            && p.x <= self.p_max.x
+            && p.y >= self.p_min.y
+            && p.y <= self.p_max.y
+            && p.z >= self.p_min.z
+            && p.z <= self.p_max.z
+    }
+}
+
+impl Display for Bounds3 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "(min={}, max={})", self.p_min, self.p_max)
+    }
+}
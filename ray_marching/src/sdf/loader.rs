@@ -0,0 +1,611 @@
+use std::fs;
+use std::rc::Rc;
+
+use serde::Deserialize;
+
+use crate::light::{Light, SpotLight};
+use crate::material::PBRMaterial;
+use crate::math::{Matrix4f, Vector2f, Vector3f};
+
+use super::mesh::Mesh;
+use super::modifier::{Bend, Displaced, Instanced, Onion, Rounded, Twist};
+use super::primitive::{
+    Cube, CubeFrame, DeathStar, Helix, HexPrism, Link, Octahedron, Sphere, Torus, TriPrism,
+};
+use super::{NormalMethod, Projection, Scene, Shape, ShapeOp, ShapeOpType};
+
+// Scene description loaded from JSON. Nodes borrow the scene they live in,
+// so loading is split into `create_scene` and `populate`:
+//
+//     let file = SceneFile::from_json("scene.json")?;
+//     let scene = file.create_scene();
+//     file.populate(&scene)?;
+#[derive(Deserialize)]
+pub struct SceneFile {
+    pub width: u32,
+    pub height: u32,
+    pub fov: f64,
+    // defaults to the camera of Scene::new
+    #[serde(default)]
+    pub eye: Option<[f64; 3]>,
+    #[serde(default)]
+    pub target: Option<[f64; 3]>,
+    // switches to an orthographic camera of this view height
+    #[serde(default)]
+    pub ortho_height: Option<f64>,
+    #[serde(default = "default_sample_per_pixel")]
+    pub sample_per_pixel: u32,
+    pub background: [f64; 3],
+    #[serde(default = "default_hdr_compression")]
+    pub hdr_compression: bool,
+    #[serde(default = "default_exposure")]
+    pub exposure: f64,
+    #[serde(default)]
+    pub max_steps: Option<u32>,
+    #[serde(default)]
+    pub march_accuracy: Option<f64>,
+    #[serde(default)]
+    pub max_dist: Option<f64>,
+    #[serde(default)]
+    pub max_reflection_depth: Option<u32>,
+    #[serde(default)]
+    pub reflection_samples: Option<u32>,
+    #[serde(default)]
+    pub gi_samples: u32,
+    #[serde(default)]
+    pub normal_method: NormalMethod,
+    #[serde(default)]
+    pub fog_color: Option<[f64; 3]>,
+    #[serde(default)]
+    pub fog_density: f64,
+    // replaces the default sun of Scene::new when given
+    #[serde(default)]
+    pub lights: Option<Vec<LightDesc>>,
+    #[serde(default)]
+    pub spot_lights: Vec<SpotLightDesc>,
+    pub materials: Vec<MaterialDesc>,
+    pub nodes: Vec<NodeDesc>,
+    pub roots: Vec<usize>,
+    #[serde(default)]
+    pub ground: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+pub enum LightDesc {
+    Directional {
+        direction: [f64; 3],
+        radiance: [f64; 3],
+    },
+    Point {
+        position: [f64; 3],
+        intensity: [f64; 3],
+        // unbounded when omitted
+        #[serde(default)]
+        radius: Option<f64>,
+    },
+}
+
+#[derive(Deserialize)]
+pub struct SpotLightDesc {
+    pub position: [f64; 3],
+    pub direction: [f64; 3],
+    pub inner_angle: f64,
+    pub outer_angle: f64,
+    pub color: [f64; 3],
+}
+
+#[derive(Deserialize)]
+pub struct MaterialDesc {
+    pub albedo: [f64; 3],
+    #[serde(default)]
+    pub emission: [f64; 3],
+    pub metallic: f64,
+    pub roughness: f64,
+    #[serde(default)]
+    pub ao: f64,
+}
+
+#[derive(Deserialize)]
+pub struct NodeDesc {
+    pub shape: ShapeDesc,
+    pub material: usize,
+    #[serde(default = "default_op")]
+    pub op: ShapeOpType,
+    #[serde(default)]
+    pub next: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+pub enum ShapeDesc {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+    },
+    Cube {
+        center: [f64; 3],
+        most_front_up_right: [f64; 3],
+    },
+    CubeFrame {
+        center: [f64; 3],
+        bounds: [f64; 3],
+        thinkness: f64,
+    },
+    Torus {
+        center: [f64; 3],
+        outer_radius: f64,
+        inner_radius: f64,
+    },
+    DeathStar {
+        center: [f64; 3],
+        ra: f64,
+        rb: f64,
+        d: f64,
+        #[serde(default)]
+        rotate_y: f64,
+    },
+    Helix {
+        center: [f64; 3],
+        fr: f64,
+        r1: f64,
+        r2: f64,
+    },
+    Octahedron {
+        center: [f64; 3],
+        s: f64,
+    },
+    Link {
+        center: [f64; 3],
+        le: f64,
+        r1: f64,
+        r2: f64,
+    },
+    HexPrism {
+        center: [f64; 3],
+        h: [f64; 2],
+    },
+    TriPrism {
+        center: [f64; 3],
+        h: [f64; 2],
+    },
+    Rounded {
+        inner: Box<ShapeDesc>,
+        radius: f64,
+    },
+    Onion {
+        inner: Box<ShapeDesc>,
+        thickness: f64,
+    },
+    Displaced {
+        inner: Box<ShapeDesc>,
+        amplitude: f64,
+        frequency: f64,
+    },
+    Twist {
+        inner: Box<ShapeDesc>,
+        k: f64,
+    },
+    Bend {
+        inner: Box<ShapeDesc>,
+        k: f64,
+    },
+    Instanced {
+        inner: Box<ShapeDesc>,
+        instances: Vec<InstanceDesc>,
+    },
+    Mesh {
+        path: String,
+        #[serde(default)]
+        center: [f64; 3],
+        #[serde(default = "default_scale")]
+        scale: f64,
+    },
+}
+
+// placement of one copy of an instanced shape, scaled, then rotated around
+// x, y and z (radians), then translated
+#[derive(Deserialize)]
+pub struct InstanceDesc {
+    pub translation: [f64; 3],
+    #[serde(default)]
+    pub rotation: [f64; 3],
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+}
+
+impl InstanceDesc {
+    fn to_matrix(&self) -> Matrix4f {
+        Matrix4f::from_translation(&vec3(&self.translation))
+            * Matrix4f::from_euler(self.rotation[0], self.rotation[1], self.rotation[2])
+            * Matrix4f::from_scale(&Vector3f::scalar(self.scale))
+    }
+}
+
+fn default_sample_per_pixel() -> u32 {
+    1
+}
+
+fn default_hdr_compression() -> bool {
+    true
+}
+
+fn default_exposure() -> f64 {
+    1.0
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+fn default_op() -> ShapeOpType {
+    ShapeOpType::Nop
+}
+
+fn vec3(v: &[f64; 3]) -> Vector3f {
+    Vector3f::new(v[0], v[1], v[2])
+}
+
+impl ShapeDesc {
+    fn to_shape(&self) -> Result<Box<dyn Shape>, String> {
+        Ok(match self {
+            ShapeDesc::Sphere { center, radius } => Box::new(Sphere {
+                center: vec3(center),
+                radius: *radius,
+            }),
+            ShapeDesc::Cube {
+                center,
+                most_front_up_right,
+            } => Box::new(Cube {
+                center: vec3(center),
+                most_front_up_right: vec3(most_front_up_right),
+            }),
+            ShapeDesc::CubeFrame {
+                center,
+                bounds,
+                thinkness,
+            } => Box::new(CubeFrame {
+                center: vec3(center),
+                bounds: vec3(bounds),
+                thinkness: *thinkness,
+            }),
+            ShapeDesc::Torus {
+                center,
+                outer_radius,
+                inner_radius,
+            } => Box::new(Torus {
+                center: vec3(center),
+                outer_radius: *outer_radius,
+                inner_radius: *inner_radius,
+            }),
+            ShapeDesc::DeathStar {
+                center,
+                ra,
+                rb,
+                d,
+                rotate_y,
+            } => Box::new(DeathStar {
+                center: vec3(center),
+                ra: *ra,
+                rb: *rb,
+                d: *d,
+                rotate_y: *rotate_y,
+            }),
+            ShapeDesc::Helix { center, fr, r1, r2 } => Box::new(Helix {
+                center: vec3(center),
+                fr: *fr,
+                r1: *r1,
+                r2: *r2,
+            }),
+            ShapeDesc::Octahedron { center, s } => Box::new(Octahedron {
+                center: vec3(center),
+                s: *s,
+            }),
+            ShapeDesc::Link { center, le, r1, r2 } => Box::new(Link {
+                center: vec3(center),
+                le: *le,
+                r1: *r1,
+                r2: *r2,
+            }),
+            ShapeDesc::HexPrism { center, h } => Box::new(HexPrism {
+                center: vec3(center),
+                h: Vector2f::new(h[0], h[1]),
+            }),
+            ShapeDesc::TriPrism { center, h } => Box::new(TriPrism {
+                center: vec3(center),
+                h: Vector2f::new(h[0], h[1]),
+            }),
+            ShapeDesc::Rounded { inner, radius } => Box::new(Rounded {
+                inner: inner.to_shape()?,
+                radius: *radius,
+            }),
+            ShapeDesc::Onion { inner, thickness } => Box::new(Onion {
+                inner: inner.to_shape()?,
+                thickness: *thickness,
+            }),
+            ShapeDesc::Displaced {
+                inner,
+                amplitude,
+                frequency,
+            } => Box::new(Displaced {
+                inner: inner.to_shape()?,
+                amplitude: *amplitude,
+                frequency: *frequency,
+            }),
+            ShapeDesc::Twist { inner, k } => Box::new(Twist::new(inner.to_shape()?, *k)?),
+            ShapeDesc::Bend { inner, k } => Box::new(Bend::new(inner.to_shape()?, *k)?),
+            ShapeDesc::Instanced { inner, instances } => {
+                let transforms: Vec<_> = instances.iter().map(InstanceDesc::to_matrix).collect();
+                Box::new(Instanced::new(inner.to_shape()?, &transforms)?)
+            }
+            ShapeDesc::Mesh {
+                path,
+                center,
+                scale,
+            } => Box::new(Mesh::from_obj(path, &vec3(center), *scale)?),
+        })
+    }
+}
+
+impl SceneFile {
+    pub fn from_json(path: &str) -> Result<SceneFile, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("cannot read scene file {}: {}", path, err))?;
+        let file: SceneFile = serde_json::from_str(&text)
+            .map_err(|err| format!("invalid scene file {}: {}", path, err))?;
+        file.validate()?;
+        Ok(file)
+    }
+
+    pub fn create_scene<'a>(&self) -> Scene<'a> {
+        let mut scene = Scene::new(
+            self.width,
+            self.height,
+            self.fov,
+            self.sample_per_pixel,
+            vec3(&self.background),
+        );
+        scene.hdr_compression = self.hdr_compression;
+        scene.exposure = self.exposure;
+        if let Some(eye) = &self.eye {
+            scene.eye = vec3(eye);
+        }
+        if let Some(target) = &self.target {
+            scene.target = vec3(target);
+        }
+        if let Some(height) = self.ortho_height {
+            scene.projection = Projection::Orthographic { height };
+        }
+        if let Some(max_steps) = self.max_steps {
+            scene.max_steps = max_steps;
+        }
+        if let Some(march_accuracy) = self.march_accuracy {
+            scene.march_accuracy = march_accuracy;
+        }
+        if let Some(max_dist) = self.max_dist {
+            scene.max_dist = max_dist;
+        }
+        if let Some(max_reflection_depth) = self.max_reflection_depth {
+            scene.max_reflection_depth = max_reflection_depth;
+        }
+        if let Some(reflection_samples) = self.reflection_samples {
+            scene.reflection_samples = reflection_samples;
+        }
+        scene.gi_samples = self.gi_samples;
+        scene.normal_method = self.normal_method;
+        if let Some(fog_color) = &self.fog_color {
+            scene.fog_color = vec3(fog_color);
+        }
+        scene.fog_density = self.fog_density;
+        if let Some(lights) = &self.lights {
+            scene.lights = lights
+                .iter()
+                .map(|light| match light {
+                    LightDesc::Directional {
+                        direction,
+                        radiance,
+                    } => Light::Directional {
+                        direction: vec3(direction),
+                        radiance: vec3(radiance),
+                    },
+                    LightDesc::Point {
+                        position,
+                        intensity,
+                        radius,
+                    } => Light::Point {
+                        position: vec3(position),
+                        intensity: vec3(intensity),
+                        radius: radius.unwrap_or(f64::INFINITY),
+                    },
+                })
+                .collect();
+        }
+        scene.lights.extend(self.spot_lights.iter().map(|light| {
+            Light::Spot(SpotLight::new(
+                vec3(&light.position),
+                vec3(&light.direction),
+                light.inner_angle,
+                light.outer_angle,
+                vec3(&light.color),
+            ))
+        }));
+        scene
+    }
+
+    pub fn populate<'a>(&self, scene: &'a Scene<'a>) -> Result<(), String> {
+        self.validate()?;
+        let materials: Vec<Rc<PBRMaterial>> = self
+            .materials
+            .iter()
+            .map(|m| {
+                Rc::new(PBRMaterial {
+                    albedo: vec3(&m.albedo),
+                    emission: vec3(&m.emission),
+                    metallic: m.metallic,
+                    roughness: m.roughness,
+                    ao: m.ao,
+                })
+            })
+            .collect();
+
+        // a node can only be created once its `next` exists, so walk the
+        // chains depth-first regardless of the order they appear in the file
+        let mut created: Vec<Option<&'a ShapeOp<'a>>> = vec![None; self.nodes.len()];
+        for index in 0..self.nodes.len() {
+            let mut chain = vec![];
+            let mut cur = Some(index);
+            while let Some(i) = cur {
+                if created[i].is_some() {
+                    break;
+                }
+                chain.push(i);
+                cur = self.nodes[i].next;
+            }
+            for &i in chain.iter().rev() {
+                let node = &self.nodes[i];
+                let material = Rc::clone(&materials[node.material]);
+                let shape = node
+                    .shape
+                    .to_shape()
+                    .map_err(|err| format!("node {}: {}", i, err))?;
+                created[i] = Some(match node.next {
+                    Some(next) => scene.add_node(shape, material, node.op, created[next]),
+                    None => scene.add_leaf_node(shape, material),
+                });
+            }
+        }
+
+        for &root in self.roots.iter() {
+            scene.add_root_node(created[root].unwrap());
+        }
+        if let Some(ground) = self.ground {
+            scene.set_ground(created[ground].unwrap());
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let n_nodes = self.nodes.len();
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.material >= self.materials.len() {
+                return Err(format!(
+                    "node {} references material {}, but only {} materials exist",
+                    i,
+                    node.material,
+                    self.materials.len()
+                ));
+            }
+            if let Some(next) = node.next {
+                if next >= n_nodes {
+                    return Err(format!(
+                        "node {} references next node {}, but only {} nodes exist",
+                        i, next, n_nodes
+                    ));
+                }
+                if let ShapeOpType::Nop = node.op {
+                    return Err(format!("node {} has a next node but no op", i));
+                }
+                if let ShapeOpType::SmoothSubtraction(k) | ShapeOpType::SmoothIntersection(k) =
+                    node.op
+                {
+                    if k <= 0.0 {
+                        return Err(format!("node {} has a non-positive blend radius {}", i, k));
+                    }
+                }
+            }
+
+            // chains must terminate, otherwise the nodes could never be built
+            let mut steps = 0;
+            let mut cur = node.next;
+            while let Some(next) = cur {
+                steps += 1;
+                if steps > n_nodes {
+                    return Err(format!("node {} is part of a cyclic next chain", i));
+                }
+                cur = self.nodes[next].next;
+            }
+        }
+
+        for &root in self.roots.iter() {
+            if root >= n_nodes {
+                return Err(format!(
+                    "root {} is out of range, only {} nodes exist",
+                    root, n_nodes
+                ));
+            }
+        }
+        if let Some(ground) = self.ground {
+            if ground >= n_nodes {
+                return Err(format!(
+                    "ground {} is out of range, only {} nodes exist",
+                    ground, n_nodes
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scene_file(materials: usize, nodes: &str) -> SceneFile {
+        let materials =
+            vec![r#"{ "albedo": [1, 1, 1], "metallic": 0, "roughness": 1 }"#; materials];
+        let json = format!(
+            r#"{{ "width": 4, "height": 4, "fov": 60, "background": [0, 0, 0],
+                  "materials": [{}], "nodes": [{}], "roots": [0] }}"#,
+            materials.join(","),
+            nodes
+        );
+        serde_json::from_str(&json).unwrap()
+    }
+
+    fn sphere_node(material: usize, op: &str, next: Option<usize>) -> String {
+        let op = if op.is_empty() {
+            String::new()
+        } else {
+            format!(r#", "op": "{}""#, op)
+        };
+        let next = next.map_or(String::new(), |next| format!(r#", "next": {}"#, next));
+        format!(
+            r#"{{ "shape": {{ "type": "Sphere", "center": [0, 0, 0], "radius": 1 }}, "material": {}{}{} }}"#,
+            material, op, next
+        )
+    }
+
+    #[test]
+    fn loads_the_two_sphere_subtraction_scene() {
+        let file = SceneFile::from_json("resource/two_spheres.json").unwrap();
+        let scene = file.create_scene();
+        file.populate(&scene).unwrap();
+        assert_eq!(scene.nodes.len(), 3);
+        assert_eq!(scene.root_nodes.len(), 2);
+        assert!(scene.is_ground(&scene.nodes[0]));
+        assert_eq!((scene.width, scene.height), (720, 405));
+    }
+
+    #[test]
+    fn rejects_a_bad_material_index() {
+        let file = scene_file(1, &sphere_node(1, "", None));
+        assert!(file.validate().unwrap_err().contains("material 1"));
+    }
+
+    #[test]
+    fn rejects_a_cyclic_next_chain() {
+        let nodes = [
+            sphere_node(0, "Union", Some(1)),
+            sphere_node(0, "Union", Some(0)),
+        ];
+        let file = scene_file(1, &nodes.join(","));
+        assert!(file.validate().unwrap_err().contains("cyclic"));
+    }
+
+    #[test]
+    fn rejects_next_without_an_op() {
+        let nodes = [sphere_node(0, "", Some(1)), sphere_node(0, "", None)];
+        let file = scene_file(1, &nodes.join(","));
+        assert!(file.validate().unwrap_err().contains("no op"));
+    }
+}
@@ -0,0 +1,182 @@
+use crate::math::Vector3f;
+use core::fmt;
+use std::f64::consts::PI;
+use std::fmt::Display;
+
+use super::{bounds::Bounds3, Shape, ShapeType};
+
+// signed distance to a triangle mesh, the magnitude is the distance to the
+// closest triangle and the sign comes from the generalized winding number,
+// so slightly open meshes still have a sensible inside
+pub struct Mesh {
+    pub name: String,
+    pub triangles: Vec<[Vector3f; 3]>,
+    bounds: Bounds3,
+}
+
+impl Mesh {
+    pub fn new(name: &str, triangles: Vec<[Vector3f; 3]>) -> Mesh {
+        let mut bounds = Bounds3::new(Vector3f::scalar(f64::MAX), Vector3f::scalar(f64::MIN));
+        for triangle in triangles.iter() {
+            for v in triangle.iter() {
+                bounds.union(&Bounds3::new(*v, *v));
+            }
+        }
+        Mesh {
+            name: String::from(name),
+            triangles,
+            bounds,
+        }
+    }
+
+    // loads every mesh of an OBJ file, scaled around the origin and then
+    // moved to `center`
+    pub fn from_obj(path: &str, center: &Vector3f, scale: f64) -> Result<Mesh, String> {
+        let (models, _) = tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS)
+            .map_err(|err| format!("cannot load OBJ file {}: {}", path, err))?;
+        let mut triangles = vec![];
+        for model in models.iter() {
+            let mesh = &model.mesh;
+            let vertex = |index: u32| {
+                let i = index as usize * 3;
+                Vector3f::new(
+                    f64::from(mesh.positions[i]),
+                    f64::from(mesh.positions[i + 1]),
+                    f64::from(mesh.positions[i + 2]),
+                ) * scale
+                    + *center
+            };
+            for face in mesh.indices.chunks_exact(3) {
+                triangles.push([vertex(face[0]), vertex(face[1]), vertex(face[2])]);
+            }
+        }
+        if triangles.is_empty() {
+            return Err(format!("OBJ file {} has no triangles", path));
+        }
+        Ok(Mesh::new(path, triangles))
+    }
+
+    pub fn winding_number(&self, p: &Vector3f) -> f64 {
+        let mut solid_angle = 0.0;
+        for [v0, v1, v2] in self.triangles.iter() {
+            // Van Oosterom and Strackee
+            let a = v0 - p;
+            let b = v1 - p;
+            let c = v2 - p;
+            let (la, lb, lc) = (a.length(), b.length(), c.length());
+            let num = a.dot(&b.cross(&c));
+            let den = la * lb * lc + a.dot(&b) * lc + b.dot(&c) * la + c.dot(&a) * lb;
+            solid_angle += 2.0 * f64::atan2(num, den);
+        }
+        solid_angle / (4.0 * PI)
+    }
+
+    // closest point on a triangle, Ericson's Real-Time Collision Detection 5.1.5
+    fn closest_point(p: &Vector3f, a: &Vector3f, b: &Vector3f, c: &Vector3f) -> Vector3f {
+        let ab = b - a;
+        let ac = c - a;
+        let ap = p - a;
+        let d1 = ab.dot(&ap);
+        let d2 = ac.dot(&ap);
+        if d1 <= 0.0 && d2 <= 0.0 {
+            return *a;
+        }
+
+        let bp = p - b;
+        let d3 = ab.dot(&bp);
+        let d4 = ac.dot(&bp);
+        if d3 >= 0.0 && d4 <= d3 {
+            return *b;
+        }
+
+        let vc = d1 * d4 - d3 * d2;
+        if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+            return a + &(ab * (d1 / (d1 - d3)));
+        }
+
+        let cp = p - c;
+        let d5 = ab.dot(&cp);
+        let d6 = ac.dot(&cp);
+        if d6 >= 0.0 && d5 <= d6 {
+            return *c;
+        }
+
+        let vb = d5 * d2 - d1 * d6;
+        if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+            return a + &(ac * (d2 / (d2 - d6)));
+        }
+
+        let va = d3 * d6 - d5 * d4;
+        if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+            return b + &((c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6))));
+        }
+
+        let denom = 1.0 / (va + vb + vc);
+        a + &(ab * (vb * denom)) + ac * (vc * denom)
+    }
+}
+
+impl Shape for Mesh {
+    fn shape_type(&self) -> ShapeType {
+        ShapeType::Mesh
+    }
+
+    fn sdf(&self, p: &Vector3f) -> f64 {
+        // far from the mesh the distance to its bounds is a cheap lower
+        // bound, which is all the marcher needs to take a safe step
+        let bounds_distance = self.bounds.distance(p);
+        if bounds_distance > 1.0 {
+            return bounds_distance;
+        }
+
+        let mut distance_sq = f64::MAX;
+        for [a, b, c] in self.triangles.iter() {
+            let d = p - &Self::closest_point(p, a, b, c);
+            distance_sq = f64::min(distance_sq, d.dot(&d));
+        }
+        let distance = f64::sqrt(distance_sq);
+        // either winding order counts as closed
+        if f64::abs(self.winding_number(p)) > 0.5 {
+            -distance
+        } else {
+            distance
+        }
+    }
+
+    fn bounds(&self) -> Bounds3 {
+        self.bounds
+    }
+}
+
+impl Display for Mesh {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Mesh(name={}, triangles={})",
+            self.name,
+            self.triangles.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_triangle_is_the_distance_to_its_plane() {
+        let triangle = [
+            Vector3f::new(0.0, 0.0, 1.0),
+            Vector3f::new(1.0, 0.0, 1.0),
+            Vector3f::new(0.0, 1.0, 1.0),
+        ];
+        let mesh = Mesh::new("triangle", vec![triangle]);
+        for (x, y, h) in [(0.25, 0.25, 0.5), (0.1, 0.6, -0.7), (0.5, 0.4, 0.01)] {
+            let d = mesh.sdf(&Vector3f::new(x, y, 1.0 + h));
+            assert!(f64::abs(d - f64::abs(h)) < 1e-12);
+        }
+        // outside its projection the closest point is on the boundary
+        let d = mesh.sdf(&Vector3f::new(1.5, 0.0, 1.0));
+        assert!(f64::abs(d - 0.5) < 1e-12);
+    }
+}
@@ -1,14 +1,24 @@
-use crate::material::pbr::pbr_lighting;
+use crate::light::Light;
+use crate::material::checkerboard::Checkerboard;
+use crate::material::pbr::{fresnel_schlick, pbr_lighting};
 use crate::material::PBRMaterial;
-use crate::math::lerp;
+use crate::math::{lerp, Math, Matrix4f};
 use crate::{domain::Ray, math::Vector3f};
+use bounds::Bounds3;
 use core::fmt;
 use elsa::FrozenVec;
+use primitive::Sphere;
 use std::any::Any;
 use std::cell::RefCell;
+use std::f64::consts::PI;
 use std::fmt::Display;
+use serde::Deserialize;
 use std::rc::Rc;
 
+pub mod bounds;
+pub mod loader;
+pub mod mesh;
+pub mod modifier;
 pub mod primitive;
 
 pub enum ShapeType {
@@ -18,6 +28,11 @@ pub enum ShapeType {
     Torus,
     DeathStar,
     Helix,
+    Octahedron,
+    Link,
+    HexPrism,
+    TriPrism,
+    Mesh,
 }
 
 impl Display for ShapeType {
@@ -29,6 +44,11 @@ impl Display for ShapeType {
             ShapeType::Torus => write!(f, "Torus"),
             ShapeType::DeathStar => write!(f, "DeathStar"),
             ShapeType::Helix => write!(f, "Helix"),
+            ShapeType::Octahedron => write!(f, "Octahedron"),
+            ShapeType::Link => write!(f, "Link"),
+            ShapeType::HexPrism => write!(f, "HexPrism"),
+            ShapeType::TriPrism => write!(f, "TriPrism"),
+            ShapeType::Mesh => write!(f, "Mesh"),
         }
     }
 }
@@ -36,17 +56,23 @@ impl Display for ShapeType {
 pub trait Shape: Send + Sync + Display + Any {
     fn shape_type(&self) -> ShapeType;
     fn sdf(&self, p: &Vector3f) -> f64;
+    // conservative AABB enclosing every point where sdf <= 0
+    fn bounds(&self) -> Bounds3;
     fn rotate_ray(&self, ray: &Ray) -> Ray {
         *ray
     }
 }
 
+#[derive(Clone, Copy, Deserialize)]
 pub enum ShapeOpType {
     Nop,
     Union,
     Subtraction,
     Intersection,
     SmoothUnion,
+    // blend radius k of the smooth seam
+    SmoothSubtraction(f64),
+    SmoothIntersection(f64),
 }
 
 impl Display for ShapeOpType {
@@ -56,16 +82,23 @@ impl Display for ShapeOpType {
             ShapeOpType::Subtraction => write!(f, "Subtraction"),
             ShapeOpType::Intersection => write!(f, "Intersection"),
             ShapeOpType::SmoothUnion => write!(f, "SmoothUnion"),
+            ShapeOpType::SmoothSubtraction(k) => write!(f, "SmoothSubtraction({})", k),
+            ShapeOpType::SmoothIntersection(k) => write!(f, "SmoothIntersection({})", k),
             ShapeOpType::Nop => write!(f, "Nop"),
         }
     }
 }
 
+// blend radius of ShapeOpType::SmoothUnion
+const SMOOTH_UNION_K: f64 = 1.0;
+
 pub struct ShapeOp<'a> {
     pub shape: Box<dyn Shape>,
     pub op: ShapeOpType,
     pub material: Rc<PBRMaterial>,
     pub next: Option<&'a ShapeOp<'a>>,
+    // conservative bounds of the whole chain starting at this node
+    pub bounds: Bounds3,
 }
 
 impl<'a> Display for ShapeOp<'a> {
@@ -116,15 +149,86 @@ impl<'a> Default for HitResult<'a> {
     }
 }
 
+pub enum Projection {
+    // vertical field of view in degrees
+    Perspective { fov: f64 },
+    // height of the view volume in world units
+    Orthographic { height: f64 },
+}
+
+impl Projection {
+    // origin offset and direction in camera space of the ray through the
+    // film position (x, y) in [-1, 1], looking down +z
+    pub fn camera_ray(&self, x: f64, y: f64, aspect: f64) -> (Vector3f, Vector3f) {
+        match self {
+            Projection::Perspective { fov } => {
+                let scale = f64::tan(Math::radian(fov * 0.5));
+                (
+                    Vector3f::zero(),
+                    Vector3f::new(x * aspect * scale, y * scale, 1.0).normalize(),
+                )
+            }
+            Projection::Orthographic { height } => {
+                let half_height = height * 0.5;
+                (
+                    Vector3f::new(x * aspect * half_height, y * half_height, 0.0),
+                    Vector3f::new(0.0, 0.0, 1.0),
+                )
+            }
+        }
+    }
+}
+
+// how the surface normal is estimated from the sdf gradient
+#[derive(Clone, Copy, Default, Deserialize)]
+pub enum NormalMethod {
+    // six samples, two per axis
+    #[default]
+    CentralDifferences,
+    // four samples at the corners of a tetrahedron, cheaper but a little
+    // less accurate on sharp features
+    Tetrahedron,
+}
+
 pub struct Scene<'a> {
     pub nodes: FrozenVec<Box<ShapeOp<'a>>>,
     pub root_nodes: FrozenVec<&'a ShapeOp<'a>>,
     pub ground_node: RefCell<Option<&'a ShapeOp<'a>>>,
+    pub ground_texture: Checkerboard,
+    // lifts the ground by height(x, z) so it becomes a height field. the
+    // vertical distance is not an exact sdf, it overshoots on steep slopes,
+    // keep the gradient of the height function well below 1
+    pub ground_height: Option<Box<dyn Fn(f64, f64) -> f64>>,
     pub background_color: Vector3f,
     pub width: u32,
     pub height: u32,
-    pub fov: f64,
+    // the camera sits at `eye` and looks at `target` with +y up
+    pub eye: Vector3f,
+    pub target: Vector3f,
+    pub projection: Projection,
     pub sample_per_pixel: u32,
+    // Reinhard c / (c + 1) applied to every ray, disable for linear HDR output
+    pub hdr_compression: bool,
+    // linear scale of the radiance before hdr compression
+    pub exposure: f64,
+    // ray marching limits, raise them for thin or far away geometry
+    pub max_steps: u32,
+    pub march_accuracy: f64,
+    pub max_dist: f64,
+    // number of mirror bounces traced after the primary hit
+    pub max_reflection_depth: u32,
+    // rays averaged for the glossy reflection of a rough metal at the
+    // primary hit, deeper bounces take a single one
+    pub reflection_samples: u32,
+    // cosine distributed rays of the single bounce diffuse gi on the
+    // primary hit, 0 keeps the flat ambient term alone
+    pub gi_samples: u32,
+    pub normal_method: NormalMethod,
+    // exponential distance fog towards fog_color, 0 density disables it
+    pub fog_color: Vector3f,
+    pub fog_density: f64,
+    // lit in addition to the directional sun light
+    pub lights: Vec<Light>,
 }
 
 impl<'a> Scene<'a> {
@@ -139,25 +243,57 @@ impl<'a> Scene<'a> {
             nodes: FrozenVec::new(),
             root_nodes: FrozenVec::new(),
             ground_node: RefCell::new(None),
+            ground_texture: Checkerboard::default(),
+            ground_height: None,
             background_color,
             width,
             height,
-            fov,
+            // looking down 32 degrees onto the default scene
+            eye: Vector3f::new(-0.3, 4.0, -9.5),
+            target: Vector3f::new(-0.3, -1.936, 0.0),
+            projection: Projection::Perspective { fov },
             sample_per_pixel,
+            hdr_compression: true,
+            exposure: 1.0,
+            max_steps: 300,
+            march_accuracy: 1e-3,
+            max_dist: 1e5,
+            max_reflection_depth: 1,
+            reflection_samples: 4,
+            gi_samples: 0,
+            normal_method: NormalMethod::default(),
+            fog_color: background_color,
+            fog_density: 0.0,
+            lights: vec![Light::Directional {
+                direction: Vector3f::new(0.32, -0.77, 0.56),
+                radiance: Vector3f::scalar(10.0),
+            }],
         }
     }
 
+    pub fn look_at(&mut self, eye: Vector3f, target: Vector3f) {
+        self.eye = eye;
+        self.target = target;
+    }
+
+    // camera-to-world transform, undefined when looking straight up or down
+    pub fn camera_to_world(&self) -> Matrix4f {
+        Matrix4f::look_at(&self.eye, &self.target, &Vector3f::new(0.0, 1.0, 0.0))
+    }
+
     pub fn add_leaf_node(
         &'a self,
         shape: Box<dyn Shape>,
         material: Rc<PBRMaterial>,
     ) -> &'a ShapeOp<'a> {
         let idx = self.nodes.len();
+        let bounds = shape.bounds();
         self.nodes.push(Box::new(ShapeOp {
             shape,
             op: ShapeOpType::Nop,
             next: None,
             material,
+            bounds,
         }));
         &self.nodes[idx]
     }
@@ -170,11 +306,13 @@ impl<'a> Scene<'a> {
         next: Option<&'a ShapeOp<'a>>,
     ) -> &'a ShapeOp<'a> {
         let idx = self.nodes.len();
+        let bounds = ShapeOp::chain_bounds(shape.as_ref(), op, next);
         self.nodes.push(Box::new(ShapeOp {
             shape,
             material,
             op,
             next,
+            bounds,
         }));
         &self.nodes[idx]
     }
@@ -195,10 +333,27 @@ impl<'a> Scene<'a> {
         }
     }
 
+    // distance to a root node, with the ground displaced by `ground_height`
+    pub fn node_sdf(&'a self, node: &'a ShapeOp<'a>, p: &Vector3f) -> f64 {
+        match &self.ground_height {
+            Some(height) if self.is_ground(node) => {
+                node.shape_sdf(&(*p - Vector3f::new(0.0, height(p.x, p.z), 0.0)))
+            }
+            _ => node.shape_sdf(p),
+        }
+    }
+
     pub fn sdf(&'a self, p: &Vector3f) -> HitResult<'a> {
         let mut result = HitResult::new();
         for node in &self.root_nodes {
-            let dist = node.shape_sdf(p);
+            // the node is at least as far as its bounds, it cannot beat the
+            // closest distance found so far. a height field ground leaves
+            // its bounds
+            let displaced = self.ground_height.is_some() && self.is_ground(node);
+            if !displaced && node.bounds.distance(p) >= result.distance {
+                continue;
+            }
+            let dist = self.node_sdf(node, p);
             if dist < result.distance {
                 result.distance = dist;
                 result.shape_op = Some(node);
@@ -208,25 +363,26 @@ impl<'a> Scene<'a> {
     }
 
     pub fn cast_ray(&'a self, origin_ray: &Ray) -> Vector3f {
-        let mut color = self._cast_ray(origin_ray, 0, None);
+        let mut color = self._cast_ray(origin_ray, 0, None) * self.exposure;
 
         // HDR
-        color.x = color.x / (color.x + 1.0);
-        color.y = color.y / (color.y + 1.0);
-        color.z = color.z / (color.z + 1.0);
+        if self.hdr_compression {
+            color.x = color.x / (color.x + 1.0);
+            color.y = color.y / (color.y + 1.0);
+            color.z = color.z / (color.z + 1.0);
+        }
 
         color
     }
 
     fn _cast_ray(&'a self, ray: &Ray, depth: u32, _source_op: Option<&'a ShapeOp<'a>>) -> Vector3f {
-        if depth > 1 {
+        if depth > self.max_reflection_depth {
             return Vector3f::zero();
         }
 
         // let mut ray = Ray::new(&origin_ray.origin, &origin_ray.direction, 0.0);
         // let mut view_material: Option<Rc<PBRMaterial>> = None;
-        let hit = self.ray_march(ray, 1e5);
-        let light_intensity = 10.0;
+        let (hit, _) = self.ray_march(ray, self.max_dist);
         if let Some(op) = hit.shape_op {
             // if let Some(orig_op) = _source_op {
             //     if std::ptr::eq(op, orig_op) {
@@ -236,97 +392,207 @@ impl<'a> Scene<'a> {
             let p = ray.eval(hit.distance);
             let normal = self.normal(&hit, &p);
             let material = Rc::clone(&op.material);
-
-            // FIXME: naive blinn-phong
-            let light_radiance = Vector3f::new(1.0, 1.0, 1.0) * light_intensity;
-            let light_dir = Vector3f::new(0.32, -0.77, 0.56);
             let view = (ray.origin - p).normalize();
-            let light = -&light_dir;
 
             let replace_albedo = if !self.is_ground(op) {
                 None
             } else {
-                // ground color
-                if ((p.x * 0.5 + self.width as f64) as u32 + (p.z * 0.5 + 1000.0) as u32) & 1 != 0 {
-                    Some(Vector3f::new(1.0, 1.0, 1.0) * 0.8)
+                // width of the pixel at the hit, stretched at grazing angles
+                let cos_theta = f64::max(f64::abs(normal.dot(&ray.direction)), 0.05);
+                let footprint = self.pixel_footprint(ray.t + hit.distance) / cos_theta;
+                Some(self.ground_texture.sample(&p, footprint))
+            };
+
+            // pbr direct lighting, each light with its own shadow ray
+            let shadow_check_dis = f64::min(1e4, self.max_dist);
+            let direct_lighting = self.lights.iter().fold(Vector3f::zero(), |acc, light| {
+                let (light_dir, radiance, distance) = light.incident(&p);
+                if radiance.dot(&radiance) <= 0.0 {
+                    return acc;
+                }
+                let shadow_orig = if normal.dot(&light_dir) >= 0.0 {
+                    p + normal * 1e-1
                 } else {
-                    Some(Vector3f::new(1.0, 1.0, 1.0) * 0.3)
+                    p - normal * 1e-1
+                };
+                let shadow_ray = Ray::new(&shadow_orig, &light_dir, 0.0);
+                let shadow_dis = f64::min(distance, shadow_check_dis);
+                if self.ray_march(&shadow_ray, shadow_dis).0.shape_op.is_some() {
+                    return acc;
                 }
-            };
+                acc + pbr_lighting(&hit, &view, &normal, &light_dir, &radiance, replace_albedo)
+            });
 
-            // shadow
-            let shadow_check_dis = 1e4;
-            let shadow_orig = if normal.dot(&light) >= 0.0 {
-                p + normal * 1e-1
-            } else {
-                p - normal * 1e-1
-            };
-            let shadow_dir = light;
-            let shadow_ray = Ray::new(&shadow_orig, &shadow_dir, 0.0);
-            let shadow_hit = self.ray_march(&shadow_ray, shadow_check_dis);
-            let shadow_attenuation = if shadow_hit.shape_op.is_none() {
-                1.0
+            let albedo = replace_albedo.unwrap_or(material.albedo);
+            let gi = if depth == 0 && self.gi_samples > 0 {
+                &self.diffuse_gi(&p, &normal, &view) * &albedo * (1.0 - material.metallic)
             } else {
-                0.0
+                Vector3f::zero()
             };
 
-            // pbr direct lighting
-            let direct_lighting = pbr_lighting(
-                &hit,
-                &view,
-                &normal,
-                &light,
-                &light_radiance,
-                replace_albedo,
-            ) * shadow_attenuation;
-
-            // indirect lighting
-            let reflection_dir = normal * 2 * normal.dot(&view) - view;
-            let reflection_orig = if normal.dot(&reflection_dir) >= 0.0 {
-                p + normal * 1e-3
+            // indirect lighting, the mirror direction spread over a cone of
+            // roughness * 45 degrees for glossy reflections. each ray is
+            // weighted by the Fresnel reflectance, so dielectrics keep a
+            // faint reflection that grows towards grazing angles
+            let f0 = lerp(Vector3f::scalar(0.04), albedo, material.metallic);
+            let mirror_dir = (-&view).reflect(&normal);
+            let cos_spread = f64::cos(f64::clamp(material.roughness, 0.0, 1.0) * PI / 4.0);
+            let n_samples = if depth == 0 && cos_spread < 1.0 && material.metallic > 0.0 {
+                u32::max(self.reflection_samples, 1)
             } else {
-                p - normal * 1e-3
+                1
             };
-            let reflection_ray = Ray::new(&reflection_orig, &reflection_dir, 0.0);
-            let reflection_factor = reflection_dir.dot(&normal) * material.metallic;
-            let reflection =
-                self._cast_ray(&reflection_ray, depth + 1, hit.shape_op) * reflection_factor;
-            return direct_lighting + reflection;
+            let reflection = (0..n_samples).fold(Vector3f::zero(), |acc, _| {
+                let mut reflection_dir = Math::sample_uniform_cone(&mirror_dir, cos_spread);
+                // keep the spread rays on the side of the surface the mirror
+                // ray leaves from
+                if reflection_dir.dot(&normal) * mirror_dir.dot(&normal) < 0.0 {
+                    reflection_dir = mirror_dir;
+                }
+                // step clear of the march accuracy, or the ray stops on the
+                // surface it leaves from
+                let offset = 2.0 * self.march_accuracy;
+                let reflection_orig = if normal.dot(&reflection_dir) >= 0.0 {
+                    p + normal * offset
+                } else {
+                    p - normal * offset
+                };
+                // t carries the distance travelled so far to widen the pixel cone
+                let reflection_ray =
+                    Ray::new(&reflection_orig, &reflection_dir, ray.t + hit.distance);
+                let half = (view + reflection_dir).normalize();
+                let fresnel = fresnel_schlick(f64::max(half.dot(&view), 0.0), &f0);
+                acc + &self._cast_ray(&reflection_ray, depth + 1, hit.shape_op) * &fresnel
+            }) / n_samples as f64;
+            return self.apply_fog(
+                material.emission + direct_lighting + gi + reflection,
+                hit.distance,
+            );
         } else if depth > 0 {
             return self.background_color;
         }
         self.background_color
     }
 
-    pub fn ray_march(&'a self, ray: &Ray, max_dist: f64) -> HitResult<'a> {
-        let max_steps = 300;
+    // radiance arriving at p averaged over cosine distributed rays, every
+    // surface they reach counts as lit by the background sky and the rays
+    // escaping the scene see the sky itself
+    fn diffuse_gi(&'a self, p: &Vector3f, normal: &Vector3f, view: &Vector3f) -> Vector3f {
+        let normal = if normal.dot(view) >= 0.0 {
+            normal.normalize()
+        } else {
+            -&normal.normalize()
+        };
+        let origin = p + &(normal * 1e-2);
+        let incident = (0..self.gi_samples).fold(Vector3f::zero(), |acc, _| {
+            let gi_ray = Ray::new(&origin, &Math::sample_cosine_hemisphere(&normal), 0.0);
+            let (hit, _) = self.ray_march(&gi_ray, self.max_dist);
+            match hit.shape_op {
+                Some(op) => {
+                    let albedo = if self.is_ground(op) {
+                        self.ground_texture.sample(&gi_ray.eval(hit.distance), 0.0)
+                    } else {
+                        op.material.albedo
+                    };
+                    acc + op.material.emission + &albedo * &self.background_color
+                }
+                None => acc + self.background_color,
+            }
+        });
+        incident / self.gi_samples as f64
+    }
+
+    // world space width of a pixel `distance` away from the camera
+    pub fn pixel_footprint(&self, distance: f64) -> f64 {
+        match self.projection {
+            Projection::Perspective { fov } => {
+                distance * 2.0 * f64::tan(Math::radian(fov * 0.5)) / self.height as f64
+            }
+            Projection::Orthographic { height } => height / self.height as f64,
+        }
+    }
+
+    pub fn apply_fog(&self, color: Vector3f, distance: f64) -> Vector3f {
+        if self.fog_density <= 0.0 {
+            return color;
+        }
+        let fog = 1.0 - f64::exp(-self.fog_density * distance);
+        lerp(color, self.fog_color, fog)
+    }
+
+    // also returns the number of sdf evaluations taken
+    pub fn ray_march(&'a self, ray: &Ray, max_dist: f64) -> (HitResult<'a>, u32) {
         let mut dist = 0.0;
-        let march_accuracy = 1e-3;
-        for _ in 0..max_steps {
+        for step in 0..self.max_steps {
             let p = ray.eval(dist);
             let hit = self.sdf(&p);
-            if hit.distance <= march_accuracy {
+            if hit.distance <= self.march_accuracy {
                 // hit object
-                return HitResult {
+                let hit = HitResult {
                     distance: dist,
                     shape_op: hit.shape_op,
                 };
+                return (hit, step + 1);
             }
 
             dist += hit.distance;
             if dist >= max_dist {
-                break;
+                return (HitResult::new(), step + 1);
             }
         }
-        return HitResult::new();
+        (HitResult::new(), self.max_steps)
+    }
+
+    // colors the primary ray by the steps it took relative to `max_steps`,
+    // from dark purple (few) to yellow (many) on a viridis like ramp
+    pub fn cast_ray_steps(&'a self, ray: &Ray) -> Vector3f {
+        const VIRIDIS: [[f64; 3]; 5] = [
+            [0.267, 0.005, 0.329],
+            [0.229, 0.322, 0.546],
+            [0.128, 0.567, 0.551],
+            [0.369, 0.789, 0.383],
+            [0.993, 0.906, 0.144],
+        ];
+        let (_, steps) = self.ray_march(ray, self.max_dist);
+        let t = f64::min(steps as f64 / self.max_steps as f64, 1.0) * (VIRIDIS.len() - 1) as f64;
+        let index = usize::min(t as usize, VIRIDIS.len() - 2);
+        let [a, b] = [VIRIDIS[index], VIRIDIS[index + 1]].map(|c| Vector3f::new(c[0], c[1], c[2]));
+        lerp(a, b, t - index as f64)
     }
 
-    pub fn normal(&'a self, hit: &HitResult, p: &Vector3f) -> Vector3f {
+    pub fn normal(&'a self, hit: &HitResult<'a>, p: &Vector3f) -> Vector3f {
         if hit.shape_op.is_none() {
             panic!("impossible");
         }
 
+        let shape_op = hit.shape_op.unwrap();
+        // a lone sphere has a closed form normal, csg chains and the
+        // displaced ground go through the sdf gradient
+        if shape_op.next.is_none() && !self.is_ground(shape_op) {
+            if let ShapeType::Sphere = shape_op.shape.shape_type() {
+                let shape: &dyn Any = shape_op.shape.as_ref();
+                if let Some(sphere) = shape.downcast_ref::<Sphere>() {
+                    return (p - &sphere.center).normalize();
+                }
+            }
+        }
+
         let eps_grad = 1e-3;
+        if let NormalMethod::Tetrahedron = self.normal_method {
+            // sum of k * sdf(p + eps * k) over the corners k, the outer
+            // products of the corners add up to 4 times the identity
+            return [
+                Vector3f::new(1.0, -1.0, -1.0),
+                Vector3f::new(-1.0, -1.0, 1.0),
+                Vector3f::new(-1.0, 1.0, -1.0),
+                Vector3f::new(1.0, 1.0, 1.0),
+            ]
+            .iter()
+            .fold(Vector3f::zero(), |sum, k| {
+                sum + k * self.node_sdf(shape_op, &(p + &(k * eps_grad)))
+            }) / (4.0 * eps_grad);
+        }
+
         let p_x_p = p + &Vector3f::new(eps_grad, 0.0, 0.0);
         let p_x_m = p - &Vector3f::new(eps_grad, 0.0, 0.0);
         let p_y_p = p + &Vector3f::new(0.0, eps_grad, 0.0);
@@ -334,13 +600,12 @@ impl<'a> Scene<'a> {
         let p_z_p = p + &Vector3f::new(0.0, 0.0, eps_grad);
         let p_z_m = p - &Vector3f::new(0.0, 0.0, eps_grad);
 
-        let shape_op = hit.shape_op.unwrap();
-        let sdf_x_p = shape_op.shape_sdf(&p_x_p);
-        let sdf_x_m = shape_op.shape_sdf(&p_x_m);
-        let sdf_y_p = shape_op.shape_sdf(&p_y_p);
-        let sdf_y_m = shape_op.shape_sdf(&p_y_m);
-        let sdf_z_p = shape_op.shape_sdf(&p_z_p);
-        let sdf_z_m = shape_op.shape_sdf(&p_z_m);
+        let sdf_x_p = self.node_sdf(shape_op, &p_x_p);
+        let sdf_x_m = self.node_sdf(shape_op, &p_x_m);
+        let sdf_y_p = self.node_sdf(shape_op, &p_y_p);
+        let sdf_y_m = self.node_sdf(shape_op, &p_y_m);
+        let sdf_z_p = self.node_sdf(shape_op, &p_z_p);
+        let sdf_z_m = self.node_sdf(shape_op, &p_z_m);
         Vector3f::new(sdf_x_p - sdf_x_m, sdf_y_p - sdf_y_m, sdf_z_p - sdf_z_m) / (2.0 * eps_grad)
     }
 }
@@ -352,6 +617,42 @@ impl<'a> Default for Scene<'a> {
 }
 
 impl<'a> ShapeOp<'a> {
+    // folds the bounds along the chain the same way `shape_sdf` folds the
+    // distances
+    fn chain_bounds(shape: &dyn Shape, op: ShapeOpType, next: Option<&ShapeOp>) -> Bounds3 {
+        let mut bounds = shape.bounds();
+        let mut cur_op = op;
+        let mut next = next;
+        while let Some(node) = next {
+            bounds = Self::op_bounds(&bounds, &cur_op, &node.shape.bounds());
+            cur_op = node.op;
+            next = node.next;
+        }
+        bounds
+    }
+
+    fn op_bounds(bounds_a: &Bounds3, op: &ShapeOpType, bounds_b: &Bounds3) -> Bounds3 {
+        match op {
+            ShapeOpType::Union => {
+                let mut bounds = *bounds_a;
+                bounds.union(bounds_b);
+                bounds
+            }
+            // the blend adds at most k / 4 to the union
+            ShapeOpType::SmoothUnion => {
+                let mut bounds = *bounds_a;
+                bounds.union(bounds_b);
+                bounds.expand(SMOOTH_UNION_K * 0.25)
+            }
+            // smooth subtraction and intersection only ever remove material
+            ShapeOpType::Subtraction
+            | ShapeOpType::Intersection
+            | ShapeOpType::SmoothSubtraction(_)
+            | ShapeOpType::SmoothIntersection(_) => *bounds_a,
+            ShapeOpType::Nop => panic!("invalid operation {}", op),
+        }
+    }
+
     pub fn shape_sdf(&self, p: &Vector3f) -> f64 {
         let mut sdf_f = self.shape.sdf(p);
         let mut cur = self;
@@ -371,11 +672,456 @@ impl<'a> ShapeOp<'a> {
             ShapeOpType::Subtraction => f64::max(sdf_a, -sdf_b),
             ShapeOpType::Intersection => f64::max(sdf_a, sdf_b),
             ShapeOpType::SmoothUnion => {
-                let k = 1.0;
+                let k = SMOOTH_UNION_K;
                 let h = f64::clamp(0.5 + 0.5 * (sdf_b - sdf_a) / k, 0.0, 1.0);
                 lerp(sdf_b, sdf_a, h) - k * h * (1.0 - h)
             }
+            ShapeOpType::SmoothSubtraction(k) => {
+                let h = f64::clamp(0.5 - 0.5 * (sdf_a + sdf_b) / k, 0.0, 1.0);
+                lerp(sdf_a, -sdf_b, h) + k * h * (1.0 - h)
+            }
+            ShapeOpType::SmoothIntersection(k) => {
+                // h reaches 1 where a is the larger distance, so the blend
+                // runs from b towards a
+                let h = f64::clamp(0.5 - 0.5 * (sdf_b - sdf_a) / k, 0.0, 1.0);
+                lerp(sdf_b, sdf_a, h) + k * h * (1.0 - h)
+            }
             ShapeOpType::Nop => panic!("invalid operation {}", op),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use primitive::Cube;
+
+    fn material(albedo: Vector3f, emission: Vector3f) -> Rc<PBRMaterial> {
+        Rc::new(PBRMaterial {
+            albedo,
+            emission,
+            metallic: 0.0,
+            roughness: 1.0,
+            ao: 0.0,
+        })
+    }
+
+    fn sphere(center: Vector3f, radius: f64) -> Box<dyn Shape> {
+        Box::new(Sphere { center, radius })
+    }
+
+    // no lights and no reflections, a hit only shows its own emission
+    fn unlit_scene<'a>() -> Scene<'a> {
+        let mut scene = Scene::new(8, 8, 45.0, 1, Vector3f::zero());
+        scene.lights.clear();
+        scene.max_reflection_depth = 0;
+        scene
+    }
+
+    fn forward_ray() -> Ray {
+        Ray::new(&Vector3f::zero(), &Vector3f::new(0.0, 0.0, 1.0), 0.0)
+    }
+
+    #[test]
+    fn hdr_compression_only_changes_the_encoding() {
+        let emission = Vector3f::new(3.0, 1.0, 0.25);
+        let radiance = |hdr_compression: bool| {
+            let mut scene = unlit_scene();
+            scene.hdr_compression = hdr_compression;
+            let node = scene.add_leaf_node(
+                sphere(Vector3f::new(0.0, 0.0, 5.0), 1.0),
+                material(Vector3f::zero(), emission),
+            );
+            scene.add_root_node(node);
+            scene.cast_ray(&forward_ray())
+        };
+
+        let linear = radiance(false);
+        assert!(linear.approx_eq(&emission, 1e-12));
+        assert!(linear.x > 1.0);
+        let compressed = radiance(true);
+        let reinhard = |c: f64| c / (c + 1.0);
+        let expected = Vector3f::new(reinhard(linear.x), reinhard(linear.y), reinhard(linear.z));
+        assert!(compressed.approx_eq(&expected, 1e-12));
+    }
+
+    #[test]
+    fn running_out_of_steps_is_a_miss() {
+        let march = |max_steps: u32| {
+            let mut scene = unlit_scene();
+            scene.max_steps = max_steps;
+            let node = scene.add_leaf_node(
+                sphere(Vector3f::new(0.3, 0.0, 5.0), 1.0),
+                material(Vector3f::scalar(1.0), Vector3f::zero()),
+            );
+            scene.add_root_node(node);
+            let (hit, steps) = scene.ray_march(&forward_ray(), scene.max_dist);
+            (hit.shape_op.is_some(), hit.distance, steps)
+        };
+
+        let (hit, distance, steps) = march(300);
+        assert!(hit && steps > 2 && steps < 300);
+        assert!(f64::abs(distance - (5.0 - f64::sqrt(0.91))) < 1e-2);
+        let (hit, distance, steps) = march(2);
+        assert!(!hit && steps == 2);
+        assert_eq!(distance, f64::MAX);
+    }
+
+    #[test]
+    fn pruned_sdf_matches_the_full_scan() {
+        let scene = unlit_scene();
+        let white = material(Vector3f::scalar(1.0), Vector3f::zero());
+        for i in 0..8 {
+            let center = Vector3f::new(i as f64 * 1.5 - 5.0, (i % 3) as f64, (i % 2) as f64 * 4.0);
+            let node = scene.add_leaf_node(sphere(center, 0.4 + 0.1 * i as f64), white.clone());
+            scene.add_root_node(node);
+        }
+        // a csg chain whose bounds come from the first shape only
+        let hole = scene.add_leaf_node(sphere(Vector3f::new(0.0, 3.5, 0.0), 0.8), white.clone());
+        let cut = scene.add_node(
+            sphere(Vector3f::new(0.0, 3.0, 0.0), 1.0),
+            white,
+            ShapeOpType::Subtraction,
+            Some(hole),
+        );
+        scene.add_root_node(cut);
+
+        for i in 0..1000 {
+            let p = Vector3f::new(
+                (i % 10) as f64 * 1.3 - 6.0,
+                (i / 10 % 10) as f64 * 0.7 - 2.0,
+                (i / 100) as f64 * 0.9 - 3.0,
+            );
+            let naive = scene
+                .root_nodes
+                .iter()
+                .map(|node| node.shape_sdf(&p))
+                .fold(f64::MAX, f64::min);
+            assert_eq!(scene.sdf(&p).distance, naive);
+        }
+    }
+
+    #[test]
+    fn fog_grows_with_distance() {
+        let mut scene = unlit_scene();
+        let color = Vector3f::new(0.8, 0.2, 0.1);
+        scene.fog_color = Vector3f::new(0.5, 0.6, 0.7);
+        assert!(scene.apply_fog(color, 100.0) == color);
+
+        scene.fog_density = 0.3;
+        assert!(scene.apply_fog(color, 0.0) == color);
+        let near = scene.apply_fog(color, 1.0);
+        let expected = lerp(color, scene.fog_color, 1.0 - f64::exp(-0.3));
+        assert!(near.approx_eq(&expected, 1e-12));
+        let far = scene.apply_fog(color, 100.0);
+        assert!(far.approx_eq(&scene.fog_color, 1e-9));
+    }
+
+    #[test]
+    fn smooth_operators_converge_to_the_hard_ones() {
+        let pairs = [(0.3, -0.2), (-0.5, 0.1), (0.05, 0.04), (1.0, -2.0)];
+        // each operator with a point on its seam, where both terms are equal
+        let cases = [
+            (ShapeOpType::Subtraction, (0.1, -0.1)),
+            (ShapeOpType::Intersection, (0.1, 0.1)),
+        ];
+        for (hard, (a, b)) in cases {
+            let smooth = |k: f64| match hard {
+                ShapeOpType::Subtraction => ShapeOpType::SmoothSubtraction(k),
+                _ => ShapeOpType::SmoothIntersection(k),
+            };
+            // at the seam the blend widens the transition by k / 4
+            let exact = ShapeOp::op_sdf(a, &hard, b);
+            assert!(f64::abs(ShapeOp::op_sdf(a, &smooth(0.5), b) - (exact + 0.125)) < 1e-12);
+
+            for (a, b) in pairs {
+                let exact = ShapeOp::op_sdf(a, &hard, b);
+                let blended = ShapeOp::op_sdf(a, &smooth(0.5), b);
+                assert!(blended >= exact && blended <= exact + 0.125 + 1e-12);
+                let errors: Vec<f64> = [0.5, 0.1, 0.01, 1e-4]
+                    .iter()
+                    .map(|k| f64::abs(ShapeOp::op_sdf(a, &smooth(*k), b) - exact))
+                    .collect();
+                assert!(errors.windows(2).all(|w| w[1] <= w[0]));
+                assert!(errors[3] < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn default_camera_is_the_old_32_degree_view() {
+        let scene = unlit_scene();
+        let camera_to_world = scene.camera_to_world();
+        // the euler rotation main used to pass to the renderer
+        let rotation = Matrix4f::from_euler(32f64.to_radians(), 0.0, 0.0);
+        let eye = Vector3f::new(-0.3, 4.0, -9.5);
+        assert!(camera_to_world
+            .transform_point(&Vector3f::zero())
+            .approx_eq(&eye, 1e-12));
+        for axis in [
+            Vector3f::new(1.0, 0.0, 0.0),
+            Vector3f::new(0.0, 1.0, 0.0),
+            Vector3f::new(0.0, 0.0, 1.0),
+        ] {
+            let expected = rotation.transform_vector(&axis);
+            assert!(camera_to_world
+                .transform_vector(&axis)
+                .approx_eq(&expected, 1e-4));
+        }
+        let forward = camera_to_world.transform_vector(&Vector3f::new(0.0, 0.0, 1.0));
+        assert!(forward.approx_eq(&(scene.target - scene.eye).normalize(), 1e-12));
+    }
+
+    #[test]
+    fn every_light_adds_to_the_diffuse() {
+        let radiance = |n_lights: usize| {
+            let mut scene = unlit_scene();
+            scene.hdr_compression = false;
+            for _ in 0..n_lights {
+                scene.lights.push(Light::Directional {
+                    direction: Vector3f::new(0.2, -0.3, 1.0),
+                    radiance: Vector3f::scalar(1.0),
+                });
+            }
+            let node = scene.add_leaf_node(
+                sphere(Vector3f::new(0.0, 0.0, 5.0), 1.0),
+                material(Vector3f::scalar(0.8), Vector3f::zero()),
+            );
+            scene.add_root_node(node);
+            scene.cast_ray(&forward_ray())
+        };
+
+        let (none, one, two) = (radiance(0), radiance(1), radiance(2));
+        assert!(one.x > none.x);
+        assert!(two.x > one.x);
+        // the lights are summed in linear radiance
+        assert!((two - one).approx_eq(&(one - none), 1e-9));
+    }
+
+    #[test]
+    fn ground_height_shifts_the_ground_surface() {
+        let height = |x: f64, z: f64| 0.3 * f64::sin(x) * f64::cos(0.5 * z);
+        let points = [
+            Vector3f::new(0.0, 0.5, 0.0),
+            Vector3f::new(1.2, 0.8, -0.7),
+            Vector3f::new(-2.5, 1.5, 3.1),
+        ];
+        let raised = |p: &Vector3f| *p + Vector3f::new(0.0, height(p.x, p.z), 0.0);
+        let distances = |ground_height: Option<Box<dyn Fn(f64, f64) -> f64>>| {
+            let mut scene = unlit_scene();
+            scene.ground_height = ground_height;
+            // a slab with its top face at y = 0
+            let ground = scene.add_leaf_node(
+                Box::new(Cube {
+                    center: Vector3f::new(0.0, -1.0, 0.0),
+                    most_front_up_right: Vector3f::new(15.0, 1.0, 15.0),
+                }),
+                material(Vector3f::scalar(1.0), Vector3f::zero()),
+            );
+            scene.add_root_node(ground);
+            scene.set_ground(ground);
+            let lifted: Vec<f64> = points
+                .iter()
+                .map(|p| scene.sdf(&raised(p)).distance)
+                .collect();
+            let plain: Vec<f64> = points.iter().map(|p| scene.sdf(p).distance).collect();
+            (plain, lifted)
+        };
+
+        let (plain, _) = distances(None);
+        for (d, p) in plain.iter().zip(points.iter()) {
+            assert!(f64::abs(d - p.y) < 1e-12);
+        }
+        let (flat, _) = distances(Some(Box::new(|_, _| 0.0)));
+        assert_eq!(flat, plain);
+        // points raised by the height keep their distance to the surface
+        let (_, lifted) = distances(Some(Box::new(height)));
+        for (d, p) in lifted.iter().zip(points.iter()) {
+            assert!(f64::abs(d - p.y) < 1e-12);
+        }
+    }
+
+    // points on the unit sphere around `SPHERE_CENTER`
+    const SPHERE_CENTER: Vector3f = Vector3f {
+        x: 0.5,
+        y: -0.2,
+        z: 4.0,
+    };
+
+    fn sphere_surface_points() -> Vec<Vector3f> {
+        [
+            Vector3f::new(1.0, 0.0, 0.0),
+            Vector3f::new(0.0, -1.0, 0.0),
+            Vector3f::new(0.3, 0.4, -0.5),
+            Vector3f::new(-0.7, 0.2, 0.6),
+        ]
+        .iter()
+        .map(|d| SPHERE_CENTER + d.normalize())
+        .collect()
+    }
+
+    #[test]
+    fn normal_methods_agree_on_a_sphere() {
+        let normals = |method: NormalMethod| {
+            let mut scene = unlit_scene();
+            scene.normal_method = method;
+            let white = material(Vector3f::scalar(1.0), Vector3f::zero());
+            // chained with a far away sphere so the gradient is estimated
+            let far = scene.add_leaf_node(sphere(Vector3f::scalar(50.0), 0.1), white.clone());
+            let node = scene.add_node(
+                sphere(SPHERE_CENTER, 1.0),
+                white,
+                ShapeOpType::Union,
+                Some(far),
+            );
+            scene.add_root_node(node);
+            let hit = HitResult {
+                distance: 0.0,
+                shape_op: Some(node),
+            };
+            sphere_surface_points()
+                .iter()
+                .map(|p| scene.normal(&hit, p))
+                .collect::<Vec<_>>()
+        };
+
+        let central = normals(NormalMethod::CentralDifferences);
+        let tetrahedron = normals(NormalMethod::Tetrahedron);
+        for ((p, a), b) in sphere_surface_points().iter().zip(central).zip(tetrahedron) {
+            let analytic = (*p - SPHERE_CENTER).normalize();
+            assert!(a.approx_eq(&analytic, 1e-5));
+            assert!(b.approx_eq(&analytic, 1e-3));
+        }
+    }
+
+    #[test]
+    fn analytic_sphere_normal_matches_central_differences() {
+        let scene = unlit_scene();
+        let white = material(Vector3f::scalar(1.0), Vector3f::zero());
+        let lone = scene.add_leaf_node(sphere(SPHERE_CENTER, 1.0), white.clone());
+        // the same sphere in a chain takes the finite difference path
+        let far = scene.add_leaf_node(sphere(Vector3f::scalar(50.0), 0.1), white.clone());
+        let chained = scene.add_node(
+            sphere(SPHERE_CENTER, 1.0),
+            white,
+            ShapeOpType::Union,
+            Some(far),
+        );
+        let hit = |node| HitResult {
+            distance: 0.0,
+            shape_op: Some(node),
+        };
+        for p in sphere_surface_points() {
+            let analytic = scene.normal(&hit(lone), &p);
+            assert!(f64::abs(analytic.length() - 1.0) < 1e-12);
+            assert!(analytic.approx_eq(&scene.normal(&hit(chained), &p), 1e-6));
+        }
+    }
+
+    // a forward ray onto a mirror wall at z = 5 that reflects it back onto
+    // an emissive sphere behind the camera
+    fn mirror_radiance(roughness: f64, n_casts: usize) -> Vec<Vector3f> {
+        let mut scene = unlit_scene();
+        scene.hdr_compression = false;
+        scene.max_reflection_depth = 1;
+        scene.reflection_samples = 1;
+        let mirror = Rc::new(PBRMaterial {
+            albedo: Vector3f::scalar(1.0),
+            emission: Vector3f::zero(),
+            metallic: 1.0,
+            roughness,
+            ao: 0.0,
+        });
+        let wall = scene.add_leaf_node(
+            Box::new(Cube {
+                center: Vector3f::new(0.0, 0.0, 6.0),
+                most_front_up_right: Vector3f::new(10.0, 10.0, 1.0),
+            }),
+            mirror,
+        );
+        scene.add_root_node(wall);
+        let lamp = scene.add_leaf_node(
+            sphere(Vector3f::new(0.0, 0.0, -6.0), 3.0),
+            material(Vector3f::zero(), Vector3f::new(2.0, 1.0, 0.5)),
+        );
+        scene.add_root_node(lamp);
+        (0..n_casts)
+            .map(|_| scene.cast_ray(&forward_ray()))
+            .collect()
+    }
+
+    #[test]
+    fn rough_reflections_spread_around_the_mirror_direction() {
+        // the mirror direction runs straight back onto the lamp, with a
+        // white metal reflecting all of it
+        for radiance in mirror_radiance(0.0, 8) {
+            assert!(radiance.approx_eq(&Vector3f::new(2.0, 1.0, 0.5), 1e-9));
+        }
+        // a rough mirror scatters some rays past the lamp
+        let rough = mirror_radiance(0.6, 256);
+        assert!(rough.iter().any(|c| c.x < 1e-9));
+        assert!(rough.iter().any(|c| c.x > 1.0));
+    }
+
+    #[test]
+    fn zero_gi_samples_keep_the_flat_ambient() {
+        let albedo = Vector3f::new(0.8, 0.4, 0.2);
+        let radiance = |gi_samples: u32, background_color: Vector3f| {
+            let mut scene = Scene::new(8, 8, 45.0, 1, background_color);
+            scene.hdr_compression = false;
+            scene.max_reflection_depth = 0;
+            scene.gi_samples = gi_samples;
+            let node = scene.add_leaf_node(
+                sphere(Vector3f::new(0.0, 0.0, 5.0), 1.0),
+                material(albedo, Vector3f::zero()),
+            );
+            scene.add_root_node(node);
+            scene.cast_ray(&forward_ray())
+        };
+        // without gi the sky does not light the hit at all
+        let sky = Vector3f::new(0.5, 0.6, 0.7);
+        let flat = radiance(0, Vector3f::zero());
+        assert!(radiance(0, sky) == flat);
+        // every gi ray off a lone sphere escapes to the sky
+        assert!(radiance(16, sky).approx_eq(&(flat + &albedo * &sky), 1e-9));
+    }
+
+    #[test]
+    fn more_bounces_add_dimmer_reflections() {
+        // two facing glowing mirrors, each reflecting half of what arrives
+        let emission = Vector3f::new(1.0, 0.5, 0.25);
+        let radiance = |max_reflection_depth: u32| {
+            let mut scene = unlit_scene();
+            scene.hdr_compression = false;
+            scene.max_reflection_depth = max_reflection_depth;
+            let mirror = Rc::new(PBRMaterial {
+                albedo: Vector3f::scalar(0.5),
+                emission,
+                metallic: 1.0,
+                roughness: 0.0,
+                ao: 0.0,
+            });
+            for z in [6.0, -6.0] {
+                let wall = scene.add_leaf_node(
+                    Box::new(Cube {
+                        center: Vector3f::new(0.0, 0.0, z),
+                        most_front_up_right: Vector3f::new(10.0, 10.0, 1.0),
+                    }),
+                    Rc::clone(&mirror),
+                );
+                scene.add_root_node(wall);
+            }
+            scene.cast_ray(&forward_ray())
+        };
+        let mut previous = radiance(0);
+        assert!(previous.approx_eq(&emission, 1e-9));
+        let mut added = emission;
+        for depth in 1..4 {
+            let current = radiance(depth);
+            // each bounce adds the next reflection at half the strength
+            added = added * 0.5;
+            assert!(current.approx_eq(&(previous + added), 1e-6));
+            previous = current;
+        }
+    }
+}
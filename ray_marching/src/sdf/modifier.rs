@@ -0,0 +1,497 @@
+use crate::math::{noise, Matrix4f};
+use crate::{domain::Ray, math::Vector3f};
+use core::fmt;
+use std::fmt::Display;
+
+use super::{bounds::Bounds3, Shape, ShapeType};
+
+// inflates any shape by `radius`, which rounds off its edges and corners
+pub struct Rounded {
+    pub inner: Box<dyn Shape>,
+    pub radius: f64,
+}
+
+impl Shape for Rounded {
+    fn shape_type(&self) -> ShapeType {
+        self.inner.shape_type()
+    }
+
+    fn sdf(&self, p: &Vector3f) -> f64 {
+        self.inner.sdf(p) - self.radius
+    }
+
+    fn bounds(&self) -> Bounds3 {
+        let bounds = self.inner.bounds();
+        let radius = Vector3f::scalar(self.radius);
+        Bounds3::new(bounds.p_min - radius, bounds.p_max + radius)
+    }
+
+    fn rotate_ray(&self, ray: &Ray) -> Ray {
+        self.inner.rotate_ray(ray)
+    }
+}
+
+impl Display for Rounded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Rounded({}, radius={})", self.inner, self.radius)
+    }
+}
+
+// hollows a shape into a shell of `thickness` around its surface
+pub struct Onion {
+    pub inner: Box<dyn Shape>,
+    pub thickness: f64,
+}
+
+impl Shape for Onion {
+    fn shape_type(&self) -> ShapeType {
+        self.inner.shape_type()
+    }
+
+    fn sdf(&self, p: &Vector3f) -> f64 {
+        f64::abs(self.inner.sdf(p)) - self.thickness
+    }
+
+    fn bounds(&self) -> Bounds3 {
+        let bounds = self.inner.bounds();
+        let thickness = Vector3f::scalar(self.thickness);
+        Bounds3::new(bounds.p_min - thickness, bounds.p_max + thickness)
+    }
+
+    fn rotate_ray(&self, ray: &Ray) -> Ray {
+        self.inner.rotate_ray(ray)
+    }
+}
+
+impl Display for Onion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Onion({}, thickness={})", self.inner, self.thickness)
+    }
+}
+
+// bumps the surface of a shape by gradient noise of `frequency` scaled to
+// at most `amplitude`. the result is no longer an exact distance, the noise
+// slope grows with amplitude * frequency and can make the marcher overshoot
+// thin bumps, so lower march_accuracy or the noise until artifacts vanish
+pub struct Displaced {
+    pub inner: Box<dyn Shape>,
+    pub amplitude: f64,
+    pub frequency: f64,
+}
+
+impl Shape for Displaced {
+    fn shape_type(&self) -> ShapeType {
+        self.inner.shape_type()
+    }
+
+    fn sdf(&self, p: &Vector3f) -> f64 {
+        self.inner.sdf(p) + self.amplitude * noise::perlin(&(*p * self.frequency))
+    }
+
+    fn bounds(&self) -> Bounds3 {
+        let bounds = self.inner.bounds();
+        let amplitude = Vector3f::scalar(f64::abs(self.amplitude));
+        Bounds3::new(bounds.p_min - amplitude, bounds.p_max + amplitude)
+    }
+
+    fn rotate_ray(&self, ray: &Ray) -> Ray {
+        self.inner.rotate_ray(ray)
+    }
+}
+
+impl Display for Displaced {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Displaced({}, amplitude={}, frequency={})",
+            self.inner, self.amplitude, self.frequency
+        )
+    }
+}
+
+// the two components of `v` across `axis` (0 = x, 1 = y, 2 = z)
+fn across_axis(v: &Vector3f, axis: usize) -> [f64; 2] {
+    match axis {
+        0 => [v.y, v.z],
+        1 => [v.x, v.z],
+        _ => [v.x, v.y],
+    }
+}
+
+// pivot of a warp around `axis` through the center of the bounds of
+// `inner`, with the radius of the cylinder around it that holds the whole
+// box. the warp needs a finite extent across the axis to find its center
+fn warp_pivot(inner: &dyn Shape, axis: usize) -> Result<(Bounds3, Vector3f, f64), String> {
+    let bounds = inner.bounds();
+    let finite = across_axis(&bounds.p_min, axis)
+        .iter()
+        .chain(across_axis(&bounds.p_max, axis).iter())
+        .all(|c| c.is_finite());
+    if !finite {
+        return Err(format!("cannot warp the unbounded shape {}", inner));
+    }
+    let [a, b] = across_axis(&(bounds.diagonal() * 0.5), axis);
+    Ok((bounds, bounds.center(), f64::sqrt(a * a + b * b)))
+}
+
+// twists a shape around the vertical axis through the center of its bounds
+// by `k` radians per unit of height. the warp stretches distances by up to
+// sqrt(1 + (k * r)^2) at radius r, so the distance is divided by that bound
+// to keep the marcher from stepping through the surface
+pub struct Twist {
+    pub inner: Box<dyn Shape>,
+    k: f64,
+    pivot: Vector3f,
+    // 1 / sqrt(1 + (k * r)^2) for the largest radius r of the inner shape
+    scale: f64,
+    bounds: Bounds3,
+}
+
+impl Twist {
+    pub fn new(inner: Box<dyn Shape>, k: f64) -> Result<Twist, String> {
+        let (inner_bounds, pivot, r) = warp_pivot(inner.as_ref(), 1)?;
+        let stretch = k * r;
+        let bounds = Bounds3::new(
+            Vector3f::new(pivot.x - r, inner_bounds.p_min.y, pivot.z - r),
+            Vector3f::new(pivot.x + r, inner_bounds.p_max.y, pivot.z + r),
+        );
+        Ok(Twist {
+            inner,
+            k,
+            pivot,
+            scale: 1.0 / f64::sqrt(1.0 + stretch * stretch),
+            bounds,
+        })
+    }
+}
+
+impl Shape for Twist {
+    fn shape_type(&self) -> ShapeType {
+        self.inner.shape_type()
+    }
+
+    fn sdf(&self, p: &Vector3f) -> f64 {
+        let pivot = self.pivot;
+        let (s, c) = f64::sin_cos(self.k * (p.y - pivot.y));
+        let (x, z) = (p.x - pivot.x, p.z - pivot.z);
+        let q = Vector3f::new(c * x - s * z + pivot.x, p.y, s * x + c * z + pivot.z);
+        self.inner.sdf(&q) * self.scale
+    }
+
+    fn bounds(&self) -> Bounds3 {
+        self.bounds
+    }
+
+    fn rotate_ray(&self, ray: &Ray) -> Ray {
+        self.inner.rotate_ray(ray)
+    }
+}
+
+impl Display for Twist {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Twist({}, k={})", self.inner, self.k)
+    }
+}
+
+// bends a shape in the xy plane around the z axis through the center of its
+// bounds by `k` radians per unit along x. the distance is scaled down like
+// Twist to stay a safe step size
+pub struct Bend {
+    pub inner: Box<dyn Shape>,
+    k: f64,
+    pivot: Vector3f,
+    scale: f64,
+    bounds: Bounds3,
+}
+
+impl Bend {
+    pub fn new(inner: Box<dyn Shape>, k: f64) -> Result<Bend, String> {
+        let (inner_bounds, pivot, r) = warp_pivot(inner.as_ref(), 2)?;
+        let stretch = k * r;
+        let bounds = Bounds3::new(
+            Vector3f::new(pivot.x - r, pivot.y - r, inner_bounds.p_min.z),
+            Vector3f::new(pivot.x + r, pivot.y + r, inner_bounds.p_max.z),
+        );
+        Ok(Bend {
+            inner,
+            k,
+            pivot,
+            scale: 1.0 / f64::sqrt(1.0 + stretch * stretch),
+            bounds,
+        })
+    }
+}
+
+impl Shape for Bend {
+    fn shape_type(&self) -> ShapeType {
+        self.inner.shape_type()
+    }
+
+    fn sdf(&self, p: &Vector3f) -> f64 {
+        let pivot = self.pivot;
+        let (x, y) = (p.x - pivot.x, p.y - pivot.y);
+        let (s, c) = f64::sin_cos(self.k * x);
+        let q = Vector3f::new(c * x - s * y + pivot.x, s * x + c * y + pivot.y, p.z);
+        self.inner.sdf(&q) * self.scale
+    }
+
+    fn bounds(&self) -> Bounds3 {
+        self.bounds
+    }
+
+    fn rotate_ray(&self, ray: &Ray) -> Ray {
+        self.inner.rotate_ray(ray)
+    }
+}
+
+impl Display for Bend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Bend({}, k={})", self.inner, self.k)
+    }
+}
+
+// copies of one shape placed by `transforms`, the distance is the closest
+// over all copies so repeated geometry is stored once. the transforms map
+// the shape into the world and must be rotations and translations with at
+// most a uniform scale, anything else no longer gives a distance
+pub struct Instanced {
+    pub inner: Box<dyn Shape>,
+    // world to instance transforms with the scale they undo
+    instances: Vec<(Matrix4f, f64)>,
+    bounds: Bounds3,
+}
+
+impl Instanced {
+    pub fn new(inner: Box<dyn Shape>, transforms: &[Matrix4f]) -> Result<Instanced, String> {
+        if transforms.is_empty() {
+            return Err(String::from("instanced shape without instances"));
+        }
+        let inner_bounds = inner.bounds();
+        let mut bounds: Option<Bounds3> = None;
+        let mut instances = vec![];
+        for transform in transforms {
+            let inverse = transform
+                .inverse()
+                .ok_or_else(|| String::from("singular instance transform"))?;
+            let scale = transform
+                .transform_vector(&Vector3f::new(1.0, 0.0, 0.0))
+                .length();
+            instances.push((inverse, scale));
+
+            // the box around the transformed corners of the inner bounds
+            for i in 0..8 {
+                let corner = Vector3f::new(
+                    if i & 1 == 0 {
+                        inner_bounds.p_min.x
+                    } else {
+                        inner_bounds.p_max.x
+                    },
+                    if i & 2 == 0 {
+                        inner_bounds.p_min.y
+                    } else {
+                        inner_bounds.p_max.y
+                    },
+                    if i & 4 == 0 {
+                        inner_bounds.p_min.z
+                    } else {
+                        inner_bounds.p_max.z
+                    },
+                );
+                let corner = transform.transform_point(&corner);
+                let corner_bounds = Bounds3::new(corner, corner);
+                match bounds.as_mut() {
+                    Some(bounds) => bounds.union(&corner_bounds),
+                    None => bounds = Some(corner_bounds),
+                }
+            }
+        }
+        Ok(Instanced {
+            inner,
+            instances,
+            bounds: bounds.unwrap(),
+        })
+    }
+}
+
+impl Shape for Instanced {
+    fn shape_type(&self) -> ShapeType {
+        self.inner.shape_type()
+    }
+
+    fn sdf(&self, p: &Vector3f) -> f64 {
+        self.instances
+            .iter()
+            .map(|(inverse, scale)| self.inner.sdf(&inverse.transform_point(p)) * scale)
+            .fold(f64::MAX, f64::min)
+    }
+
+    fn bounds(&self) -> Bounds3 {
+        self.bounds
+    }
+
+    fn rotate_ray(&self, ray: &Ray) -> Ray {
+        self.inner.rotate_ray(ray)
+    }
+}
+
+impl Display for Instanced {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Instanced({}, instances={})",
+            self.inner,
+            self.instances.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdf::primitive::{Cube, Helix, Sphere};
+
+    fn slab() -> Box<dyn Shape> {
+        Box::new(Cube {
+            most_front_up_right: Vector3f::new(1.0, 2.0, 0.2),
+            center: Vector3f::new(0.5, 0.0, 0.0),
+        })
+    }
+
+    fn points() -> Vec<Vector3f> {
+        vec![
+            Vector3f::new(0.0, 0.0, 0.0),
+            Vector3f::new(1.5, 1.0, 0.0),
+            Vector3f::new(-2.0, 0.5, 1.0),
+            Vector3f::new(0.3, -3.0, -0.4),
+        ]
+    }
+
+    #[test]
+    fn zero_k_keeps_the_inner_shape() {
+        let inner = slab();
+        let twist = Twist::new(slab(), 0.0).unwrap();
+        let bend = Bend::new(slab(), 0.0).unwrap();
+        for p in points() {
+            assert!(f64::abs(twist.sdf(&p) - inner.sdf(&p)) < 1e-12);
+            assert!(f64::abs(bend.sdf(&p) - inner.sdf(&p)) < 1e-12);
+        }
+    }
+
+    #[test]
+    fn nonzero_k_moves_an_off_axis_hit() {
+        // on the +x face of the slab, away from the pivot at x = 0.5
+        let p = Vector3f::new(1.5, 1.0, 0.0);
+        assert!(f64::abs(slab().sdf(&p)) < 1e-12);
+        let twist = Twist::new(slab(), 1.0).unwrap();
+        let bend = Bend::new(slab(), 1.0).unwrap();
+        assert!(f64::abs(twist.sdf(&p)) > 0.05);
+        assert!(f64::abs(bend.sdf(&p)) > 0.05);
+    }
+
+    #[test]
+    fn bounds_hold_the_warped_shape() {
+        let twist = Twist::new(slab(), 1.0).unwrap();
+        let bounds = twist.bounds();
+        let p = Vector3f::new(0.5, 1.5, 0.0);
+        assert!(twist.sdf(&p) < 0.0);
+        assert!(bounds.contains(&p));
+        assert!(bounds.p_min.x.is_finite() && bounds.p_max.z.is_finite());
+    }
+
+    #[test]
+    fn unbounded_inner_shapes_are_rejected() {
+        let helix = || -> Box<dyn Shape> {
+            Box::new(Helix {
+                center: Vector3f::zero(),
+                fr: 1.0,
+                r1: 1.0,
+                r2: 0.2,
+            })
+        };
+        assert!(Twist::new(helix(), 1.0).is_err());
+        assert!(Bend::new(helix(), 1.0).is_err());
+    }
+
+    #[test]
+    fn rounded_is_the_inner_distance_minus_the_radius() {
+        let cube = || -> Box<dyn Shape> {
+            Box::new(Cube {
+                most_front_up_right: Vector3f::scalar(1.0),
+                center: Vector3f::zero(),
+            })
+        };
+        let rounded = Rounded {
+            inner: cube(),
+            radius: 0.1,
+        };
+        assert!(matches!(rounded.shape_type(), ShapeType::Cube));
+        // next to an edge the sharp corner becomes a 0.1 fillet
+        let edge = Vector3f::new(1.2, 1.2, 0.0);
+        assert!(f64::abs(rounded.sdf(&edge) - (f64::sqrt(0.08) - 0.1)) < 1e-12);
+        for p in points().into_iter().chain([edge]) {
+            assert!(f64::abs(rounded.sdf(&p) - (cube().sdf(&p) - 0.1)) < 1e-12);
+        }
+    }
+
+    #[test]
+    fn onion_crosses_zero_on_both_walls() {
+        let sphere = || -> Box<dyn Shape> {
+            Box::new(Sphere {
+                center: Vector3f::zero(),
+                radius: 1.0,
+            })
+        };
+        let onion = Onion {
+            inner: sphere(),
+            thickness: 0.1,
+        };
+        for p in points() {
+            assert!(f64::abs(onion.sdf(&p) - (f64::abs(sphere().sdf(&p)) - 0.1)) < 1e-12);
+        }
+        // sign changes along +x, outside -> wall -> hollow -> wall -> outside
+        let crossings = (0..200)
+            .map(|i| onion.sdf(&Vector3f::new(i as f64 * 0.01, 0.0, 0.0)))
+            .collect::<Vec<_>>()
+            .windows(2)
+            .filter(|w| (w[0] < 0.0) != (w[1] < 0.0))
+            .count();
+        assert_eq!(crossings, 2);
+        assert!(onion.sdf(&Vector3f::zero()) > 0.0);
+        assert!(f64::abs(onion.sdf(&Vector3f::new(0.9, 0.0, 0.0))) < 1e-12);
+        assert!(f64::abs(onion.sdf(&Vector3f::new(1.1, 0.0, 0.0))) < 1e-12);
+    }
+
+    #[test]
+    fn instances_are_the_nearest_transformed_sphere() {
+        let unit_sphere = || -> Box<dyn Shape> {
+            Box::new(Sphere {
+                center: Vector3f::zero(),
+                radius: 1.0,
+            })
+        };
+        // a unit sphere at x = 3 and one scaled to radius 2 around (-2, 1, 0)
+        let placements = [
+            Matrix4f::from_translation(&Vector3f::new(3.0, 0.0, 0.0)),
+            Matrix4f::from_translation(&Vector3f::new(-2.0, 1.0, 0.0))
+                * Matrix4f::from_scale(&Vector3f::scalar(2.0)),
+        ];
+        let spheres = [
+            (Vector3f::new(3.0, 0.0, 0.0), 1.0),
+            (Vector3f::new(-2.0, 1.0, 0.0), 2.0),
+        ];
+        let instanced = Instanced::new(unit_sphere(), &placements).unwrap();
+        for p in points() {
+            let distances = spheres.map(|(center, radius)| (p - center).length() - radius);
+            for (placement, expected) in placements.iter().zip(distances) {
+                let single = Instanced::new(unit_sphere(), &[*placement]).unwrap();
+                assert!(f64::abs(single.sdf(&p) - expected) < 1e-12);
+            }
+            assert!(f64::abs(instanced.sdf(&p) - f64::min(distances[0], distances[1])) < 1e-12);
+        }
+
+        // a flattened instance has no inverse to carry points into the shape
+        let flat = Matrix4f::from_scale(&Vector3f::new(1.0, 0.0, 1.0));
+        assert!(Instanced::new(unit_sphere(), &[placements[0], flat]).is_err());
+        assert!(Instanced::new(unit_sphere(), &[]).is_err());
+    }
+}
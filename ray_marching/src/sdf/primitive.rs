@@ -1,11 +1,10 @@
-use crate::math::{max, min, Vector2f};
+use crate::math::{max, min, Matrix4f, Vector2f};
 use crate::{domain::Ray, math::Vector3f};
 use core::fmt;
-use nalgebra::{Rotation3, Vector3};
 use std::f64::consts::TAU;
 use std::fmt::Display;
 
-use super::{Shape, ShapeType};
+use super::{bounds::Bounds3, Shape, ShapeType};
 
 pub struct Sphere {
     pub center: Vector3f,
@@ -20,6 +19,10 @@ impl Shape for Sphere {
     fn sdf(&self, p: &Vector3f) -> f64 {
         (&self.center - p).length() - self.radius
     }
+
+    fn bounds(&self) -> Bounds3 {
+        Bounds3::from_center_extent(&self.center, &Vector3f::scalar(self.radius))
+    }
 }
 
 impl Display for Sphere {
@@ -51,6 +54,10 @@ impl Shape for Cube {
         d_clamped.z = f64::max(d.z, 0.0);
         d_clamped.length() + f64::min(f64::max(f64::max(d.x, d.y), d.z), 0.0)
     }
+
+    fn bounds(&self) -> Bounds3 {
+        Bounds3::from_center_extent(&self.center, &self.most_front_up_right)
+    }
 }
 
 impl Display for Cube {
@@ -93,9 +100,13 @@ impl Shape for CubeFrame {
                     + min(max(q.x, max(p.y, q.z)), 0.0),
             ),
             Vector3f::max_scalar(&Vector3f::new(q.x, q.y, p.z), 0.0).length()
-                + min(max(q.x, max(q.y, q.z)), 0.0),
+                + min(max(q.x, max(q.y, p.z)), 0.0),
         )
     }
+
+    fn bounds(&self) -> Bounds3 {
+        Bounds3::from_center_extent(&self.center, &self.bounds)
+    }
 }
 
 impl Display for CubeFrame {
@@ -127,6 +138,11 @@ impl Shape for Torus {
         .length()
             - self.inner_radius
     }
+
+    fn bounds(&self) -> Bounds3 {
+        let r = self.outer_radius + self.inner_radius;
+        Bounds3::from_center_extent(&self.center, &Vector3f::new(r, self.inner_radius, r))
+    }
 }
 
 impl Display for Torus {
@@ -170,16 +186,16 @@ impl Shape for DeathStar {
         }
     }
 
+    fn bounds(&self) -> Bounds3 {
+        // the carved sphere never grows past the main one
+        Bounds3::from_center_extent(&self.center, &Vector3f::scalar(self.ra))
+    }
+
     fn rotate_ray(&self, ray: &Ray) -> Ray {
-        let dir = Vector3::new(ray.direction.x, ray.direction.y, ray.direction.z);
-        let rotation = Rotation3::from_euler_angles(0.0, 0.0, self.rotate_y).inverse();
-        let dir_a: nalgebra::Matrix<
-            f64,
-            nalgebra::Const<3>,
-            nalgebra::Const<1>,
-            nalgebra::ArrayStorage<f64, 3, 1>,
-        > = (rotation * dir).normalize();
-        Ray::new(&ray.origin, &Vector3f::new(dir_a.x, dir_a.y, dir_a.z), 0.0)
+        // the inverse of a rotation is its transpose
+        let rotation = Matrix4f::from_euler(0.0, 0.0, self.rotate_y).transpose();
+        let dir = rotation.transform_vector(&ray.direction).normalize();
+        Ray::new(&ray.origin, &dir, 0.0)
     }
 }
 
@@ -221,6 +237,13 @@ impl Shape for Helix {
         let q = Vector3f::new(qc.x, f64::sin(qc.y) * self.r1, f64::cos(qc.y) * self.r1);
         (p - q).length() - self.r2 - 0.0001
     }
+
+    fn bounds(&self) -> Bounds3 {
+        // the helix repeats forever along x, the sdf inflates the tube by
+        // another 1e-4
+        let r = self.r1 + self.r2 + 0.0001;
+        Bounds3::from_center_extent(&self.center, &Vector3f::new(f64::INFINITY, r, r))
+    }
 }
 
 impl Display for Helix {
@@ -232,3 +255,473 @@ impl Display for Helix {
         )
     }
 }
+
+pub struct Octahedron {
+    pub center: Vector3f,
+    pub s: f64,
+}
+
+impl Shape for Octahedron {
+    fn shape_type(&self) -> ShapeType {
+        ShapeType::Octahedron
+    }
+
+    fn sdf(&self, p: &Vector3f) -> f64 {
+        let mut p = p - &self.center;
+        p.x = f64::abs(p.x);
+        p.y = f64::abs(p.y);
+        p.z = f64::abs(p.z);
+
+        let m = p.x + p.y + p.z - self.s;
+        // pick the face whose edge region contains p
+        let q = if 3.0 * p.x < m {
+            p
+        } else if 3.0 * p.y < m {
+            Vector3f::new(p.y, p.z, p.x)
+        } else if 3.0 * p.z < m {
+            Vector3f::new(p.z, p.x, p.y)
+        } else {
+            return m * 0.57735027;
+        };
+        let k = f64::clamp(0.5 * (q.z - q.y + self.s), 0.0, self.s);
+        Vector3f::new(q.x, q.y - self.s + k, q.z - k).length()
+    }
+
+    fn bounds(&self) -> Bounds3 {
+        Bounds3::from_center_extent(&self.center, &Vector3f::scalar(self.s))
+    }
+}
+
+impl Display for Octahedron {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Octahedron(center={}, s={})", self.center, self.s)
+    }
+}
+
+// chain link, a torus in the xy plane stretched by `le` along y
+pub struct Link {
+    pub center: Vector3f,
+    pub le: f64,
+    pub r1: f64,
+    pub r2: f64,
+}
+
+impl Shape for Link {
+    fn shape_type(&self) -> ShapeType {
+        ShapeType::Link
+    }
+
+    fn sdf(&self, p: &Vector3f) -> f64 {
+        let p = p - &self.center;
+        let q = Vector3f::new(p.x, max(f64::abs(p.y) - self.le, 0.0), p.z);
+        Vector2f::new(Vector2f::new(q.x, q.y).length() - self.r1, q.z).length() - self.r2
+    }
+
+    fn bounds(&self) -> Bounds3 {
+        let r = self.r1 + self.r2;
+        Bounds3::from_center_extent(&self.center, &Vector3f::new(r, self.le + r, self.r2))
+    }
+}
+
+impl Display for Link {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Link(center={}, le={}, r1={}, r2={})",
+            self.center, self.le, self.r1, self.r2
+        )
+    }
+}
+
+// hexagonal prism along z, `h.x` is the distance from the axis to the flat
+// sides and `h.y` the half length
+pub struct HexPrism {
+    pub center: Vector3f,
+    pub h: Vector2f,
+}
+
+impl Shape for HexPrism {
+    fn shape_type(&self) -> ShapeType {
+        ShapeType::HexPrism
+    }
+
+    fn sdf(&self, p: &Vector3f) -> f64 {
+        let k = Vector3f::new(-0.8660254, 0.5, 0.57735);
+        let mut p = p - &self.center;
+        p.x = f64::abs(p.x);
+        p.y = f64::abs(p.y);
+        p.z = f64::abs(p.z);
+        // fold p into the sector between a flat side and a corner
+        let fold = 2.0 * min(k.x * p.x + k.y * p.y, 0.0);
+        p.x -= fold * k.x;
+        p.y -= fold * k.y;
+
+        let edge = Vector2f::new(
+            p.x - f64::clamp(p.x, -k.z * self.h.x, k.z * self.h.x),
+            p.y - self.h.x,
+        );
+        let d = Vector2f::new(edge.length() * f64::signum(p.y - self.h.x), p.z - self.h.y);
+        min(max(d.x, d.y), 0.0) + Vector2f::new(max(d.x, 0.0), max(d.y, 0.0)).length()
+    }
+
+    fn bounds(&self) -> Bounds3 {
+        // the corners lie 2 / sqrt(3) times further out than the sides
+        let extent = Vector3f::new(self.h.x * 1.1547006, self.h.x, self.h.y);
+        Bounds3::from_center_extent(&self.center, &extent)
+    }
+}
+
+impl Display for HexPrism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "HexPrism(center={}, h=({}, {}))",
+            self.center, self.h.x, self.h.y
+        )
+    }
+}
+
+// equilateral triangular prism along z pointing up, `h.x` is the height of
+// the triangle and `h.y` the half length. the field is a bound, not exact
+pub struct TriPrism {
+    pub center: Vector3f,
+    pub h: Vector2f,
+}
+
+impl Shape for TriPrism {
+    fn shape_type(&self) -> ShapeType {
+        ShapeType::TriPrism
+    }
+
+    fn sdf(&self, p: &Vector3f) -> f64 {
+        let p = p - &self.center;
+        let q = Vector3f::new(f64::abs(p.x), f64::abs(p.y), f64::abs(p.z));
+        max(
+            q.z - self.h.y,
+            max(q.x * 0.8660254 + p.y * 0.5, -p.y) - self.h.x * 0.5,
+        )
+    }
+
+    fn bounds(&self) -> Bounds3 {
+        let extent = Vector3f::new(self.h.x * 0.8660254, self.h.x, self.h.y);
+        Bounds3::from_center_extent(&self.center, &extent)
+    }
+}
+
+impl Display for TriPrism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "TriPrism(center={}, h=({}, {}))",
+            self.center, self.h.x, self.h.y
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_sdf(shape: &dyn Shape, p: Vector3f, expected: f64) {
+        let d = shape.sdf(&p);
+        assert!(
+            f64::abs(d - expected) < 1e-6,
+            "{} at {}: {} != {}",
+            shape,
+            p,
+            d,
+            expected
+        );
+    }
+
+    // surface points found by bisecting from `inside` outwards along the
+    // axes and diagonals, directions that never leave the shape are skipped
+    fn surface_points(shape: &dyn Shape, inside: Vector3f) -> Vec<Vector3f> {
+        assert!(
+            shape.sdf(&inside) < 0.0,
+            "{} does not contain {}",
+            shape,
+            inside
+        );
+        let mut points = vec![];
+        for i in 0..27 {
+            let dir = Vector3f::new(
+                (i % 3) as f64 - 1.0,
+                (i / 3 % 3) as f64 - 1.0,
+                (i / 9) as f64 - 1.0,
+            );
+            if dir.length() == 0.0 {
+                continue;
+            }
+            let dir = dir.normalize();
+            let Some(step) =
+                (1..2000).find(|step| shape.sdf(&(inside + dir * (*step as f64 * 0.01))) > 0.0)
+            else {
+                continue;
+            };
+            let (mut t0, mut t1) = ((step - 1) as f64 * 0.01, step as f64 * 0.01);
+            for _ in 0..50 {
+                let t = (t0 + t1) * 0.5;
+                if shape.sdf(&(inside + dir * t)) > 0.0 {
+                    t1 = t;
+                } else {
+                    t0 = t;
+                }
+            }
+            points.push(inside + dir * t1);
+        }
+        points
+    }
+
+    #[test]
+    fn bounds_contain_the_surface() {
+        let center = Vector3f::new(0.5, -1.0, 2.0);
+        let shapes: Vec<(Box<dyn Shape>, Vector3f)> = vec![
+            (
+                Box::new(Sphere {
+                    center,
+                    radius: 1.5,
+                }),
+                center,
+            ),
+            (
+                Box::new(Cube {
+                    most_front_up_right: Vector3f::new(1.0, 2.0, 0.5),
+                    center,
+                }),
+                center,
+            ),
+            (
+                Box::new(CubeFrame {
+                    center,
+                    bounds: Vector3f::new(1.0, 1.5, 2.0),
+                    thinkness: 0.1,
+                }),
+                center + Vector3f::new(0.95, 1.45, 0.0),
+            ),
+            (
+                Box::new(Torus {
+                    center,
+                    outer_radius: 2.0,
+                    inner_radius: 0.5,
+                }),
+                center + Vector3f::new(2.0, 0.0, 0.0),
+            ),
+            (
+                Box::new(DeathStar {
+                    center,
+                    ra: 1.0,
+                    rb: 0.8,
+                    d: 1.2,
+                    rotate_y: 0.0,
+                }),
+                center + Vector3f::new(-0.5, 0.0, 0.0),
+            ),
+            (
+                Box::new(Helix {
+                    center,
+                    fr: 0.4,
+                    r1: 1.0,
+                    r2: 0.2,
+                }),
+                center + Vector3f::new(0.0, 0.0, 1.0),
+            ),
+            (Box::new(Octahedron { center, s: 1.0 }), center),
+            (
+                Box::new(Link {
+                    center,
+                    le: 0.5,
+                    r1: 1.0,
+                    r2: 0.25,
+                }),
+                center + Vector3f::new(1.0, 0.0, 0.0),
+            ),
+            (
+                Box::new(HexPrism {
+                    center,
+                    h: Vector2f::new(1.0, 0.5),
+                }),
+                center,
+            ),
+            (
+                Box::new(TriPrism {
+                    center,
+                    h: Vector2f::new(1.0, 0.5),
+                }),
+                center,
+            ),
+        ];
+        for (shape, inside) in shapes {
+            let bounds = shape.bounds().expand(1e-6);
+            let points = surface_points(shape.as_ref(), inside);
+            assert!(!points.is_empty());
+            for p in points {
+                assert!(
+                    bounds.contains(&p),
+                    "{} at {} is outside {}",
+                    shape,
+                    p,
+                    bounds
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn cpu_sdf_matches_hand_computed_values() {
+        let sphere = Sphere {
+            center: Vector3f::new(1.0, 0.0, 0.0),
+            radius: 2.0,
+        };
+        assert_sdf(&sphere, Vector3f::new(4.0, 0.0, 0.0), 1.0);
+        assert_sdf(&sphere, Vector3f::new(1.0, 0.0, 0.0), -2.0);
+
+        let cube = Cube {
+            most_front_up_right: Vector3f::new(1.0, 2.0, 3.0),
+            center: Vector3f::zero(),
+        };
+        assert_sdf(&cube, Vector3f::new(2.0, 0.0, 0.0), 1.0);
+        assert_sdf(&cube, Vector3f::zero(), -1.0);
+        assert_sdf(&cube, Vector3f::new(2.0, 3.0, 0.0), f64::sqrt(2.0));
+
+        let torus = Torus {
+            center: Vector3f::zero(),
+            outer_radius: 2.0,
+            inner_radius: 0.5,
+        };
+        assert_sdf(&torus, Vector3f::new(2.0, 0.0, 0.0), -0.5);
+        assert_sdf(&torus, Vector3f::zero(), 1.5);
+        assert_sdf(&torus, Vector3f::new(0.0, 1.0, 2.0), 0.5);
+
+        let octahedron = Octahedron {
+            center: Vector3f::zero(),
+            s: 1.0,
+        };
+        assert_sdf(&octahedron, Vector3f::zero(), -1.0 / f64::sqrt(3.0));
+        assert_sdf(&octahedron, Vector3f::new(2.0, 0.0, 0.0), 1.0);
+
+        let link = Link {
+            center: Vector3f::zero(),
+            le: 1.0,
+            r1: 1.0,
+            r2: 0.25,
+        };
+        assert_sdf(&link, Vector3f::new(1.0, 0.0, 0.0), -0.25);
+        assert_sdf(&link, Vector3f::new(0.0, 3.0, 0.0), 0.75);
+
+        let hex = HexPrism {
+            center: Vector3f::zero(),
+            h: Vector2f::new(1.0, 2.0),
+        };
+        assert_sdf(&hex, Vector3f::zero(), -1.0);
+        assert_sdf(&hex, Vector3f::new(0.0, 1.5, 0.0), 0.5);
+        assert_sdf(&hex, Vector3f::new(0.0, 0.0, 3.0), 1.0);
+
+        let tri = TriPrism {
+            center: Vector3f::zero(),
+            h: Vector2f::new(1.0, 2.0),
+        };
+        assert_sdf(&tri, Vector3f::zero(), -0.5);
+        assert_sdf(&tri, Vector3f::new(0.0, -1.0, 0.0), 0.5);
+        assert_sdf(&tri, Vector3f::new(0.0, 0.0, 3.0), 1.0);
+
+        let frame = CubeFrame {
+            center: Vector3f::zero(),
+            bounds: Vector3f::scalar(1.0),
+            thinkness: 0.1,
+        };
+        assert_sdf(&frame, Vector3f::zero(), 0.8 * f64::sqrt(2.0));
+        assert_sdf(&frame, Vector3f::new(1.0, 1.0, 0.0), 0.0);
+        assert_sdf(&frame, Vector3f::new(1.5, 1.0, 0.0), 0.5);
+    }
+
+    #[test]
+    fn cube_frame_z_bars_match_the_other_axes() {
+        // the z bars used q.z for their interior term and reported 0 inside
+        let frame = CubeFrame {
+            center: Vector3f::zero(),
+            bounds: Vector3f::scalar(1.0),
+            thinkness: 0.1,
+        };
+        assert_sdf(&frame, Vector3f::new(0.95, 0.95, 0.0), -0.05);
+        assert_sdf(&frame, Vector3f::new(0.95, 0.0, 0.95), -0.05);
+        assert_sdf(&frame, Vector3f::new(0.0, 0.95, 0.95), -0.05);
+        assert_sdf(
+            &frame,
+            Vector3f::new(1.05, 1.05, 0.5),
+            0.05 * f64::sqrt(2.0),
+        );
+    }
+
+    #[test]
+    fn octahedron_vertices_are_on_the_surface() {
+        let center = Vector3f::new(1.0, 2.0, 3.0);
+        let s = 1.5;
+        let octahedron = Octahedron { center, s };
+        for offset in [
+            Vector3f::new(s, 0.0, 0.0),
+            Vector3f::new(0.0, s, 0.0),
+            Vector3f::new(0.0, 0.0, s),
+        ] {
+            assert_sdf(&octahedron, center + offset, 0.0);
+            assert_sdf(&octahedron, center - offset, 0.0);
+            // past a vertex the closest point is the vertex itself
+            assert_sdf(&octahedron, center + offset * 2.0, s);
+        }
+        assert_sdf(&octahedron, center + Vector3f::scalar(s / 3.0), 0.0);
+        assert!(octahedron.sdf(&center) < 0.0);
+        assert_sdf(&octahedron, center, -s / f64::sqrt(3.0));
+    }
+
+    #[test]
+    fn link_centerline_is_a_tube_radius_inside() {
+        let center = Vector3f::new(0.5, -1.0, 2.0);
+        let link = Link {
+            center,
+            le: 0.75,
+            r1: 1.0,
+            r2: 0.2,
+        };
+        for i in 0..=8 {
+            // the straight sides, then the round ends
+            let y = -0.75 + 1.5 * i as f64 / 8.0;
+            assert_sdf(&link, center + Vector3f::new(1.0, y, 0.0), -0.2);
+            assert_sdf(&link, center + Vector3f::new(-1.0, y, 0.0), -0.2);
+            let (sin, cos) = f64::sin_cos(TAU * i as f64 / 16.0);
+            assert_sdf(&link, center + Vector3f::new(cos, 0.75 + sin, 0.0), -0.2);
+            assert_sdf(&link, center + Vector3f::new(cos, -0.75 - sin, 0.0), -0.2);
+        }
+        // the middle of the link is empty
+        assert_sdf(&link, center, 1.0 - 0.2);
+    }
+
+    #[test]
+    fn prisms_match_known_distances() {
+        let center = Vector3f::new(1.0, -2.0, 0.5);
+        let at = |x: f64, y: f64, z: f64| center + Vector3f::new(x, y, z);
+        let close = |a: f64, b: f64| f64::abs(a - b) < 1e-6;
+
+        // apothem 1 and half depth 0.5, the corners lie on the x axis
+        let hex = HexPrism {
+            center,
+            h: Vector2f::new(1.0, 0.5),
+        };
+        let corner = 2.0 / f64::sqrt(3.0);
+        assert!(close(hex.sdf(&at(0.0, 1.5, 0.0)), 0.5));
+        assert!(close(hex.sdf(&at(corner + 0.3, 0.0, 0.0)), 0.3));
+        assert!(close(hex.sdf(&at(0.0, 0.0, -0.7)), 0.2));
+        assert!(close(hex.sdf(&center), -0.5));
+
+        // inradius 0.5 with the base at the bottom and a corner on top
+        let tri = TriPrism {
+            center,
+            h: Vector2f::new(1.0, 2.0),
+        };
+        let slanted_normal = Vector3f::new(f64::sqrt(3.0) * 0.5, 0.5, 0.0);
+        assert!(close(tri.sdf(&at(0.0, -0.9, 0.0)), 0.4));
+        assert!(close(tri.sdf(&(center + slanted_normal * 0.75)), 0.25));
+        assert!(close(tri.sdf(&(center + slanted_normal * 0.25)), -0.25));
+        assert!(close(tri.sdf(&at(0.0, 0.0, 2.3)), 0.3));
+        assert!(close(tri.sdf(&center), -0.5));
+    }
+}
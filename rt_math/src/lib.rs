@@ -0,0 +1,237 @@
+use rand::Rng;
+use rand_distr::Uniform;
+use std::{
+    f64::consts::PI,
+    ops::{Add, Mul},
+};
+
+mod sampler;
+mod vector;
+
+pub use sampler::{HaltonSampler, Sampler, UniformSampler};
+pub use vector::{Vector2f, Vector3f};
+
+pub struct Math;
+impl Math {
+    pub fn radian(degree: f64) -> f64 {
+        degree * PI / 180.0
+    }
+
+    pub fn degree(radian: f64) -> f64 {
+        radian / PI * 180.0
+    }
+
+    pub fn sample_uniform_distribution(low: f64, high: f64) -> f64 {
+        let uni = Uniform::new(low, high);
+        let mut rng = rand::thread_rng();
+        rng.sample(uni)
+    }
+
+    pub fn sample_concentric_disk() -> (f64, f64) {
+        Self::concentric_disk(
+            Self::sample_uniform_distribution(0.0, 1.0),
+            Self::sample_uniform_distribution(0.0, 1.0),
+        )
+    }
+
+    // Shirley and Chiu's concentric mapping of (u1, u2) in the unit square
+    // onto the unit disk, which keeps strata compact
+    pub fn concentric_disk(u1: f64, u2: f64) -> (f64, f64) {
+        let u = 2.0 * u1 - 1.0;
+        let v = 2.0 * u2 - 1.0;
+        if u == 0.0 && v == 0.0 {
+            return (0.0, 0.0);
+        }
+        let (r, theta) = if f64::abs(u) > f64::abs(v) {
+            (u, PI / 4.0 * (v / u))
+        } else {
+            (v, PI / 2.0 - PI / 4.0 * (u / v))
+        };
+        (r * f64::cos(theta), r * f64::sin(theta))
+    }
+
+    pub fn sample_cosine_hemisphere(normal: &Vector3f) -> Vector3f {
+        Self::cosine_hemisphere(
+            normal,
+            Self::sample_uniform_distribution(0.0, 1.0),
+            Self::sample_uniform_distribution(0.0, 1.0),
+        )
+    }
+
+    // direction around `normal` with density cos(theta) / PI, by lifting the
+    // disk image of (u1, u2) onto the hemisphere (Malley's method)
+    pub fn cosine_hemisphere(normal: &Vector3f, u1: f64, u2: f64) -> Vector3f {
+        let (x, y) = Self::concentric_disk(u1, u2);
+        let z = f64::sqrt(f64::max(0.0, 1.0 - x * x - y * y));
+        let (tangent, bitangent) = Self::orthonormal_basis(normal);
+        tangent * x + bitangent * y + normal * z
+    }
+
+    // direction uniformly distributed over the cone of directions within
+    // acos(cos_theta_max) of the unit `axis`
+    pub fn sample_uniform_cone(axis: &Vector3f, cos_theta_max: f64) -> Vector3f {
+        if cos_theta_max >= 1.0 {
+            return *axis;
+        }
+        let z = Self::sample_uniform_distribution(cos_theta_max, 1.0);
+        let phi = Self::sample_uniform_distribution(0.0, 2.0 * PI);
+        let r = f64::sqrt(f64::max(0.0, 1.0 - z * z));
+        let (tangent, bitangent) = Self::orthonormal_basis(axis);
+        tangent * (r * f64::cos(phi)) + bitangent * (r * f64::sin(phi)) + axis * z
+    }
+
+    // microfacet normal of the GGX distribution as seen from `wo`, with
+    // Heitz's visible normal sampling. `wo` points away from the surface,
+    // `roughness` is squared into alpha like the marcher's pbr lighting
+    // and (u1, u2) are uniform in [0, 1). the reflected direction
+    // (-wo).reflect(h) has the density ggx_pdf / (4 * wo.dot(h))
+    pub fn sample_ggx(
+        normal: &Vector3f,
+        wo: &Vector3f,
+        roughness: f64,
+        u1: f64,
+        u2: f64,
+    ) -> Vector3f {
+        let alpha = f64::max(roughness * roughness, 1e-4);
+        let (tangent, bitangent) = Self::orthonormal_basis(normal);
+        let v = Vector3f::new(wo.dot(&tangent), wo.dot(&bitangent), wo.dot(normal));
+
+        // stretch the view into the hemisphere configuration
+        let vh = Vector3f::new(alpha * v.x, alpha * v.y, v.z).normalize();
+        let len_sq = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if len_sq > 0.0 {
+            Vector3f::new(-vh.y, vh.x, 0.0) / f64::sqrt(len_sq)
+        } else {
+            Vector3f::new(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross(&t1);
+
+        // uniform disk sample warped onto the visible half of it
+        let r = f64::sqrt(u1);
+        let phi = 2.0 * PI * u2;
+        let p1 = r * f64::cos(phi);
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * f64::sqrt(1.0 - p1 * p1) + s * r * f64::sin(phi);
+        let nh = t1 * p1 + t2 * p2 + vh * f64::sqrt(f64::max(0.0, 1.0 - p1 * p1 - p2 * p2));
+
+        // unstretch back to the ellipsoid configuration
+        let h = Vector3f::new(alpha * nh.x, alpha * nh.y, f64::max(nh.z, 0.0)).normalize();
+        tangent * h.x + bitangent * h.y + normal * h.z
+    }
+
+    // density of `sample_ggx` returning the half vector `h`, the visible
+    // normal distribution G1(wo) * max(wo.h, 0) * D(h) / n.wo
+    pub fn ggx_pdf(normal: &Vector3f, wo: &Vector3f, h: &Vector3f, roughness: f64) -> f64 {
+        let alpha = f64::max(roughness * roughness, 1e-4);
+        let n_dot_wo = wo.dot(normal);
+        let n_dot_h = h.dot(normal);
+        if n_dot_wo <= 0.0 || n_dot_h <= 0.0 {
+            return 0.0;
+        }
+        let a2 = alpha * alpha;
+        let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+        let ndf = a2 / (PI * d * d);
+        let tan2 = (1.0 - n_dot_wo * n_dot_wo) / (n_dot_wo * n_dot_wo);
+        let g1 = 2.0 / (1.0 + f64::sqrt(1.0 + a2 * tan2));
+        g1 * f64::max(wo.dot(h), 0.0) * ndf / n_dot_wo
+    }
+
+    // two unit vectors perpendicular to the unit `normal` and each other
+    pub fn orthonormal_basis(normal: &Vector3f) -> (Vector3f, Vector3f) {
+        let c = if f64::abs(normal.x) > f64::abs(normal.y) {
+            let inv_len = 1.0 / f64::sqrt(normal.x * normal.x + normal.z * normal.z);
+            Vector3f::new(normal.z * inv_len, 0.0, -normal.x * inv_len)
+        } else {
+            let inv_len = 1.0 / f64::sqrt(normal.y * normal.y + normal.z * normal.z);
+            Vector3f::new(0.0, normal.z * inv_len, -normal.y * inv_len)
+        };
+        let b = c.cross(normal);
+        (b, c)
+    }
+}
+
+pub fn lerp<T>(x: T, y: T, a: f64) -> T
+where
+    T: Mul<f64, Output = T> + Add<Output = T> + Copy,
+{
+    x * (1.0 - a) + y * a
+}
+
+pub fn min(a: f64, b: f64) -> f64 {
+    f64::min(a, b)
+}
+
+pub fn max(a: f64, b: f64) -> f64 {
+    f64::max(a, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // midpoints of an n x n grid over the unit square
+    fn stratified(n: u32) -> impl Iterator<Item = (f64, f64)> {
+        (0..n * n).map(move |i| {
+            let (x, y) = (i % n, i / n);
+            ((x as f64 + 0.5) / n as f64, (y as f64 + 0.5) / n as f64)
+        })
+    }
+
+    #[test]
+    fn disk_and_cosine_samples_stay_in_their_domain() {
+        for (u1, u2) in stratified(64) {
+            let (x, y) = Math::concentric_disk(u1, u2);
+            assert!(x * x + y * y <= 1.0 + 1e-12);
+        }
+        // the corners of the square land on the circle
+        let (x, y) = Math::concentric_disk(1.0, 1.0);
+        assert!(f64::abs(x * x + y * y - 1.0) < 1e-12);
+
+        let normal = Vector3f::new(1.0, 2.0, -2.0).normalize();
+        let n = 128;
+        let mut cos_sum = 0.0;
+        for (u1, u2) in stratified(n) {
+            let w = Math::cosine_hemisphere(&normal, u1, u2);
+            assert!(f64::abs(w.length() - 1.0) < 1e-9);
+            assert!(w.dot(&normal) >= 0.0);
+            cos_sum += w.dot(&normal);
+        }
+        // E[cos] is 2 / 3 for the density cos / PI, uniform directions give 1 / 2
+        assert!(f64::abs(cos_sum / (n * n) as f64 - 2.0 / 3.0) < 1e-3);
+    }
+
+    #[test]
+    fn ggx_samples_match_their_pdf() {
+        let normal = Vector3f::new(0.0, 0.0, 1.0);
+        let wo = Vector3f::new(0.6, 0.0, 0.8);
+        for roughness in [0.3, 0.7] {
+            // midpoint quadrature over the hemisphere with cos(theta) = 1 - s^2,
+            // which packs the nodes around the peak of the distribution
+            let n = 400;
+            let integral: f64 = stratified(n)
+                .map(|(s, u)| {
+                    let z = 1.0 - s * s;
+                    let r = f64::sqrt(1.0 - z * z);
+                    let phi = 2.0 * PI * u;
+                    let h = Vector3f::new(r * f64::cos(phi), r * f64::sin(phi), z);
+                    Math::ggx_pdf(&normal, &wo, &h, roughness) * 2.0 * s * 2.0 * PI
+                })
+                .sum::<f64>()
+                / (n * n) as f64;
+            assert!(f64::abs(integral - 1.0) < 1e-2);
+
+            for (u1, u2) in stratified(32) {
+                let h = Math::sample_ggx(&normal, &wo, roughness, u1, u2);
+                assert!(f64::abs(h.length() - 1.0) < 1e-9);
+                assert!(h.dot(&normal) >= 0.0);
+                assert!(Math::ggx_pdf(&normal, &wo, &h, roughness) > 0.0);
+            }
+        }
+
+        // smooth surfaces only sample half vectors next to the normal
+        let closest = stratified(32)
+            .map(|(u1, u2)| Math::sample_ggx(&normal, &wo, 0.05, u1, u2).dot(&normal))
+            .fold(1.0, f64::min);
+        assert!(closest > 0.99);
+    }
+}
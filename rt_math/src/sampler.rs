@@ -0,0 +1,216 @@
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+// source of the uniform numbers in [0, 1) a path consumes, one dimension
+// after another. the order the numbers are drawn in has to be the same for
+// every sample so quasi-random sequences line up across samples
+pub trait Sampler {
+    fn next_1d(&mut self) -> f64;
+
+    // continues at `dimension`, quasi-random samplers are only stratified
+    // when a decision draws from the same dimension in every sample
+    fn set_dimension(&mut self, _dimension: usize) {}
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        let u = self.next_1d();
+        let v = self.next_1d();
+        (u, v)
+    }
+}
+
+// independent pseudo-random numbers, reproducible for a fixed seed
+#[derive(Clone)]
+pub struct UniformSampler {
+    rng: SmallRng,
+}
+
+impl UniformSampler {
+    pub fn new(seed: u64) -> UniformSampler {
+        UniformSampler {
+            rng: SmallRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl Sampler for UniformSampler {
+    fn next_1d(&mut self) -> f64 {
+        self.rng.gen::<f64>()
+    }
+}
+
+// bases of the halton dimensions, a path going deeper than this falls back
+// to hashed pseudo-random numbers
+const HALTON_PRIMES: [u64; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+// sample `index` of the halton sequence, dimension d is the radical inverse
+// of the index in the d-th prime base. with a `scramble` seed the digits of
+// every dimension are permuted and the result is shifted by a random offset
+// (Cranley-Patterson rotation), so neighbouring pixels do not repeat the
+// same pattern and the large bases of deep bounces stop lining up
+#[derive(Clone)]
+pub struct HaltonSampler {
+    index: u64,
+    dimension: usize,
+    scramble: Option<u64>,
+}
+
+impl HaltonSampler {
+    pub fn new(index: u64, scramble: Option<u64>) -> HaltonSampler {
+        HaltonSampler {
+            index,
+            dimension: 0,
+            scramble,
+        }
+    }
+
+    // the digits of `index` in `base` mirrored around the radix point,
+    // base 2 gives the van der Corput sequence 0, 1/2, 1/4, 3/4, 1/8, ...
+    pub fn radical_inverse(base: u64, index: u64) -> f64 {
+        let inv_base = 1.0 / base as f64;
+        let mut index = index;
+        let mut inv_base_n = 1.0;
+        let mut reversed = 0.0;
+        while index > 0 {
+            inv_base_n *= inv_base;
+            reversed += (index % base) as f64 * inv_base_n;
+            index /= base;
+        }
+        reversed
+    }
+
+    // radical inverse with the digits at every position mapped through
+    // their own random linear permutation d -> (a * d + c) mod base, the
+    // zero digits past the last one of `index` are permuted as well
+    fn scrambled_radical_inverse(base: u64, index: u64, seed: u64) -> f64 {
+        let inv_base = 1.0 / base as f64;
+        let mut index = index;
+        let mut state = seed;
+        let mut inv_base_n = 1.0;
+        let mut reversed = 0.0;
+        while inv_base_n * inv_base > f64::EPSILON {
+            state = hash(state);
+            let a = 1 + (state >> 32) % (base - 1);
+            let c = state % base;
+            inv_base_n *= inv_base;
+            reversed += ((a * (index % base) + c) % base) as f64 * inv_base_n;
+            index /= base;
+        }
+        reversed
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn set_dimension(&mut self, dimension: usize) {
+        self.dimension = dimension;
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let dimension = self.dimension as u64;
+        self.dimension += 1;
+        let seed = self.scramble.unwrap_or(0);
+        let base = match HALTON_PRIMES.get(dimension as usize) {
+            Some(base) => *base,
+            None => return unit_hash(hash(seed ^ hash(self.index) ^ hash(dimension << 32))),
+        };
+        match self.scramble {
+            Some(seed) => {
+                let dimension_seed = hash(seed ^ hash(dimension));
+                let value = Self::scrambled_radical_inverse(base, self.index, dimension_seed);
+                let shifted = value + unit_hash(hash(dimension_seed));
+                shifted - f64::floor(shifted)
+            }
+            None => Self::radical_inverse(base, self.index),
+        }
+    }
+}
+
+// splitmix64 finalizer, a cheap well mixed 64 bit hash
+fn hash(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+// top 53 bits of `x` as a number in [0, 1)
+fn unit_hash(x: u64) -> f64 {
+    (x >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn halton_base_2_is_van_der_corput_and_seeds_repeat() {
+        let van_der_corput = [0.0, 0.5, 0.25, 0.75, 0.125, 0.625, 0.375, 0.875, 0.0625];
+        for (index, expected) in van_der_corput.iter().enumerate() {
+            let mut sampler = HaltonSampler::new(index as u64, None);
+            assert_eq!(sampler.next_1d(), *expected);
+            // the second dimension is base 3
+            assert_eq!(
+                sampler.next_1d(),
+                HaltonSampler::radical_inverse(3, index as u64)
+            );
+        }
+
+        let draw =
+            |sampler: &mut dyn Sampler| (0..16).map(|_| sampler.next_1d()).collect::<Vec<_>>();
+        assert_eq!(
+            draw(&mut UniformSampler::new(7)),
+            draw(&mut UniformSampler::new(7))
+        );
+        assert_ne!(
+            draw(&mut UniformSampler::new(7)),
+            draw(&mut UniformSampler::new(8))
+        );
+        assert_eq!(
+            draw(&mut HaltonSampler::new(5, Some(3))),
+            draw(&mut HaltonSampler::new(5, Some(3)))
+        );
+    }
+
+    // spread of the estimates of the integral of x * y over the unit square,
+    // each trial taking 64 points from the sampler of `trial`
+    fn estimate_variance(sampler: impl Fn(u64, u64) -> Box<dyn Sampler>) -> f64 {
+        let estimates: Vec<f64> = (0..200)
+            .map(|trial| {
+                (0..64)
+                    .map(|index| {
+                        let (x, y) = sampler(trial, index).next_2d();
+                        x * y
+                    })
+                    .sum::<f64>()
+                    / 64.0
+            })
+            .collect();
+        let mean = estimates.iter().sum::<f64>() / estimates.len() as f64;
+        assert!(f64::abs(mean - 0.25) < 1e-2);
+        estimates
+            .iter()
+            .map(|e| (e - mean) * (e - mean))
+            .sum::<f64>()
+            / estimates.len() as f64
+    }
+
+    #[test]
+    fn halton_estimates_vary_less_than_uniform_ones() {
+        // a uniform sampler per trial, drawn from in order
+        let uniform = estimate_variance(|trial, index| {
+            let mut sampler = UniformSampler::new(trial);
+            for _ in 0..index {
+                sampler.next_2d();
+            }
+            Box::new(sampler)
+        });
+        // trials only differ by their scramble seed
+        let halton =
+            estimate_variance(|trial, index| Box::new(HaltonSampler::new(index, Some(trial))));
+        assert!(halton * 5.0 < uniform);
+    }
+}
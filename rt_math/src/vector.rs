@@ -0,0 +1,392 @@
+use std::{fmt::Display, ops};
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct Vector3f {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Vector3f {
+    pub fn new(x: f64, y: f64, z: f64) -> Vector3f {
+        Vector3f { x, y, z }
+    }
+
+    pub fn scalar(v: f64) -> Vector3f {
+        Vector3f { x: v, y: v, z: v }
+    }
+
+    pub fn zero() -> Vector3f {
+        Vector3f {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    }
+
+    pub fn min(p1: &Vector3f, p2: &Vector3f) -> Vector3f {
+        Vector3f {
+            x: f64::min(p1.x, p2.x),
+            y: f64::min(p1.y, p2.y),
+            z: f64::min(p1.z, p2.z),
+        }
+    }
+
+    pub fn max(p1: &Vector3f, p2: &Vector3f) -> Vector3f {
+        Vector3f {
+            x: f64::max(p1.x, p2.x),
+            y: f64::max(p1.y, p2.y),
+            z: f64::max(p1.z, p2.z),
+        }
+    }
+
+    pub fn max_scalar(p1: &Vector3f, val: f64) -> Vector3f {
+        Vector3f {
+            x: f64::max(p1.x, val),
+            y: f64::max(p1.y, val),
+            z: f64::max(p1.z, val),
+        }
+    }
+
+    pub fn normalize(&self) -> Vector3f {
+        let mag2 = self.x * self.x + self.y * self.y + self.z * self.z;
+        if mag2 > f64::EPSILON {
+            let inv_mag = 1.0 / f64::sqrt(mag2);
+            self * inv_mag
+        } else {
+            *self
+        }
+    }
+
+    pub fn length(&self) -> f64 {
+        f64::sqrt(self.x * self.x + self.y * self.y + self.z * self.z)
+    }
+
+    pub fn dot(&self, rhs: &Vector3f) -> f64 {
+        self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
+    }
+
+    pub fn cross(&self, rhs: &Vector3f) -> Vector3f {
+        Vector3f {
+            x: self.y * rhs.z - self.z * rhs.y,
+            y: self.z * rhs.x - self.x * rhs.z,
+            z: self.x * rhs.y - self.y * rhs.x,
+        }
+    }
+
+    pub fn distance_sq(&self, rhs: &Vector3f) -> f64 {
+        (self.x - rhs.x) * (self.x - rhs.x)
+            + (self.y - rhs.y) * (self.y - rhs.y)
+            + (self.z - rhs.z) * (self.z - rhs.z)
+    }
+
+    // mirrors the incident direction about the normal
+    pub fn reflect(&self, normal: &Vector3f) -> Vector3f {
+        *self - *normal * (2.0 * self.dot(normal))
+    }
+
+    // Snell refraction of the incident direction through a surface with the
+    // given unit normal (facing the incident side), `eta` being the ratio of
+    // the refractive indices n_incident / n_transmitted. Returns None on total
+    // internal reflection
+    pub fn refract(&self, normal: &Vector3f, eta: f64) -> Option<Vector3f> {
+        let cos_i = -self.dot(normal);
+        let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+        if sin2_t > 1.0 {
+            return None;
+        }
+        let cos_t = f64::sqrt(1.0 - sin2_t);
+        Some(*self * eta + *normal * (eta * cos_i - cos_t))
+    }
+
+    // component-wise comparison, each axis may differ by at most `eps`
+    pub fn approx_eq(&self, rhs: &Vector3f, eps: f64) -> bool {
+        f64::abs(self.x - rhs.x) <= eps
+            && f64::abs(self.y - rhs.y) <= eps
+            && f64::abs(self.z - rhs.z) <= eps
+    }
+
+    // narrows every component to the nearest f32, the one conversion used
+    // wherever vectors leave the renderer as 32-bit floats
+    pub fn to32(&self) -> [f32; 3] {
+        [self.x as f32, self.y as f32, self.z as f32]
+    }
+}
+
+impl<T> ops::Mul<T> for Vector3f
+where
+    f64: From<T>,
+{
+    type Output = Vector3f;
+
+    fn mul(self, rhs: T) -> Self::Output {
+        let val = f64::from(rhs);
+        Vector3f {
+            x: self.x * val,
+            y: self.y * val,
+            z: self.z * val,
+        }
+    }
+}
+
+impl<T> ops::Mul<T> for &Vector3f
+where
+    f64: From<T>,
+{
+    type Output = Vector3f;
+
+    fn mul(self, rhs: T) -> Self::Output {
+        let val = f64::from(rhs);
+        Vector3f {
+            x: self.x * val,
+            y: self.y * val,
+            z: self.z * val,
+        }
+    }
+}
+
+impl<T> ops::Div<T> for Vector3f
+where
+    f64: From<T>,
+{
+    type Output = Vector3f;
+
+    fn div(self, rhs: T) -> Self::Output {
+        let val = f64::from(rhs);
+        Vector3f {
+            x: self.x / val,
+            y: self.y / val,
+            z: self.z / val,
+        }
+    }
+}
+
+impl<T> ops::Div<T> for &Vector3f
+where
+    f64: From<T>,
+{
+    type Output = Vector3f;
+
+    fn div(self, rhs: T) -> Self::Output {
+        let val = f64::from(rhs);
+        Vector3f {
+            x: self.x / val,
+            y: self.y / val,
+            z: self.z / val,
+        }
+    }
+}
+
+impl ops::Add<Vector3f> for Vector3f {
+    type Output = Vector3f;
+
+    fn add(self, rhs: Vector3f) -> Self::Output {
+        Vector3f {
+            x: self.x + rhs.x,
+            y: self.y + rhs.y,
+            z: self.z + rhs.z,
+        }
+    }
+}
+
+impl ops::Add for &Vector3f {
+    type Output = Vector3f;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Vector3f {
+            x: self.x + rhs.x,
+            y: self.y + rhs.y,
+            z: self.z + rhs.z,
+        }
+    }
+}
+
+impl ops::AddAssign for Vector3f {
+    fn add_assign(&mut self, rhs: Self) {
+        self.x += rhs.x;
+        self.y += rhs.y;
+        self.z += rhs.z;
+    }
+}
+
+impl ops::Sub<Vector3f> for Vector3f {
+    type Output = Vector3f;
+
+    fn sub(self, rhs: Vector3f) -> Self::Output {
+        Vector3f {
+            x: self.x - rhs.x,
+            y: self.y - rhs.y,
+            z: self.z - rhs.z,
+        }
+    }
+}
+
+impl ops::Sub for &Vector3f {
+    type Output = Vector3f;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Vector3f {
+            x: self.x - rhs.x,
+            y: self.y - rhs.y,
+            z: self.z - rhs.z,
+        }
+    }
+}
+
+impl ops::Sub<f64> for Vector3f {
+    type Output = Vector3f;
+
+    fn sub(self, rhs: f64) -> Self::Output {
+        Vector3f {
+            x: self.x - rhs,
+            y: self.y - rhs,
+            z: self.z - rhs,
+        }
+    }
+}
+
+impl ops::Mul for &Vector3f {
+    type Output = Vector3f;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        Vector3f {
+            x: self.x * rhs.x,
+            y: self.y * rhs.y,
+            z: self.z * rhs.z,
+        }
+    }
+}
+
+impl ops::Neg for &Vector3f {
+    type Output = Vector3f;
+
+    fn neg(self) -> Self::Output {
+        Vector3f {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+}
+
+impl Display for Vector3f {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "({}, {}, {})", self.x, self.y, self.z)
+    }
+}
+
+pub struct Vector2f {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl Vector2f {
+    pub fn new(x: f64, y: f64) -> Vector2f {
+        Vector2f { x, y }
+    }
+
+    pub fn length(&self) -> f64 {
+        f64::sqrt(self.x * self.x + self.y * self.y)
+    }
+
+    pub fn dot(&self, rhs: &Vector2f) -> f64 {
+        self.x * rhs.x + self.y * rhs.y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equality_is_exact() {
+        let v = Vector3f::new(0.1, 0.2, 0.3);
+        assert!(v == Vector3f::new(0.1, 0.2, 0.3));
+        assert!(v != Vector3f::new(0.1, 0.2, 0.3 + 1e-15));
+        assert!(Vector3f::new(0.1 + 0.2, 0.0, 0.0) != Vector3f::new(0.3, 0.0, 0.0));
+    }
+
+    #[test]
+    fn approx_eq_accepts_differences_up_to_eps() {
+        // every value here is exact in binary, so the boundary is exact too
+        let v = Vector3f::new(0.5, -1.0, 2.0);
+        assert!(v.approx_eq(&Vector3f::new(0.75, -1.0, 2.0), 0.25));
+        assert!(!v.approx_eq(&Vector3f::new(0.75, -1.0, 2.0), 0.125));
+        assert!(v.approx_eq(&Vector3f::new(0.25, -1.25, 2.25), 0.25));
+        // a single axis outside eps is enough to fail
+        assert!(!v.approx_eq(&Vector3f::new(0.5, -1.0, 2.5), 0.25));
+        assert!(v.approx_eq(&v, 0.0));
+    }
+
+    #[test]
+    fn reflect_inverts_the_normal_component() {
+        let normal = Vector3f::new(0.0, 1.0, 0.0);
+        let incident = Vector3f::new(0.6, -0.8, 0.0);
+        let reflected = incident.reflect(&normal);
+        assert!(reflected.approx_eq(&Vector3f::new(0.6, 0.8, 0.0), 1e-12));
+        assert!(f64::abs(reflected.dot(&normal) + incident.dot(&normal)) < 1e-12);
+    }
+
+    #[test]
+    fn refract_follows_snell_and_fails_past_the_critical_angle() {
+        let normal = Vector3f::new(0.0, 1.0, 0.0);
+        let eta = 1.0 / 1.5;
+        let (sin_i, cos_i) = f64::sin_cos(0.6);
+        let incident = Vector3f::new(sin_i, -cos_i, 0.0);
+        let refracted = incident.refract(&normal, eta).unwrap();
+        assert!(f64::abs(refracted.length() - 1.0) < 1e-12);
+        assert!(f64::abs(refracted.x - eta * sin_i) < 1e-12);
+        assert!(refracted.y < 0.0);
+
+        // leaving glass, sin of the critical angle is 1 / 1.5
+        let critical = f64::asin(1.0 / 1.5);
+        let beyond = critical + 1e-6;
+        let incident = Vector3f::new(f64::sin(beyond), -f64::cos(beyond), 0.0);
+        assert!(incident.refract(&normal, 1.5).is_none());
+        let below = critical - 1e-6;
+        let incident = Vector3f::new(f64::sin(below), -f64::cos(below), 0.0);
+        assert!(incident.refract(&normal, 1.5).is_some());
+    }
+
+    #[test]
+    fn common_operations() {
+        let a = Vector3f::new(1.0, -2.0, 3.0);
+        let b = Vector3f::new(-4.0, 0.5, 2.0);
+        assert!(a + b == Vector3f::new(-3.0, -1.5, 5.0));
+        assert!(a - b == Vector3f::new(5.0, -2.5, 1.0));
+        assert!(&a * &b == Vector3f::new(-4.0, -1.0, 6.0));
+        assert!(a * 2.0 == Vector3f::new(2.0, -4.0, 6.0));
+        assert!(a / 2.0 == Vector3f::new(0.5, -1.0, 1.5));
+        assert!(-&a == Vector3f::new(-1.0, 2.0, -3.0));
+        let mut c = a;
+        c += b;
+        assert!(c == a + b);
+
+        assert_eq!(a.dot(&b), 1.0);
+        let cross = a.cross(&b);
+        assert!(cross == Vector3f::new(-5.5, -14.0, -7.5));
+        assert_eq!(cross.dot(&a), 0.0);
+        assert_eq!(cross.dot(&b), 0.0);
+        assert_eq!(Vector3f::new(2.0, 3.0, 6.0).length(), 7.0);
+        let unit = Vector3f::new(2.0, 3.0, 6.0) / 7.0;
+        assert!(Vector3f::new(2.0, 3.0, 6.0)
+            .normalize()
+            .approx_eq(&unit, 1e-15));
+        assert_eq!(a.distance_sq(&b), 32.25);
+
+        assert!(Vector3f::min(&a, &b) == Vector3f::new(-4.0, -2.0, 2.0));
+        assert!(Vector3f::max(&a, &b) == Vector3f::new(1.0, 0.5, 3.0));
+        assert!(crate::lerp(a, b, 0.5) == Vector3f::new(-1.5, -0.75, 2.5));
+    }
+
+    #[test]
+    fn to32_narrows_each_component() {
+        let v = Vector3f::new(0.1, -1e40, 16777217.0);
+        let narrowed = v.to32();
+        assert_eq!(narrowed[0], 0.1f32);
+        // out of range values become infinite, integers past 2^24 round to even
+        assert_eq!(narrowed[1], f32::NEG_INFINITY);
+        assert_eq!(narrowed[2], 16777216.0f32);
+        assert_eq!(Vector3f::new(1.5, 2.0, -0.25).to32(), [1.5, 2.0, -0.25]);
+    }
+}